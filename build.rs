@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+
+    // Compiled with protox, so building doesn't need protoc installed
+    let descriptors = protox::compile(["otp/v1/otp.proto"], ["proto"])?;
+
+    // Served by the reflection service
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    std::fs::write(
+        out_dir.join("otp_descriptor.bin"),
+        prost::Message::encode_to_vec(&descriptors),
+    )?;
+
+    tonic_prost_build::configure().compile_fds(descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use otp_types::Problem;
+use std::fmt;
+
+/// Errors returned by the client
+#[derive(Debug)]
+pub enum Error {
+    /// The server refused the request; the problem details say why
+    Api { status: u16, problem: Problem },
+    /// The request couldn't be sent or its response read, including timeouts
+    Http(reqwest::Error),
+    /// The base URL can't be used for API requests
+    InvalidUrl(String),
+}
+
+impl Error {
+    /// Stable error code from the server, e.g. `locked` or `unauthorized`
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Api { problem, .. } => Some(&problem.code),
+            _ => None,
+        }
+    }
+
+    /// HTTP status of a refused request
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Api { status, .. } => Some(*status),
+            Error::Http(e) => e.status().map(|status| status.as_u16()),
+            Error::InvalidUrl(_) => None,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Error::Http(e) if e.is_timeout())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api { status, problem } => {
+                write!(f, "{} {}: {}", status, problem.code, problem.detail)
+            }
+            Error::Http(e) => write!(f, "HTTP error: {}", e),
+            Error::InvalidUrl(url) => write!(f, "Invalid base URL: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,795 @@
+//! Async client for the OTP server's HTTP API.
+//!
+//! Requests and responses are the server's own types from `otp-types`, so the
+//! client can't drift from the wire format. Calls go to the `/api/v2` API,
+//! authenticated with an API key (optionally signing every request) or a bearer
+//! token, with timeouts and retries of requests the server couldn't serve right now.
+//!
+//! ```no_run
+//! # async fn example() -> otp_client::Result<()> {
+//! use otp_client::{Client, VerifyOtpRequest};
+//!
+//! let client = Client::builder("https://otp.example.com")
+//!     .api_key("my-api-key")
+//!     .tenant("acme")
+//!     .build()?;
+//! let result = client
+//!     .verify_totp(&VerifyOtpRequest {
+//!         secret: "3132333435363738393031323334353637383930".to_string(),
+//!         otp: "287082".to_string(),
+//!         ..Default::default()
+//!     })
+//!     .await?;
+//! println!("valid: {}", result.valid);
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+mod retry;
+
+pub use error::{Error, Result};
+pub use otp_types::{
+    ApiKeyResponse, CreateApiKeyRequest, EnrollRequest, EnrollmentResponse, GenerateHotpRequest,
+    GenerateHotpResponse, GenerateOtpRequest, GenerateOtpResponse, GenerateSecretResponse, Problem,
+    PurgeTenantResponse, Scope, UnlockRequest, VerifyHotpRequest, VerifyHotpResponse,
+    VerifyOtpRequest, VerifyOtpResponse,
+};
+pub use retry::RetryPolicy;
+
+use retry::Retry;
+
+use otp_types::{API_KEY_HEADER, SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER, TENANT_HEADER};
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Method, Response, Url};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Content type of the server's error responses
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Path of the API the client calls, relative to the base URL
+const API_PATH: [&str; 2] = ["api", "v2"];
+
+#[derive(Clone)]
+enum Credentials {
+    None,
+    ApiKey(String),
+    /// API key whose requests are signed, for keys created with `require_signature`
+    SignedApiKey {
+        api_key: String,
+        signing_secret: String,
+    },
+    Bearer(String),
+}
+
+/// Builds a [`Client`]
+pub struct ClientBuilder {
+    base_url: String,
+    credentials: Credentials,
+    tenant: Option<String>,
+    timeout: Duration,
+    connect_timeout: Duration,
+    retry: RetryPolicy,
+}
+
+impl ClientBuilder {
+    /// Authenticate with an API key
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.credentials = Credentials::ApiKey(api_key.into());
+        self
+    }
+
+    /// Authenticate with an API key and sign every request with the key's signing secret
+    pub fn signed_api_key(
+        mut self,
+        api_key: impl Into<String>,
+        signing_secret: impl Into<String>,
+    ) -> Self {
+        self.credentials = Credentials::SignedApiKey {
+            api_key: api_key.into(),
+            signing_secret: signing_secret.into(),
+        };
+        self
+    }
+
+    /// Authenticate with a JWT bearer token
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.credentials = Credentials::Bearer(token.into());
+        self
+    }
+
+    /// Tenant to act on, for credentials that aren't bound to one
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Limit on each attempt, from connecting to reading the response. Default 10s.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Limit on establishing a connection. Default 5s.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let mut api_url =
+            Url::parse(&self.base_url).map_err(|_| Error::InvalidUrl(self.base_url.clone()))?;
+        api_url
+            .path_segments_mut()
+            .map_err(|_| Error::InvalidUrl(self.base_url.clone()))?
+            .pop_if_empty()
+            .extend(API_PATH);
+
+        let http = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .connect_timeout(self.connect_timeout)
+            .build()?;
+        Ok(Client {
+            http,
+            api_url,
+            credentials: self.credentials,
+            tenant: self.tenant,
+            retry: self.retry,
+        })
+    }
+}
+
+/// Client for the OTP server's HTTP API. Cheap to clone; clones share connections.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    api_url: Url,
+    credentials: Credentials,
+    tenant: Option<String>,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// Client for the server at `base_url`, e.g. `https://otp.example.com`
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            credentials: Credentials::None,
+            tenant: None,
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// The same client acting on another tenant
+    pub fn with_tenant(&self, tenant: impl Into<String>) -> Client {
+        Client {
+            tenant: Some(tenant.into()),
+            ..self.clone()
+        }
+    }
+
+    /// Generate a new random secret
+    pub async fn generate_secret(&self) -> Result<GenerateSecretResponse> {
+        self.call(Method::POST, &["secret"], None::<&()>, Retry::Idempotent)
+            .await
+    }
+
+    /// Current TOTP for a secret
+    pub async fn generate_totp(&self, request: &GenerateOtpRequest) -> Result<GenerateOtpResponse> {
+        self.call(
+            Method::POST,
+            &["otp", "generate"],
+            Some(request),
+            Retry::Idempotent,
+        )
+        .await
+    }
+
+    /// Verify a TOTP. A refused code is `Ok` with `valid: false`; lockouts are errors.
+    pub async fn verify_totp(&self, request: &VerifyOtpRequest) -> Result<VerifyOtpResponse> {
+        self.call(
+            Method::POST,
+            &["otp", "verify"],
+            Some(request),
+            Retry::Unprocessed,
+        )
+        .await
+    }
+
+    /// HOTP for a secret and counter
+    pub async fn generate_hotp(
+        &self,
+        request: &GenerateHotpRequest,
+    ) -> Result<GenerateHotpResponse> {
+        self.call(
+            Method::POST,
+            &["hotp", "generate"],
+            Some(request),
+            Retry::Idempotent,
+        )
+        .await
+    }
+
+    /// Verify an HOTP. A refused code is `Ok` with `valid: false`; lockouts are errors.
+    pub async fn verify_hotp(&self, request: &VerifyHotpRequest) -> Result<VerifyHotpResponse> {
+        self.call(
+            Method::POST,
+            &["hotp", "verify"],
+            Some(request),
+            Retry::Unprocessed,
+        )
+        .await
+    }
+
+    /// Enroll a user with a new secret
+    pub async fn enroll(&self, request: &EnrollRequest) -> Result<EnrollmentResponse> {
+        self.call(
+            Method::POST,
+            &["admin", "enrollments"],
+            Some(request),
+            Retry::Unprocessed,
+        )
+        .await
+    }
+
+    pub async fn delete_enrollment(&self, user_id: &str) -> Result<()> {
+        self.call::<_, IgnoredAny>(
+            Method::DELETE,
+            &["admin", "enrollments", user_id],
+            None::<&()>,
+            Retry::Idempotent,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Lift a credential's or user's lockout
+    pub async fn unlock(&self, request: &UnlockRequest) -> Result<()> {
+        self.call::<_, IgnoredAny>(
+            Method::POST,
+            &["admin", "unlock"],
+            Some(request),
+            Retry::Idempotent,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn create_api_key(&self, request: &CreateApiKeyRequest) -> Result<ApiKeyResponse> {
+        self.call(
+            Method::POST,
+            &["admin", "api-keys"],
+            Some(request),
+            Retry::Unprocessed,
+        )
+        .await
+    }
+
+    /// Issue a new key in place of an existing one, which stays valid for a grace period
+    pub async fn rotate_api_key(&self, id: &str) -> Result<ApiKeyResponse> {
+        self.call(
+            Method::POST,
+            &["admin", "api-keys", id, "rotate"],
+            None::<&()>,
+            Retry::Unprocessed,
+        )
+        .await
+    }
+
+    pub async fn revoke_api_key(&self, id: &str) -> Result<()> {
+        self.call::<_, IgnoredAny>(
+            Method::DELETE,
+            &["admin", "api-keys", id],
+            None::<&()>,
+            Retry::Idempotent,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Delete everything stored for a tenant
+    pub async fn purge_tenant(&self, tenant: &str) -> Result<PurgeTenantResponse> {
+        self.call(
+            Method::DELETE,
+            &["admin", "tenants", tenant],
+            None::<&()>,
+            Retry::Idempotent,
+        )
+        .await
+    }
+
+    /// Send a request, retrying it as the retry policy and `retry` allow
+    async fn call<B, T>(
+        &self,
+        method: Method,
+        path: &[&str],
+        body: Option<&B>,
+        retry: Retry,
+    ) -> Result<T>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let mut url = self.api_url.clone();
+        url.path_segments_mut()
+            .expect("API URL is a base")
+            .extend(path);
+        let body = body.map(|body| serde_json::to_vec(body).expect("API types serialize to JSON"));
+
+        let mut retries = 0;
+        loop {
+            let result = self.attempt(method.clone(), &url, body.as_deref()).await;
+            let wait = match &result {
+                Ok(response) if retry.retries(response.status().as_u16(), is_problem(response)) => {
+                    retries += 1;
+                    self.retry.backoff(retries, retry_after(response))
+                }
+                Err(e) if e.is_connect() => {
+                    retries += 1;
+                    self.retry.backoff(retries, None)
+                }
+                _ => None,
+            };
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return read_response(result?).await,
+            }
+        }
+    }
+
+    /// Send a request once. Signatures are made per attempt, so retries carry a fresh
+    /// timestamp and aren't refused as replays.
+    async fn attempt(
+        &self,
+        method: Method,
+        url: &Url,
+        body: Option<&[u8]>,
+    ) -> reqwest::Result<Response> {
+        let mut request = self.http.request(method.clone(), url.clone());
+        if let Some(tenant) = &self.tenant {
+            request = request.header(TENANT_HEADER, tenant);
+        }
+        match &self.credentials {
+            Credentials::None => {}
+            Credentials::ApiKey(api_key) => request = request.header(API_KEY_HEADER, api_key),
+            Credentials::SignedApiKey {
+                api_key,
+                signing_secret,
+            } => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let path_and_query = match url.query() {
+                    Some(query) => format!("{}?{}", url.path(), query),
+                    None => url.path().to_string(),
+                };
+                let signature = otp_types::signature(
+                    signing_secret.as_bytes(),
+                    timestamp,
+                    method.as_str(),
+                    &path_and_query,
+                    body.unwrap_or_default(),
+                );
+                request = request
+                    .header(API_KEY_HEADER, api_key)
+                    .header(SIGNATURE_TIMESTAMP_HEADER, timestamp.to_string())
+                    .header(SIGNATURE_HEADER, signature);
+            }
+            Credentials::Bearer(token) => request = request.bearer_auth(token),
+        }
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_vec());
+        }
+        request.send().await
+    }
+}
+
+fn is_problem(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|v| v == PROBLEM_JSON)
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Decode a success body, or turn an error response into [`Error::Api`]
+async fn read_response<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().await?);
+    }
+
+    let retry_after = retry_after(&response).map(|wait| wait.as_secs());
+    let body = response.bytes().await?;
+    // Proxies in front of the server may answer without problem details
+    let problem = serde_json::from_slice(&body).unwrap_or_else(|_| Problem {
+        problem_type: "about:blank".to_string(),
+        title: status.canonical_reason().unwrap_or("Error").to_string(),
+        status: status.as_u16(),
+        detail: String::from_utf8_lossy(&body).into_owned(),
+        code: "http_error".to_string(),
+        retry_after,
+    });
+    Err(Error::Api {
+        status: status.as_u16(),
+        problem,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpServer};
+    use otp::config::{Config, RateLimit};
+    use otp::otp::hotp::Hotp;
+    use otp::storage::memory::MemoryStore;
+    use otp::storage::OtpStore;
+    use std::sync::Arc;
+
+    const ADMIN_KEY: &str = "admin-key";
+    const SECRET: &str = "3132333435363738393031323334353637383930";
+    const SECRETS_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// Serve the real app on a local port, returning its base URL
+    fn serve(config: Config) -> String {
+        let config = Arc::new(config);
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(storage.clone()))
+                .configure(otp::server::routes::configure_routes)
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", addr)
+    }
+
+    fn auth_config() -> Config {
+        Config {
+            api_auth_enabled: true,
+            admin_api_key: Some(ADMIN_KEY.to_string()),
+            secrets_key: Some(SECRETS_KEY.to_string()),
+            ..Config::default()
+        }
+    }
+
+    fn verify_hotp(otp: &str, counter: u64) -> VerifyHotpRequest {
+        VerifyHotpRequest {
+            secret: SECRET.to_string(),
+            otp: otp.to_string(),
+            counter,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_round_trip() {
+        let base_url = serve(auth_config());
+        let client = Client::builder(&base_url)
+            .api_key(ADMIN_KEY)
+            .tenant("acme")
+            .build()
+            .unwrap();
+
+        let secret = client.generate_secret().await.unwrap();
+        assert_eq!(secret.secret.len(), 40);
+
+        // RFC 4226 test vector; the same code again is a replay
+        let generated = client
+            .generate_hotp(&GenerateHotpRequest {
+                secret: SECRET.to_string(),
+                counter: 1,
+            })
+            .await
+            .unwrap();
+        assert_eq!(generated.otp, "287082");
+        let result = client.verify_hotp(&verify_hotp("287082", 1)).await.unwrap();
+        assert!(result.valid);
+        assert_eq!(result.reason, "valid");
+        let result = client.verify_hotp(&verify_hotp("287082", 1)).await.unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.reason, "reused");
+        // Replay protection is per tenant
+        let result = client
+            .with_tenant("other")
+            .verify_hotp(&verify_hotp("287082", 1))
+            .await
+            .unwrap();
+        assert!(result.valid);
+
+        let enrollment = client
+            .enroll(&EnrollRequest {
+                user_id: "alice".to_string(),
+                pin: None,
+            })
+            .await
+            .unwrap();
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+        let otp = client
+            .generate_totp(&GenerateOtpRequest {
+                secret: enrollment.secret.clone(),
+            })
+            .await
+            .unwrap()
+            .otp;
+        let result = client
+            .verify_totp(&VerifyOtpRequest {
+                secret: enrollment.secret,
+                otp,
+                user_id: Some("alice".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(result.valid);
+        client.delete_enrollment("alice").await.unwrap();
+
+        let purged = client.purge_tenant("acme").await.unwrap();
+        assert_eq!(purged.tenant, "acme");
+        assert!(purged.deleted > 0);
+    }
+
+    #[actix_web::test]
+    async fn test_errors_carry_problem_details() {
+        let base_url = serve(auth_config());
+
+        let error = Client::builder(&base_url)
+            .build()
+            .unwrap()
+            .generate_secret()
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(401));
+        assert_eq!(error.code(), Some("unauthorized"));
+
+        let error = Client::builder(&base_url)
+            .api_key(ADMIN_KEY)
+            .build()
+            .unwrap()
+            .generate_hotp(&GenerateHotpRequest {
+                secret: "not hex".to_string(),
+                counter: 1,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(400));
+        assert_eq!(error.code(), Some("validation_error"));
+    }
+
+    #[actix_web::test]
+    async fn test_signed_api_key() {
+        let base_url = serve(auth_config());
+        let admin = Client::builder(&base_url)
+            .api_key(ADMIN_KEY)
+            .build()
+            .unwrap();
+        let key = admin
+            .create_api_key(&CreateApiKeyRequest {
+                tenant: Some("acme".to_string()),
+                scopes: vec![Scope::Generate, Scope::Verify],
+                require_signature: true,
+            })
+            .await
+            .unwrap();
+        assert!(key.require_signature);
+
+        // The key is refused without a signature...
+        let error = Client::builder(&base_url)
+            .api_key(&key.api_key)
+            .build()
+            .unwrap()
+            .generate_secret()
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(401));
+
+        // ...and accepted on signed requests, with and without a body
+        let signed = Client::builder(&base_url)
+            .signed_api_key(&key.api_key, &key.signing_secret)
+            .build()
+            .unwrap();
+        signed.generate_secret().await.unwrap();
+        let result = signed.verify_hotp(&verify_hotp("287082", 1)).await.unwrap();
+        assert!(result.valid);
+
+        admin.revoke_api_key(&key.id).await.unwrap();
+        let error = signed.generate_secret().await.unwrap_err();
+        assert_eq!(error.status(), Some(401));
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limited_requests_are_retried() {
+        let base_url = serve(Config {
+            rate_limit_hotp: Some(RateLimit {
+                limit: 1,
+                window_seconds: 1,
+            }),
+            ..Config::default()
+        });
+        let generate = |counter| GenerateHotpRequest {
+            secret: SECRET.to_string(),
+            counter,
+        };
+
+        let client = Client::builder(&base_url)
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap();
+        client.generate_hotp(&generate(1)).await.unwrap();
+        let error = client.generate_hotp(&generate(2)).await.unwrap_err();
+        assert_eq!(error.status(), Some(429));
+        assert_eq!(error.code(), Some("too_many_requests"));
+        assert!(matches!(
+            &error,
+            Error::Api { problem, .. } if problem.retry_after.is_some()
+        ));
+
+        // Retried once the window passes
+        let client = Client::builder(&base_url)
+            .retry(RetryPolicy {
+                max_retries: 3,
+                initial_backoff: Duration::from_millis(50),
+                max_backoff: Duration::from_secs(2),
+            })
+            .build()
+            .unwrap();
+        let otp = client.generate_hotp(&generate(3)).await.unwrap().otp;
+        let expected = Hotp::new(hex::decode(SECRET).unwrap(), 6)
+            .generate(3)
+            .unwrap();
+        assert_eq!(otp, expected);
+    }
+
+    #[actix_web::test]
+    async fn test_verifications_are_not_retried_after_a_gateway_error() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // A gateway that may have forwarded every request before failing
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new().default_service(web::to(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { actix_web::HttpResponse::BadGateway().finish() }
+            }))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let client = Client::builder(format!("http://{}", addr))
+            .retry(RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            })
+            .build()
+            .unwrap();
+        let error = client
+            .verify_hotp(&verify_hotp("755224", 0))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(502));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        // Generating a code again is harmless
+        let generate = GenerateHotpRequest {
+            secret: SECRET.to_string(),
+            counter: 0,
+        };
+        client.generate_hotp(&generate).await.unwrap_err();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_verifications_are_not_retried_after_a_lost_response() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Passes verifications on to the server, then answers the way the server does
+        // when the audit log fails after the code was consumed
+        let upstream = serve(Config::default());
+        let direct = Client::builder(&upstream).build().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let server = HttpServer::new(move || {
+            let (upstream, counter) = (upstream.clone(), counter.clone());
+            App::new().default_service(web::to(
+                move |req: actix_web::HttpRequest, body: web::Bytes| {
+                    let (upstream, counter) = (upstream.clone(), counter.clone());
+                    async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let response = reqwest::Client::new()
+                            .post(format!("{}{}", upstream, req.path()))
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(body)
+                            .send()
+                            .await
+                            .unwrap();
+                        assert!(response.status().is_success());
+                        actix_web::HttpResponse::ServiceUnavailable()
+                            .content_type(PROBLEM_JSON)
+                            .body(r#"{"type":"urn:otp-server:problem:storage_unavailable","title":"Storage unavailable","status":503,"detail":"Audit log unavailable","code":"storage_unavailable"}"#)
+                    }
+                },
+            ))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let client = Client::builder(format!("http://{}", addr))
+            .retry(RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            })
+            .build()
+            .unwrap();
+        let otp = Hotp::new(hex::decode(SECRET).unwrap(), 6)
+            .generate(0)
+            .unwrap();
+        let error = client.verify_hotp(&verify_hotp(&otp, 0)).await.unwrap_err();
+        assert_eq!(error.code(), Some("storage_unavailable"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // A retry would have been refused: the code was used up by the first attempt
+        let retried = direct.verify_hotp(&verify_hotp(&otp, 0)).await.unwrap();
+        assert!(!retried.valid);
+    }
+
+    #[actix_web::test]
+    async fn test_timeout() {
+        // Accepts connections but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = Client::builder(format!("http://{}", listener.local_addr().unwrap()))
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let error = client.generate_secret().await.unwrap_err();
+        assert!(error.is_timeout());
+    }
+
+    #[test]
+    fn test_invalid_base_url() {
+        assert!(matches!(
+            Client::builder("not a url").build(),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            Client::builder("mailto:otp@example.com").build(),
+            Err(Error::InvalidUrl(_))
+        ));
+    }
+}
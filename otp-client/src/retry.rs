@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+/// How requests the server couldn't serve right now are retried.
+///
+/// Connection failures and `429`, `502`, `503` and `504` responses are retried with
+/// exponential backoff, waiting at least as long as the server's `Retry-After` asks.
+/// Verifications and other requests that must not take effect twice are only retried
+/// when the server can't have processed them: connection failures, and `429` answered
+/// by the server's rate limiter with problem details. A `503` isn't retried for them, as
+/// the server may answer it after the code was consumed, e.g. when the audit log fails.
+/// Timeouts are never retried: the server may already have consumed the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts; a longer `Retry-After` isn't waited for
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before the given retry (1 for the first), or `None` to give up
+    pub(crate) fn backoff(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry > self.max_retries {
+            return None;
+        }
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_backoff);
+        match retry_after {
+            Some(retry_after) if retry_after > self.max_backoff => None,
+            Some(retry_after) => Some(retry_after.max(exponential)),
+            None => Some(exponential),
+        }
+    }
+}
+
+/// Which failed responses a request may be sent again after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Retry {
+    /// Sending the request twice has the same effect as sending it once
+    Idempotent,
+    /// The request must not take effect twice, e.g. a verification that consumes the code
+    Unprocessed,
+}
+
+impl Retry {
+    /// Whether a response means the server may serve the same request later;
+    /// `problem` is whether the server itself answered, with problem details
+    pub(crate) fn retries(self, status: u16, problem: bool) -> bool {
+        match self {
+            Retry::Idempotent => matches!(status, 429 | 502 | 503 | 504),
+            // Rate limiting refuses requests before they reach a handler; a gateway's 502
+            // or 504 and the server's own 503 don't say whether the request took effect
+            Retry::Unprocessed => status == 429 && problem,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 4,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+        assert_eq!(policy.backoff(1, None), Some(Duration::from_millis(100)));
+        assert_eq!(policy.backoff(2, None), Some(Duration::from_millis(200)));
+        assert_eq!(policy.backoff(3, None), Some(Duration::from_millis(300)));
+        assert_eq!(policy.backoff(5, None), None);
+
+        // Retry-After is honored, unless it's longer than we're willing to wait
+        assert_eq!(
+            policy.backoff(1, Some(Duration::from_millis(250))),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(policy.backoff(1, Some(Duration::from_secs(1))), None);
+
+        assert_eq!(RetryPolicy::none().backoff(1, None), None);
+        assert!(Retry::Idempotent.retries(503, false));
+        assert!(Retry::Idempotent.retries(504, false));
+        assert!(!Retry::Idempotent.retries(500, true));
+    }
+
+    #[test]
+    fn test_unprocessed_requests_are_retried_only_when_rate_limited() {
+        assert!(Retry::Unprocessed.retries(429, true));
+        assert!(!Retry::Unprocessed.retries(429, false));
+        assert!(!Retry::Unprocessed.retries(503, true));
+        assert!(!Retry::Unprocessed.retries(502, true));
+        assert!(!Retry::Unprocessed.retries(504, true));
+    }
+}
@@ -0,0 +1,242 @@
+//! Request and response types of the OTP server API, shared by the server and its
+//! clients so both sides always agree on the wire format.
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying the caller's API key
+pub const API_KEY_HEADER: &str = "X-API-Key";
+/// Header selecting the tenant a request operates on
+pub const TENANT_HEADER: &str = "X-Tenant-Id";
+/// Header carrying the request signature
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// Header carrying the Unix time the request was signed at
+pub const SIGNATURE_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+/// Media type of error responses
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Problem details body (RFC 7807)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Problem {
+    /// URI identifying the problem type
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable machine-readable error code
+    pub code: String,
+    /// Seconds until the request may be retried, for lockouts and rate limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+/// What an API key may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Create secrets and generate codes
+    Generate,
+    /// Verify codes
+    Verify,
+    /// Administrative endpoints
+    Admin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenerateSecretResponse {
+    pub secret: String,
+    pub secret_base32: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenerateOtpRequest {
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenerateOtpResponse {
+    pub otp: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VerifyOtpRequest {
+    #[cfg_attr(
+        feature = "openapi",
+        schema(example = "3132333435363738393031323334353637383930")
+    )]
+    pub secret: String,
+    #[cfg_attr(feature = "openapi", schema(example = "287082"))]
+    pub otp: String,
+    /// Credential the failed-attempt limit applies to; defaults to a fingerprint of the secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_id: Option<String>,
+    /// User whose credentials share a failed-attempt limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// Return a signed receipt when the code is valid
+    #[serde(default)]
+    pub receipt: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VerifyOtpResponse {
+    pub valid: bool,
+    /// Why the code was accepted or not: `valid`, `invalid` or `reused`
+    #[cfg_attr(feature = "openapi", schema(example = "valid"))]
+    pub reason: String,
+    /// Signed verification receipt, when one was requested and the code is valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<String>,
+}
+
+// --- HOTP Structs ---
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenerateHotpRequest {
+    pub secret: String,
+    pub counter: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenerateHotpResponse {
+    pub otp: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VerifyHotpRequest {
+    #[cfg_attr(
+        feature = "openapi",
+        schema(example = "3132333435363738393031323334353637383930")
+    )]
+    pub secret: String,
+    #[cfg_attr(feature = "openapi", schema(example = "287082"))]
+    pub otp: String,
+    #[cfg_attr(feature = "openapi", schema(example = 1))]
+    pub counter: u64,
+    /// Credential the failed-attempt limit applies to; defaults to a fingerprint of the secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_id: Option<String>,
+    /// User whose credentials share a failed-attempt limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// Return a signed receipt when the code is valid
+    #[serde(default)]
+    pub receipt: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VerifyHotpResponse {
+    pub valid: bool,
+    /// Why the code was accepted or not: `valid`, `invalid` or `reused`
+    #[cfg_attr(feature = "openapi", schema(example = "valid"))]
+    pub reason: String,
+    /// Signed verification receipt, when one was requested and the code is valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<String>,
+}
+// --- End HOTP Structs ---
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UnlockRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_id: Option<String>,
+    /// Alternative to `credential_id` for credentials verified without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CreateApiKeyRequest {
+    /// Tenant the key is bound to; omit for a key that may act on any tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub require_signature: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ApiKeyResponse {
+    pub id: String,
+    /// Plaintext key, shown only once
+    pub api_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+    pub require_signature: bool,
+    /// Secret requests are signed with, shown only once. Never send it; unlike the key,
+    /// it doesn't travel with requests.
+    pub signing_secret: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EnrollRequest {
+    pub user_id: String,
+    /// PIN entered before the code where a PIN and OTP are taken together, e.g. RADIUS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EnrollmentResponse {
+    pub user_id: String,
+    /// Hex-encoded secret, shown only once
+    pub secret: String,
+    pub secret_base32: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PurgeTenantResponse {
+    #[cfg_attr(feature = "openapi", schema(example = "acme"))]
+    pub tenant: String,
+    /// Number of stored entries removed
+    #[cfg_attr(feature = "openapi", schema(example = 42))]
+    pub deleted: u64,
+}
+
+/// Request signature over the timestamp, method, path with query and a hash of the body:
+/// `hex(HMAC-SHA256(signing_secret, timestamp \n METHOD \n path?query \n hex(sha256(body))))`
+pub fn signature(
+    signing_secret: &[u8],
+    timestamp: u64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> String {
+    let mut mac = HmacSha256::new_from_slice(signing_secret).expect("HMAC accepts any key length");
+    mac.update(
+        format!(
+            "{}\n{}\n{}\n{}",
+            timestamp,
+            method.to_uppercase(),
+            path_and_query,
+            hex::encode(Sha256::digest(body))
+        )
+        .as_bytes(),
+    );
+    hex::encode(mac.finalize().into_bytes())
+}
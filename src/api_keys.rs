@@ -0,0 +1,439 @@
+use crate::secrets::SecretBox;
+use crate::storage::{OtpStore, SYSTEM_TENANT};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use otp_types::{signature, Scope};
+
+/// Prefix of every issued API key, so leaked keys are easy to recognise and scan for
+const KEY_PREFIX: &str = "otpk";
+/// Prefix of every issued signing secret
+const SIGNING_SECRET_PREFIX: &str = "otps";
+
+/// An API key as stored: only hashes of the key itself are kept, and the signing secret
+/// is encrypted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    /// Tenant the key is bound to; `None` for keys that may act on any tenant
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// Whether requests made with the key must carry an HMAC signature
+    pub require_signature: bool,
+    pub created_at: u64,
+    key_hash: String,
+    /// Hash of the key replaced by the last rotation, accepted until `previous_valid_until`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_key_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_valid_until: Option<u64>,
+    /// Sealed secret requests are signed with; empty for keys issued before signing
+    /// secrets, which can't sign until rotated
+    #[serde(default)]
+    signing_secret: String,
+    /// Sealed signing secret of the key replaced by the last rotation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_signing_secret: Option<String>,
+}
+
+impl ApiKeyRecord {
+    /// Seconds the key replaced by the last rotation stays valid, `None` once it has expired
+    pub fn rotation_grace_remaining(&self, now: u64) -> Option<u64> {
+        self.previous_valid_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+}
+
+/// A newly created or rotated key; the plaintext key and signing secret are only ever
+/// returned here
+#[derive(Debug, Clone)]
+pub struct IssuedKey {
+    pub record: ApiKeyRecord,
+    pub api_key: String,
+    /// Secret requests are signed with. Unlike the key it is never sent, so a leaked key
+    /// can't be used to sign.
+    pub signing_secret: String,
+}
+
+/// Caller authenticated by an API key
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    pub key_id: String,
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+    pub require_signature: bool,
+    /// Key for request signatures: the key's signing secret. Empty for credentials that
+    /// can't sign requests.
+    pub signing_key: Vec<u8>,
+}
+
+impl ApiClient {
+    /// Client for the bootstrap admin key from the configuration, which has no signing
+    /// secret
+    pub fn bootstrap() -> Self {
+        Self {
+            key_id: "bootstrap".to_string(),
+            tenant: None,
+            scopes: vec![Scope::Generate, Scope::Verify, Scope::Admin],
+            require_signature: false,
+            signing_key: Vec::new(),
+        }
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Whether the client may act on a tenant (`None` meaning keys that span tenants)
+    pub fn can_access(&self, tenant: Option<&str>) -> bool {
+        match &self.tenant {
+            None => true,
+            Some(own) => tenant == Some(own.as_str()),
+        }
+    }
+}
+
+fn record_key(id: &str) -> String {
+    format!("apikey:{}", id)
+}
+
+fn hash_key(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::rngs::ThreadRng::default().fill(&mut buf[..]);
+    hex::encode(buf)
+}
+
+/// Compare in constant time so response timing doesn't reveal how much of a hash matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Key ID embedded in an API key of the form `otpk_<id>_<secret>`
+fn key_id(api_key: &str) -> Option<&str> {
+    let rest = api_key.strip_prefix(KEY_PREFIX)?.strip_prefix('_')?;
+    let (id, secret) = rest.split_once('_')?;
+    (!id.is_empty() && !secret.is_empty()).then_some(id)
+}
+
+fn issue(secrets: &SecretBox, mut record: ApiKeyRecord) -> IssuedKey {
+    let api_key = format!("{}_{}_{}", KEY_PREFIX, record.id, random_hex(32));
+    let signing_secret = format!("{}_{}", SIGNING_SECRET_PREFIX, random_hex(32));
+    record.key_hash = hash_key(&api_key);
+    record.signing_secret = secrets.seal(&record_key(&record.id), signing_secret.as_bytes());
+    IssuedKey {
+        record,
+        api_key,
+        signing_secret,
+    }
+}
+
+/// Open a sealed signing secret; legacy records without one yield an empty key
+fn open_signing_secret(
+    secrets: &SecretBox,
+    id: &str,
+    sealed: &str,
+) -> Result<(Vec<u8>, bool), String> {
+    if sealed.is_empty() {
+        return Ok((Vec::new(), false));
+    }
+    let opened = secrets.open(&record_key(id), sealed)?;
+    Ok((opened.plaintext, opened.stale))
+}
+
+/// Reseal signing secrets still sealed under the previous secrets key
+async fn reseal(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    mut record: ApiKeyRecord,
+) -> Result<(), String> {
+    let context = record_key(&record.id);
+    for sealed in std::iter::once(&mut record.signing_secret)
+        .chain(record.previous_signing_secret.as_mut())
+        .filter(|sealed| !sealed.is_empty())
+    {
+        let opened = secrets.open(&context, sealed)?;
+        *sealed = secrets.seal(&context, &opened.plaintext);
+    }
+    save(store, &record).await
+}
+
+async fn load(store: &dyn OtpStore, id: &str) -> Result<Option<ApiKeyRecord>, String> {
+    match store.get_value(SYSTEM_TENANT, &record_key(id)).await? {
+        Some(json) => serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| format!("Corrupt API key record {}: {}", id, e)),
+        None => Ok(None),
+    }
+}
+
+async fn save(store: &dyn OtpStore, record: &ApiKeyRecord) -> Result<(), String> {
+    let json = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    store
+        .set_value(SYSTEM_TENANT, &record_key(&record.id), &json)
+        .await
+}
+
+/// Create a key; the returned plaintext can't be recovered later
+pub async fn create(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    tenant: Option<String>,
+    scopes: Vec<Scope>,
+    require_signature: bool,
+    now: u64,
+) -> Result<IssuedKey, String> {
+    let issued = issue(
+        secrets,
+        ApiKeyRecord {
+            id: random_hex(8),
+            tenant,
+            scopes,
+            require_signature,
+            created_at: now,
+            key_hash: String::new(),
+            previous_key_hash: None,
+            previous_valid_until: None,
+            signing_secret: String::new(),
+            previous_signing_secret: None,
+        },
+    );
+    save(store, &issued.record).await?;
+    Ok(issued)
+}
+
+pub async fn get(store: &dyn OtpStore, id: &str) -> Result<Option<ApiKeyRecord>, String> {
+    load(store, id).await
+}
+
+/// Replace a key's secret, keeping the old one valid for `grace_seconds`
+pub async fn rotate(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    id: &str,
+    grace_seconds: u64,
+    now: u64,
+) -> Result<Option<IssuedKey>, String> {
+    let record = match load(store, id).await? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let previous_hash = record.key_hash.clone();
+    let previous_signing_secret = record.signing_secret.clone();
+    let mut issued = issue(secrets, record);
+    if grace_seconds > 0 {
+        issued.record.previous_key_hash = Some(previous_hash);
+        issued.record.previous_valid_until = Some(now + grace_seconds);
+        issued.record.previous_signing_secret = Some(previous_signing_secret);
+    } else {
+        issued.record.previous_key_hash = None;
+        issued.record.previous_valid_until = None;
+        issued.record.previous_signing_secret = None;
+    }
+    save(store, &issued.record).await?;
+    Ok(Some(issued))
+}
+
+/// Delete a key, returning whether it existed
+pub async fn revoke(store: &dyn OtpStore, id: &str) -> Result<bool, String> {
+    let existed = load(store, id).await?.is_some();
+    store.remove(SYSTEM_TENANT, &[record_key(id)]).await?;
+    Ok(existed)
+}
+
+/// Look up the client a presented API key belongs to, `None` if the key is unknown
+pub async fn authenticate(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    api_key: &str,
+    now: u64,
+) -> Result<Option<ApiClient>, String> {
+    let id = match key_id(api_key) {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let record = match load(store, id).await? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let presented = hash_key(api_key);
+    let current = constant_time_eq(presented.as_bytes(), record.key_hash.as_bytes());
+    let previous = match (&record.previous_key_hash, record.previous_valid_until) {
+        (Some(hash), Some(until)) if now < until => {
+            constant_time_eq(presented.as_bytes(), hash.as_bytes())
+        }
+        _ => false,
+    };
+    if !current && !previous {
+        return Ok(None);
+    }
+
+    // Requests made with the replaced key are signed with its signing secret
+    let sealed = match (current, &record.previous_signing_secret) {
+        (false, Some(sealed)) => sealed.as_str(),
+        (false, None) => "",
+        (true, _) => record.signing_secret.as_str(),
+    };
+    let (signing_key, stale) = open_signing_secret(secrets, &record.id, sealed)?;
+    if stale {
+        if let Err(e) = reseal(store, secrets, record.clone()).await {
+            log::warn!(
+                "Failed to reseal signing secrets of API key {}: {}",
+                record.id,
+                e
+            );
+        }
+    }
+
+    Ok(Some(ApiClient {
+        key_id: record.id,
+        tenant: record.tenant,
+        scopes: record.scopes,
+        require_signature: record.require_signature,
+        signing_key,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::tests::{secrets, KEY, OTHER_KEY};
+    use crate::storage::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_create_and_authenticate() {
+        let store = MemoryStore::new();
+        let secrets = secrets(KEY, None);
+        let issued = create(
+            &store,
+            &secrets,
+            Some("acme".to_string()),
+            vec![Scope::Verify],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+        assert!(issued.api_key.starts_with("otpk_"));
+
+        assert!(issued.signing_secret.starts_with("otps_"));
+
+        // Only the hash of the key and the sealed signing secret are stored
+        let stored = store
+            .get_value(SYSTEM_TENANT, &record_key(&issued.record.id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!stored.contains(&issued.api_key));
+        assert!(!stored.contains(&issued.signing_secret));
+
+        let client = authenticate(&store, &secrets, &issued.api_key, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.tenant.as_deref(), Some("acme"));
+        assert_eq!(client.signing_key, issued.signing_secret.as_bytes());
+        // The signing secret can't be derived from the key
+        assert_ne!(
+            client.signing_key,
+            Sha256::digest(issued.api_key.as_bytes()).to_vec()
+        );
+        assert!(client.has_scope(Scope::Verify));
+        assert!(!client.has_scope(Scope::Admin));
+        assert!(client.can_access(Some("acme")));
+        assert!(!client.can_access(Some("globex")));
+
+        let forged = format!("{}0", issued.api_key);
+        assert!(authenticate(&store, &secrets, &forged, 0)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(authenticate(&store, &secrets, "garbage", 0)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_previous_key_for_grace_period() {
+        let store = MemoryStore::new();
+        let secrets = secrets(KEY, None);
+        let old = create(&store, &secrets, None, vec![Scope::Admin], false, 0)
+            .await
+            .unwrap();
+        let new = rotate(&store, &secrets, &old.record.id, 60, 100)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(old.api_key, new.api_key);
+        assert_ne!(old.signing_secret, new.signing_secret);
+
+        assert!(authenticate(&store, &secrets, &new.api_key, 100)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(new.record.rotation_grace_remaining(100), Some(60));
+        assert_eq!(new.record.rotation_grace_remaining(160), None);
+        // The replaced key keeps signing with its own secret
+        let client = authenticate(&store, &secrets, &old.api_key, 159)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.signing_key, old.signing_secret.as_bytes());
+        assert!(authenticate(&store, &secrets, &old.api_key, 160)
+            .await
+            .unwrap()
+            .is_none());
+
+        assert!(revoke(&store, &old.record.id).await.unwrap());
+        assert!(authenticate(&store, &secrets, &new.api_key, 100)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_signing_secrets_are_resealed_after_key_rotation() {
+        let store = MemoryStore::new();
+        let issued = create(&store, &secrets(KEY, None), None, vec![], true, 0)
+            .await
+            .unwrap();
+
+        let rotated = secrets(OTHER_KEY, Some(KEY));
+        let client = authenticate(&store, &rotated, &issued.api_key, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.signing_key, issued.signing_secret.as_bytes());
+
+        // Once read, the secret opens without the previous key
+        let client = authenticate(&store, &secrets(OTHER_KEY, None), &issued.api_key, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.signing_key, issued.signing_secret.as_bytes());
+    }
+
+    #[test]
+    fn test_signature_covers_request() {
+        let key = Sha256::digest(b"otpk_id_secret").to_vec();
+        let sig = signature(&key, 1000, "post", "/api/otp/verify", b"{}");
+        assert_eq!(sig, signature(&key, 1000, "POST", "/api/otp/verify", b"{}"));
+        assert_ne!(sig, signature(&key, 1001, "POST", "/api/otp/verify", b"{}"));
+        assert_ne!(
+            sig,
+            signature(&key, 1000, "POST", "/api/hotp/verify", b"{}")
+        );
+        assert_ne!(
+            sig,
+            signature(&key, 1000, "POST", "/api/otp/verify", b"{ }")
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use super::{now, parse_lines, AuditEvent, AuditRecord, AuditSink, ChainHead};
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Appends records to a JSONL file, one record per line.
+///
+/// The chain is resumed from the last record when the file is opened, after removing a
+/// partial record left by a crash. Only one process may write a file; replicas sharing
+/// a log should use the Redis stream instead.
+pub struct FileSink {
+    path: String,
+    key: Vec<u8>,
+    state: Mutex<(File, ChainHead)>,
+}
+
+impl FileSink {
+    pub async fn open(path: &str, key: &[u8]) -> Result<Self, String> {
+        let contents = match fs::read(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read audit log {}: {}", path, e)),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| format!("Failed to open audit log {}: {}", path, e))?;
+
+        // A crash mid-append leaves a partial record after the last newline. It was never
+        // acknowledged, so it is cut off; a record that only lacks its newline is completed.
+        let mut end = contents
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let tail = &contents[end..];
+        if !tail.is_empty() {
+            let complete = std::str::from_utf8(tail)
+                .is_ok_and(|tail| serde_json::from_str::<AuditRecord>(tail).is_ok());
+            let recovered = if complete {
+                end = contents.len();
+                file.write_all(b"\n").await
+            } else {
+                log::warn!(
+                    "Audit log {} ends in a partial record, probably from a crash; removing its {} bytes",
+                    path,
+                    tail.len()
+                );
+                file.set_len(end as u64).await
+            };
+            recovered.map_err(|e| format!("Failed to repair audit log {}: {}", path, e))?;
+        }
+
+        // The chain continues from the last record
+        let contents = String::from_utf8_lossy(&contents[..end]);
+        let last = contents.lines().rev().find(|line| !line.trim().is_empty());
+        let head = parse_lines(last.unwrap_or_default())
+            .map_err(|e| format!("Failed to resume audit log {}: {}", path, e))?
+            .last()
+            .map(ChainHead::of)
+            .unwrap_or_else(ChainHead::genesis);
+
+        Ok(Self {
+            path: path.to_string(),
+            key: key.to_vec(),
+            state: Mutex::new((file, head)),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileSink {
+    async fn append(&self, event: AuditEvent) -> Result<AuditRecord, String> {
+        let mut state = self.state.lock().await;
+        let (file, head) = &mut *state;
+        let record = AuditRecord::new(head, event, now(), &self.key);
+        let mut line = serde_json::to_string(&record)
+            .map_err(|e| format!("Failed to serialize audit record: {}", e))?;
+        line.push('\n');
+
+        // One write per record, so a crash can't interleave half a line with the next
+        file.write_all(line.as_bytes())
+            .await
+            .and(file.flush().await)
+            .map_err(|e| format!("Failed to write audit log {}: {}", self.path, e))?;
+        *head = ChainHead::of(&record);
+        Ok(record)
+    }
+
+    async fn records(&self) -> Result<Vec<AuditRecord>, String> {
+        let contents = fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| format!("Failed to read audit log {}: {}", self.path, e))?;
+        parse_lines(&contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{verify_chain, Action, TEST_KEY};
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "otp-audit-{}-{}.jsonl",
+            name,
+            hex::encode(rand::random::<[u8; 8]>())
+        ));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_file_chain_resumes_after_reopen() {
+        let path = temp_path("resume");
+        let sink = FileSink::open(&path, TEST_KEY).await.unwrap();
+        sink.append(
+            AuditEvent::new(Action::EnrollmentCreated, Some("acme")).user_id(Some("alice")),
+        )
+        .await
+        .unwrap();
+        drop(sink);
+
+        let sink = FileSink::open(&path, TEST_KEY).await.unwrap();
+        let record = sink
+            .append(AuditEvent::new(Action::Verification, Some("acme")).outcome("valid"))
+            .await
+            .unwrap();
+        assert_eq!(record.seq, 2);
+
+        let records = sink.records().await.unwrap();
+        assert_eq!(
+            verify_chain(&records, TEST_KEY).unwrap(),
+            ChainHead::of(&record)
+        );
+
+        // Editing a line in place is caught
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replace("alice", "mallory")).unwrap();
+        let records = sink.records().await.unwrap();
+        assert!(verify_chain(&records, TEST_KEY)
+            .unwrap_err()
+            .starts_with("Record 1"));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_torn_final_line_is_recovered() {
+        let path = temp_path("torn");
+        let sink = FileSink::open(&path, TEST_KEY).await.unwrap();
+        sink.append(AuditEvent::new(Action::Verification, Some("acme")).outcome("valid"))
+            .await
+            .unwrap();
+        drop(sink);
+        let intact = std::fs::read_to_string(&path).unwrap();
+
+        // Half a record written before a crash is removed
+        std::fs::write(&path, format!("{}{{\"seq\":2,\"ti", intact)).unwrap();
+        let sink = FileSink::open(&path, TEST_KEY).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), intact);
+        drop(sink);
+
+        // A record missing only its newline is kept
+        std::fs::write(&path, intact.trim_end()).unwrap();
+        let sink = FileSink::open(&path, TEST_KEY).await.unwrap();
+        let record = sink
+            .append(AuditEvent::new(Action::Lockout, Some("acme")))
+            .await
+            .unwrap();
+        assert_eq!(record.seq, 2);
+        let records = sink.records().await.unwrap();
+        assert_eq!(
+            verify_chain(&records, TEST_KEY).unwrap(),
+            ChainHead::of(&record)
+        );
+        std::fs::remove_file(&path).ok();
+    }
+}
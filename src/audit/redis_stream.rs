@@ -0,0 +1,167 @@
+use super::{now, AuditEvent, AuditRecord, AuditSink, ChainHead};
+use crate::storage::SYSTEM_TENANT;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client as RedisClient, Script};
+use std::collections::HashMap;
+use tokio::sync::OnceCell;
+
+/// Append a record only if the chain head is still the one it was chained onto.
+///
+/// KEYS: stream, head. ARGV: expected head, record JSON, new head.
+const APPEND_SCRIPT: &str = r"
+if (redis.call('GET', KEYS[2]) or '') ~= ARGV[1] then
+    return 0
+end
+redis.call('XADD', KEYS[1], '*', 'record', ARGV[2])
+redis.call('SET', KEYS[2], ARGV[3])
+return 1
+";
+
+/// Appends raced by other replicas are retried on the new head this many times
+const MAX_APPEND_ATTEMPTS: u32 = 10;
+
+/// Entries read per XRANGE call
+const PAGE_SIZE: usize = 500;
+
+/// Appends records to a Redis stream shared by every replica.
+///
+/// The chain head is kept next to the stream and only advanced together with an append,
+/// so concurrent writers each chain onto the latest record instead of forking the chain.
+/// One connection is shared by all appends; it is established on first use and
+/// re-established by the connection manager after Redis drops it.
+pub struct RedisStreamSink {
+    client: RedisClient,
+    connection: OnceCell<ConnectionManager>,
+    key: Vec<u8>,
+    stream_key: String,
+    head_key: String,
+}
+
+impl RedisStreamSink {
+    pub fn open(redis_url: &str, key_prefix: &str, key: &[u8]) -> Result<Self, String> {
+        let client = RedisClient::open(redis_url)
+            .map_err(|e| format!("Failed to create Redis client: {}", e))?;
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+            key: key.to_vec(),
+            stream_key: format!("{}:{}:audit:records", key_prefix, SYSTEM_TENANT),
+            head_key: format!("{}:{}:audit:head", key_prefix, SYSTEM_TENANT),
+        })
+    }
+
+    /// The shared connection; cloning it is cheap and shares the underlying socket
+    async fn connection(&self) -> Result<ConnectionManager, String> {
+        self.connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))
+    }
+}
+
+/// Head stored as `<seq>:<hash>`
+fn encode_head(head: &ChainHead) -> String {
+    format!("{}:{}", head.seq, head.hash)
+}
+
+fn decode_head(value: Option<&str>) -> Result<ChainHead, String> {
+    let Some(value) = value else {
+        return Ok(ChainHead::genesis());
+    };
+    let (seq, hash) = value
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid audit chain head: {}", value))?;
+    Ok(ChainHead {
+        seq: seq
+            .parse()
+            .map_err(|_| format!("Invalid audit chain head: {}", value))?,
+        hash: hash.to_string(),
+    })
+}
+
+#[async_trait]
+impl AuditSink for RedisStreamSink {
+    async fn append(&self, event: AuditEvent) -> Result<AuditRecord, String> {
+        let mut conn = self.connection().await?;
+        let script = Script::new(APPEND_SCRIPT);
+        for _ in 0..MAX_APPEND_ATTEMPTS {
+            let current: Option<String> = conn
+                .get(&self.head_key)
+                .await
+                .map_err(|e| format!("Failed to read audit chain head: {}", e))?;
+            let head = decode_head(current.as_deref())?;
+            let record = AuditRecord::new(&head, event.clone(), now(), &self.key);
+            let json = serde_json::to_string(&record)
+                .map_err(|e| format!("Failed to serialize audit record: {}", e))?;
+
+            let appended: i64 = script
+                .key(&self.stream_key)
+                .key(&self.head_key)
+                .arg(current.unwrap_or_default())
+                .arg(json)
+                .arg(encode_head(&ChainHead::of(&record)))
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| format!("Failed to append audit record: {}", e))?;
+            if appended == 1 {
+                return Ok(record);
+            }
+        }
+        Err(format!(
+            "Audit chain head kept moving, gave up after {} attempts",
+            MAX_APPEND_ATTEMPTS
+        ))
+    }
+
+    async fn records(&self) -> Result<Vec<AuditRecord>, String> {
+        let mut conn = self.connection().await?;
+        let mut records = Vec::new();
+        let mut start = "-".to_string();
+        loop {
+            let entries: Vec<(String, HashMap<String, String>)> = redis::cmd("XRANGE")
+                .arg(&self.stream_key)
+                .arg(&start)
+                .arg("+")
+                .arg("COUNT")
+                .arg(PAGE_SIZE)
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| format!("Failed to read audit stream: {}", e))?;
+
+            for (id, fields) in &entries {
+                let json = fields
+                    .get("record")
+                    .ok_or_else(|| format!("Stream entry {}: no audit record", id))?;
+                records.push(serde_json::from_str(json).map_err(|e| {
+                    format!("Stream entry {}: not a valid audit record: {}", id, e)
+                })?);
+            }
+            match entries.last() {
+                // Continue after the last entry read; `(` makes the start exclusive
+                Some((id, _)) if entries.len() == PAGE_SIZE => start = format!("({}", id),
+                _ => return Ok(records),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head_encoding() {
+        assert_eq!(decode_head(None).unwrap(), ChainHead::genesis());
+        let head = ChainHead {
+            seq: 42,
+            hash: "ab".repeat(32),
+        };
+        assert_eq!(decode_head(Some(&encode_head(&head))).unwrap(), head);
+        assert!(decode_head(Some("garbage")).is_err());
+
+        let sink = RedisStreamSink::open("redis://127.0.0.1", "otp", b"key").unwrap();
+        assert_eq!(sink.stream_key, "otp:_system:audit:records");
+    }
+}
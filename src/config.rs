@@ -1,7 +1,13 @@
+use crate::logging;
+use crate::server::client_ip::parse_networks;
 use dotenv::dotenv;
+use ipnet::IpNet;
+use serde::Serialize;
 use std::env;
 
-#[derive(Debug, Clone)]
+/// Server configuration. Serializes to the effective configuration with secrets redacted,
+/// for logging at startup.
+#[derive(Debug, Clone, Serialize)]
 pub struct Config {
     pub server_host: String,
     pub server_port: u16,
@@ -12,14 +18,173 @@ pub struct Config {
     pub otp_expiry_seconds: u64,
     // pub storage_cleanup_interval: u64, // Removed unused field
     pub storage_type: StorageType,
+    #[serde(serialize_with = "logging::serialize_url")]
     pub redis_url: String,
+    pub redis_key_prefix: String,
+    pub default_tenant: String,
+    #[serde(serialize_with = "logging::serialize_secret")]
+    pub replay_key_pepper: String,
+    #[serde(serialize_with = "logging::serialize_optional_secret")]
+    pub replay_key_pepper_previous: Option<String>,
+    /// Hex-encoded 32-byte key that secrets kept in storage are encrypted with
+    #[serde(serialize_with = "logging::serialize_optional_secret")]
+    pub secrets_key: Option<String>,
+    /// Previous `secrets_key` during a rotation, still used to decrypt
+    #[serde(serialize_with = "logging::serialize_optional_secret")]
+    pub secrets_key_previous: Option<String>,
+    pub redis_connect_retries: u32,
+    pub redis_connect_backoff_ms: u64,
+    pub redis_connect_backoff_max_ms: u64,
+    /// Interval of the pings that keep the storage readiness state current
+    pub storage_ping_interval_seconds: u64,
+    pub storage_failure_policy: FailurePolicy,
+    pub circuit_breaker_failure_threshold: u32,
+    pub circuit_breaker_reset_seconds: u64,
+    pub health_storage_latency_degraded_ms: u64,
+    pub health_clock_tolerance_seconds: u64,
+    pub lockout_max_credential_failures: u32,
+    pub lockout_max_user_failures: u32,
+    pub lockout_failure_window_seconds: u64,
+    pub lockout_base_seconds: u64,
+    pub lockout_max_seconds: u64,
+    pub trusted_proxies: Vec<IpNet>,
+    pub rate_limit_secret: Option<RateLimit>,
+    pub rate_limit_otp: Option<RateLimit>,
+    pub rate_limit_hotp: Option<RateLimit>,
+    pub stuffing_min_attempts: u64,
+    pub stuffing_failure_ratio: f64,
+    pub stuffing_window_seconds: u64,
+    pub stuffing_block_seconds: u64,
+    pub stuffing_ipv4_prefix: u8,
+    pub stuffing_ipv6_prefix: u8,
+    pub access_policy_file: Option<String>,
+    pub access_policy_reload_seconds: u64,
+    /// Enabled unless `API_AUTH_ENABLED=false`; `Config::default()` leaves it off for tests
+    pub api_auth_enabled: bool,
+    #[serde(serialize_with = "logging::serialize_optional_secret")]
+    pub admin_api_key: Option<String>,
+    pub api_key_rotation_grace_seconds: u64,
+    pub request_signature_tolerance_seconds: u64,
+    /// HTTP date after which the v1 API may be removed, sent in a `Sunset` header
+    pub api_v1_sunset: Option<String>,
+    pub jwt_jwks_file: Option<String>,
+    #[serde(serialize_with = "logging::serialize_optional_url")]
+    pub jwt_jwks_url: Option<String>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    pub jwt_tenant_claim: String,
+    pub jwt_scope_claim: String,
+    pub jwt_jwks_refresh_seconds: u64,
+    pub jwt_leeway_seconds: u64,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    pub tls_client_ca_file: Option<String>,
+    pub tls_client_auth_required: bool,
+    pub tls_reload_seconds: u64,
+    pub tls_client_identities_file: Option<String>,
+    pub receipt_signing_key_files: Vec<String>,
+    pub receipt_issuer: String,
+    pub receipt_audience: Option<String>,
+    pub receipt_acr: String,
+    pub receipt_ttl_seconds: u64,
+    pub enrollment_issuer: String,
+    #[serde(serialize_with = "logging::serialize_optional_secret")]
+    pub forward_auth_session_secret: Option<String>,
+    pub forward_auth_tenant: Option<String>,
+    pub forward_auth_session_seconds: u64,
+    pub forward_auth_cookie_name: String,
+    pub forward_auth_cookie_domain: Option<String>,
+    pub forward_auth_cookie_secure: bool,
+    #[serde(serialize_with = "logging::serialize_optional_url")]
+    pub forward_auth_login_url: Option<String>,
+    pub forward_auth_policy_file: Option<String>,
+    pub radius_bind: Option<String>,
+    pub radius_clients_file: Option<String>,
+    pub grpc_bind: Option<String>,
+    pub metrics_enabled: bool,
+    pub metrics_bind: Option<String>,
+    #[serde(serialize_with = "logging::serialize_optional_url")]
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    pub otel_service_name: String,
+    pub otel_sample_ratio: f64,
+    pub audit_file: Option<String>,
+    pub audit_redis_stream: bool,
+    /// Key the audit record hashes are computed with
+    #[serde(serialize_with = "logging::serialize_optional_secret")]
+    pub audit_hmac_key: Option<String>,
+    pub audit_failure_policy: FailurePolicy,
+    pub webhooks_file: Option<String>,
+    pub webhook_timeout_seconds: u64,
+    pub webhook_max_attempts: u32,
+    pub webhook_backoff_seconds: u64,
+    pub webhook_backoff_max_seconds: u64,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum StorageType {
     Redis,
 }
 
+/// How requests behave when replay-protection storage, the audit log or the webhook
+/// queue is unavailable
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Reject the request with an error
+    Closed,
+    /// Carry on without replay protection, the audit record or the webhook and raise an alert
+    Open,
+}
+
+impl FailurePolicy {
+    fn parse(name: &str, value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "open" => FailurePolicy::Open,
+            "closed" => FailurePolicy::Closed,
+            other => {
+                log::warn!("Unknown {} '{}', defaulting to closed", name, other);
+                FailurePolicy::Closed
+            }
+        }
+    }
+}
+
+/// Requests allowed per sliding window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub window_seconds: u64,
+}
+
+impl RateLimit {
+    /// Parse `<limit>/<window_seconds>`, e.g. `100/60`. Empty or zero disables the limit.
+    fn parse(name: &str, value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        let parsed = value.split_once('/').and_then(|(limit, window)| {
+            Some(RateLimit {
+                limit: limit.trim().parse().ok()?,
+                window_seconds: window.trim().parse().ok()?,
+            })
+        });
+        match parsed {
+            Some(limit) if limit.limit > 0 && limit.window_seconds > 0 => Some(limit),
+            Some(_) => None,
+            None => {
+                log::warn!(
+                    "Invalid {} '{}', expected <limit>/<window_seconds>; rate limiting disabled",
+                    name,
+                    value
+                );
+                None
+            }
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -31,6 +196,88 @@ impl Default for Config {
             // storage_cleanup_interval: 60, // Removed unused field
             storage_type: StorageType::Redis,
             redis_url: "redis://127.0.0.1:6379".to_string(),
+            redis_key_prefix: "otp".to_string(),
+            default_tenant: "default".to_string(),
+            replay_key_pepper: String::new(),
+            replay_key_pepper_previous: None,
+            secrets_key: None,
+            secrets_key_previous: None,
+            redis_connect_retries: 5,
+            redis_connect_backoff_ms: 1000,
+            redis_connect_backoff_max_ms: 30000,
+            storage_ping_interval_seconds: 5,
+            storage_failure_policy: FailurePolicy::Closed,
+            circuit_breaker_failure_threshold: 5,
+            circuit_breaker_reset_seconds: 30,
+            health_storage_latency_degraded_ms: 250,
+            health_clock_tolerance_seconds: 30,
+            lockout_max_credential_failures: 5,
+            lockout_max_user_failures: 20,
+            lockout_failure_window_seconds: 900,
+            lockout_base_seconds: 60,
+            lockout_max_seconds: 3600,
+            trusted_proxies: Vec::new(),
+            rate_limit_secret: None,
+            rate_limit_otp: None,
+            rate_limit_hotp: None,
+            stuffing_min_attempts: 20,
+            stuffing_failure_ratio: 0.9,
+            stuffing_window_seconds: 600,
+            stuffing_block_seconds: 3600,
+            stuffing_ipv4_prefix: 24,
+            stuffing_ipv6_prefix: 48,
+            access_policy_file: None,
+            access_policy_reload_seconds: 10,
+            api_auth_enabled: false,
+            admin_api_key: None,
+            api_key_rotation_grace_seconds: 86400,
+            request_signature_tolerance_seconds: 300,
+            api_v1_sunset: None,
+            jwt_jwks_file: None,
+            jwt_jwks_url: None,
+            jwt_issuer: None,
+            jwt_audience: None,
+            jwt_tenant_claim: "tenant".to_string(),
+            jwt_scope_claim: "scope".to_string(),
+            jwt_jwks_refresh_seconds: 300,
+            jwt_leeway_seconds: 60,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_client_ca_file: None,
+            tls_client_auth_required: false,
+            tls_reload_seconds: 60,
+            tls_client_identities_file: None,
+            receipt_signing_key_files: Vec::new(),
+            receipt_issuer: "otp-server".to_string(),
+            receipt_audience: None,
+            receipt_acr: "otp".to_string(),
+            receipt_ttl_seconds: 300,
+            enrollment_issuer: "OTP Server".to_string(),
+            forward_auth_session_secret: None,
+            forward_auth_tenant: None,
+            forward_auth_session_seconds: 43200,
+            forward_auth_cookie_name: "otp_session".to_string(),
+            forward_auth_cookie_domain: None,
+            forward_auth_cookie_secure: true,
+            forward_auth_login_url: None,
+            forward_auth_policy_file: None,
+            radius_bind: None,
+            radius_clients_file: None,
+            grpc_bind: None,
+            metrics_enabled: true,
+            metrics_bind: None,
+            otel_exporter_otlp_endpoint: None,
+            otel_service_name: "otp-server".to_string(),
+            otel_sample_ratio: 1.0,
+            audit_file: None,
+            audit_redis_stream: false,
+            audit_hmac_key: None,
+            audit_failure_policy: FailurePolicy::Closed,
+            webhooks_file: None,
+            webhook_timeout_seconds: 10,
+            webhook_max_attempts: 10,
+            webhook_backoff_seconds: 5,
+            webhook_backoff_max_seconds: 3600,
         }
     }
 }
@@ -64,6 +311,244 @@ impl Config {
         let redis_url =
             env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
 
+        let redis_key_prefix = env::var("REDIS_KEY_PREFIX").unwrap_or_else(|_| "otp".to_string());
+        let default_tenant = env::var("DEFAULT_TENANT").unwrap_or_else(|_| "default".to_string());
+        let replay_key_pepper = env::var("REPLAY_KEY_PEPPER").unwrap_or_default();
+        let replay_key_pepper_previous = env::var("REPLAY_KEY_PEPPER_PREVIOUS")
+            .ok()
+            .filter(|p| !p.is_empty());
+        let secrets_key = env::var("SECRETS_KEY").ok().filter(|k| !k.is_empty());
+        let secrets_key_previous = env::var("SECRETS_KEY_PREVIOUS")
+            .ok()
+            .filter(|k| !k.is_empty());
+
+        let redis_connect_retries = env::var("REDIS_CONNECT_RETRIES")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+        let redis_connect_backoff_ms = env::var("REDIS_CONNECT_BACKOFF_MS")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse()
+            .unwrap_or(1000);
+        let redis_connect_backoff_max_ms = env::var("REDIS_CONNECT_BACKOFF_MAX_MS")
+            .unwrap_or_else(|_| "30000".to_string())
+            .parse()
+            .unwrap_or(30000);
+        let storage_ping_interval_seconds = env::var("STORAGE_PING_INTERVAL_SECONDS")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+
+        let storage_failure_policy = env::var("STORAGE_FAILURE_POLICY")
+            .map(|v| FailurePolicy::parse("STORAGE_FAILURE_POLICY", &v))
+            .unwrap_or(FailurePolicy::Closed);
+        let circuit_breaker_failure_threshold = env::var("CIRCUIT_BREAKER_FAILURE_THRESHOLD")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+        let circuit_breaker_reset_seconds = env::var("CIRCUIT_BREAKER_RESET_SECONDS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .unwrap_or(30);
+        let health_storage_latency_degraded_ms = env::var("HEALTH_STORAGE_LATENCY_DEGRADED_MS")
+            .unwrap_or_else(|_| "250".to_string())
+            .parse()
+            .unwrap_or(250);
+        // Default to one time step: beyond that the skew window no longer covers the drift
+        let health_clock_tolerance_seconds = env::var("HEALTH_CLOCK_TOLERANCE_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(otp_expiry_seconds);
+
+        let lockout_max_credential_failures = env::var("LOCKOUT_MAX_CREDENTIAL_FAILURES")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+        let lockout_max_user_failures = env::var("LOCKOUT_MAX_USER_FAILURES")
+            .unwrap_or_else(|_| "20".to_string())
+            .parse()
+            .unwrap_or(20);
+        let lockout_failure_window_seconds = env::var("LOCKOUT_FAILURE_WINDOW_SECONDS")
+            .unwrap_or_else(|_| "900".to_string())
+            .parse()
+            .unwrap_or(900);
+        let lockout_base_seconds = env::var("LOCKOUT_BASE_SECONDS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60);
+        let lockout_max_seconds = env::var("LOCKOUT_MAX_SECONDS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .unwrap_or(3600);
+
+        let trusted_proxies = parse_networks(&env::var("TRUSTED_PROXIES").unwrap_or_default());
+        let rate_limit_secret = RateLimit::parse(
+            "RATE_LIMIT_SECRET",
+            &env::var("RATE_LIMIT_SECRET").unwrap_or_default(),
+        );
+        let rate_limit_otp = RateLimit::parse(
+            "RATE_LIMIT_OTP",
+            &env::var("RATE_LIMIT_OTP").unwrap_or_default(),
+        );
+        let rate_limit_hotp = RateLimit::parse(
+            "RATE_LIMIT_HOTP",
+            &env::var("RATE_LIMIT_HOTP").unwrap_or_default(),
+        );
+
+        let stuffing_min_attempts = env::var("STUFFING_MIN_ATTEMPTS")
+            .unwrap_or_else(|_| "20".to_string())
+            .parse()
+            .unwrap_or(20);
+        let stuffing_failure_ratio = env::var("STUFFING_FAILURE_RATIO")
+            .unwrap_or_else(|_| "0.9".to_string())
+            .parse()
+            .unwrap_or(0.9);
+        let stuffing_window_seconds = env::var("STUFFING_WINDOW_SECONDS")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse()
+            .unwrap_or(600);
+        let stuffing_block_seconds = env::var("STUFFING_BLOCK_SECONDS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .unwrap_or(3600);
+        let stuffing_ipv4_prefix = env::var("STUFFING_IPV4_PREFIX")
+            .unwrap_or_else(|_| "24".to_string())
+            .parse()
+            .unwrap_or(24);
+        let stuffing_ipv6_prefix = env::var("STUFFING_IPV6_PREFIX")
+            .unwrap_or_else(|_| "48".to_string())
+            .parse()
+            .unwrap_or(48);
+
+        let access_policy_file = env::var("ACCESS_POLICY_FILE")
+            .ok()
+            .filter(|p| !p.is_empty());
+        let access_policy_reload_seconds = env::var("ACCESS_POLICY_RELOAD_SECONDS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+
+        let api_auth_enabled = env::var("API_AUTH_ENABLED")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let admin_api_key = env::var("ADMIN_API_KEY").ok().filter(|k| !k.is_empty());
+        let api_key_rotation_grace_seconds = env::var("API_KEY_ROTATION_GRACE_SECONDS")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse()
+            .unwrap_or(86400);
+        let request_signature_tolerance_seconds = env::var("REQUEST_SIGNATURE_TOLERANCE_SECONDS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+
+        let optional = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let api_v1_sunset = optional("API_V1_SUNSET");
+        let jwt_jwks_file = optional("JWT_JWKS_FILE");
+        let jwt_jwks_url = optional("JWT_JWKS_URL");
+        let jwt_issuer = optional("JWT_ISSUER");
+        let jwt_audience = optional("JWT_AUDIENCE");
+        let jwt_tenant_claim =
+            env::var("JWT_TENANT_CLAIM").unwrap_or_else(|_| "tenant".to_string());
+        let jwt_scope_claim = env::var("JWT_SCOPE_CLAIM").unwrap_or_else(|_| "scope".to_string());
+        let jwt_jwks_refresh_seconds = env::var("JWT_JWKS_REFRESH_SECONDS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+        let jwt_leeway_seconds = env::var("JWT_LEEWAY_SECONDS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60);
+
+        let tls_cert_file = optional("TLS_CERT_FILE");
+        let tls_key_file = optional("TLS_KEY_FILE");
+        let tls_client_ca_file = optional("TLS_CLIENT_CA_FILE");
+        let tls_client_auth_required = env::var("TLS_CLIENT_AUTH_REQUIRED")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let tls_reload_seconds = env::var("TLS_RELOAD_SECONDS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60);
+        let tls_client_identities_file = optional("TLS_CLIENT_IDENTITIES_FILE");
+
+        // The first key signs; any others are only published in the JWKS
+        let receipt_signing_key_files = env::var("RECEIPT_SIGNING_KEY_FILES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect();
+        let receipt_issuer =
+            env::var("RECEIPT_ISSUER").unwrap_or_else(|_| "otp-server".to_string());
+        let receipt_audience = optional("RECEIPT_AUDIENCE");
+        let receipt_acr = env::var("RECEIPT_ACR").unwrap_or_else(|_| "otp".to_string());
+        let receipt_ttl_seconds = env::var("RECEIPT_TTL_SECONDS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+
+        let enrollment_issuer =
+            env::var("ENROLLMENT_ISSUER").unwrap_or_else(|_| "OTP Server".to_string());
+        let forward_auth_session_secret = optional("FORWARD_AUTH_SESSION_SECRET");
+        let forward_auth_tenant = optional("FORWARD_AUTH_TENANT");
+        let forward_auth_session_seconds = env::var("FORWARD_AUTH_SESSION_SECONDS")
+            .unwrap_or_else(|_| "43200".to_string())
+            .parse()
+            .unwrap_or(43200);
+        let forward_auth_cookie_name =
+            env::var("FORWARD_AUTH_COOKIE_NAME").unwrap_or_else(|_| "otp_session".to_string());
+        let forward_auth_cookie_domain = optional("FORWARD_AUTH_COOKIE_DOMAIN");
+        let forward_auth_cookie_secure = env::var("FORWARD_AUTH_COOKIE_SECURE")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let forward_auth_login_url = optional("FORWARD_AUTH_LOGIN_URL");
+        let forward_auth_policy_file = optional("FORWARD_AUTH_POLICY_FILE");
+        let radius_bind = optional("RADIUS_BIND");
+        let radius_clients_file = optional("RADIUS_CLIENTS_FILE");
+        let grpc_bind = optional("GRPC_BIND");
+        let metrics_enabled = env::var("METRICS_ENABLED")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let metrics_bind = optional("METRICS_BIND");
+        let otel_exporter_otlp_endpoint = optional("OTEL_EXPORTER_OTLP_ENDPOINT");
+        let otel_service_name =
+            env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "otp-server".to_string());
+        let otel_sample_ratio = env::var("OTEL_TRACES_SAMPLER_ARG")
+            .unwrap_or_else(|_| "1.0".to_string())
+            .parse()
+            .unwrap_or(1.0);
+        let audit_file = optional("AUDIT_FILE");
+        let audit_redis_stream = env::var("AUDIT_REDIS_STREAM")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let audit_hmac_key = optional("AUDIT_HMAC_KEY");
+        let audit_failure_policy = env::var("AUDIT_FAILURE_POLICY")
+            .map(|v| FailurePolicy::parse("AUDIT_FAILURE_POLICY", &v))
+            .unwrap_or(FailurePolicy::Closed);
+        let webhooks_file = optional("WEBHOOKS_FILE");
+        let webhook_timeout_seconds = env::var("WEBHOOK_TIMEOUT_SECONDS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+        let webhook_max_attempts = env::var("WEBHOOK_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+        let webhook_backoff_seconds = env::var("WEBHOOK_BACKOFF_SECONDS")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+        let webhook_backoff_max_seconds = env::var("WEBHOOK_BACKOFF_MAX_SECONDS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .unwrap_or(3600);
+
         Self {
             server_host,
             server_port,
@@ -73,10 +558,126 @@ impl Config {
             // storage_cleanup_interval, // Removed unused field
             storage_type,
             redis_url,
+            redis_key_prefix,
+            default_tenant,
+            replay_key_pepper,
+            replay_key_pepper_previous,
+            secrets_key,
+            secrets_key_previous,
+            redis_connect_retries,
+            redis_connect_backoff_ms,
+            redis_connect_backoff_max_ms,
+            storage_ping_interval_seconds,
+            storage_failure_policy,
+            circuit_breaker_failure_threshold,
+            circuit_breaker_reset_seconds,
+            health_storage_latency_degraded_ms,
+            health_clock_tolerance_seconds,
+            lockout_max_credential_failures,
+            lockout_max_user_failures,
+            lockout_failure_window_seconds,
+            lockout_base_seconds,
+            lockout_max_seconds,
+            trusted_proxies,
+            rate_limit_secret,
+            rate_limit_otp,
+            rate_limit_hotp,
+            stuffing_min_attempts,
+            stuffing_failure_ratio,
+            stuffing_window_seconds,
+            stuffing_block_seconds,
+            stuffing_ipv4_prefix,
+            stuffing_ipv6_prefix,
+            access_policy_file,
+            access_policy_reload_seconds,
+            api_auth_enabled,
+            admin_api_key,
+            api_key_rotation_grace_seconds,
+            request_signature_tolerance_seconds,
+            api_v1_sunset,
+            jwt_jwks_file,
+            jwt_jwks_url,
+            jwt_issuer,
+            jwt_audience,
+            jwt_tenant_claim,
+            jwt_scope_claim,
+            jwt_jwks_refresh_seconds,
+            jwt_leeway_seconds,
+            tls_cert_file,
+            tls_key_file,
+            tls_client_ca_file,
+            tls_client_auth_required,
+            tls_reload_seconds,
+            tls_client_identities_file,
+            receipt_signing_key_files,
+            receipt_issuer,
+            receipt_audience,
+            receipt_acr,
+            receipt_ttl_seconds,
+            enrollment_issuer,
+            forward_auth_session_secret,
+            forward_auth_tenant,
+            forward_auth_session_seconds,
+            forward_auth_cookie_name,
+            forward_auth_cookie_domain,
+            forward_auth_cookie_secure,
+            forward_auth_login_url,
+            forward_auth_policy_file,
+            radius_bind,
+            radius_clients_file,
+            grpc_bind,
+            metrics_enabled,
+            metrics_bind,
+            otel_exporter_otlp_endpoint,
+            otel_service_name,
+            otel_sample_ratio,
+            audit_file,
+            audit_redis_stream,
+            audit_hmac_key,
+            audit_failure_policy,
+            webhooks_file,
+            webhook_timeout_seconds,
+            webhook_max_attempts,
+            webhook_backoff_seconds,
+            webhook_backoff_max_seconds,
         }
     }
 
     pub fn server_address(&self) -> String {
         format!("{}:{}", self.server_host, self.server_port)
     }
+
+    /// Whether the HTTP and gRPC APIs are only reachable from this machine
+    pub fn binds_loopback_only(&self) -> bool {
+        let loopback = |host: &str| {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            host.eq_ignore_ascii_case("localhost")
+                || host
+                    .parse::<std::net::IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback())
+        };
+        loopback(&self.server_host)
+            && self.grpc_bind.as_deref().is_none_or(|bind| {
+                bind.rsplit_once(':')
+                    .is_some_and(|(host, _)| loopback(host))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_binds() {
+        let config = |host: &str, grpc: Option<&str>| Config {
+            server_host: host.to_string(),
+            grpc_bind: grpc.map(str::to_string),
+            ..Config::default()
+        };
+        assert!(config("127.0.0.1", None).binds_loopback_only());
+        assert!(config("localhost", Some("[::1]:50051")).binds_loopback_only());
+        assert!(!config("0.0.0.0", None).binds_loopback_only());
+        assert!(!config("::1", Some("0.0.0.0:50051")).binds_loopback_only());
+    }
 }
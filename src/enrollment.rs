@@ -0,0 +1,358 @@
+use crate::api_keys::constant_time_eq;
+use crate::secrets::SecretBox;
+use crate::storage::OtpStore;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A user's enrolled TOTP credential, stored per tenant
+#[derive(Debug, Clone)]
+pub struct Enrollment {
+    pub user_id: String,
+    /// Hex-encoded TOTP secret
+    pub secret: String,
+    /// Argon2id hash of the PIN entered before the code, as a PHC string, for front ends
+    /// that take a PIN and OTP together. Enrollments made before PINs were hashed with
+    /// Argon2 hold `salt$hmac` until the PIN is next entered.
+    pub pin_hash: Option<String>,
+    pub created_at: u64,
+}
+
+/// An enrollment as kept in storage, with its secret sealed under `SECRETS_KEY`
+#[derive(Serialize, Deserialize)]
+struct StoredEnrollment {
+    user_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    sealed_secret: String,
+    /// Plaintext secret of enrollments made before secrets were sealed, sealed when next read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin_hash: Option<String>,
+    created_at: u64,
+}
+
+/// Hash a PIN with Argon2id at its default (OWASP recommended) cost, so stolen hashes of
+/// short PINs can't be brute-forced cheaply
+fn hash_pin(salt: [u8; 16], pin: &str) -> Result<String, String> {
+    let salt = SaltString::encode_b64(&salt).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash PIN: {}", e))
+}
+
+/// Hash of PINs set before Argon2 was used
+fn legacy_hash_pin(salt: &str, pin: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(salt.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(pin.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+impl Enrollment {
+    /// Whether a PIN matches; `false` when the user has no PIN
+    pub fn check_pin(&self, pin: &str) -> bool {
+        let Some(stored) = self.pin_hash.as_deref() else {
+            return false;
+        };
+        if let Ok(hash) = PasswordHash::new(stored) {
+            return Argon2::default()
+                .verify_password(pin.as_bytes(), &hash)
+                .is_ok();
+        }
+        match stored.split_once('$') {
+            Some((salt, hash)) => {
+                constant_time_eq(legacy_hash_pin(salt, pin).as_bytes(), hash.as_bytes())
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the PIN is stored in the legacy format and should be rehashed with
+    /// [`upgrade_pin`] once entered correctly
+    pub fn pin_needs_upgrade(&self) -> bool {
+        self.pin_hash
+            .as_deref()
+            .is_some_and(|stored| PasswordHash::new(stored).is_err())
+    }
+
+    /// `otpauth://` URI for authenticator apps, usually shown as a QR code
+    pub fn otpauth_uri(&self, issuer: &str, digits: usize, period: u64) -> String {
+        let secret = hex::decode(&self.secret).unwrap_or_default();
+        format!(
+            "otpauth://totp/{issuer}:{user}?secret={secret}&issuer={issuer}&digits={digits}&period={period}",
+            issuer = percent_encode(issuer),
+            user = percent_encode(&self.user_id),
+            secret = BASE32_NOPAD.encode(&secret),
+            digits = digits,
+            period = period,
+        )
+    }
+}
+
+/// Percent-encode everything but unreserved characters (RFC 3986)
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn record_key(user_id: &str) -> String {
+    format!("enrollment:{}", user_id)
+}
+
+/// What a sealed secret is bound to, so it can't be moved to another user or tenant
+fn seal_context(tenant: &str, user_id: &str) -> String {
+    format!("enrollment:{}:{}", tenant, user_id)
+}
+
+/// Enroll a user with a new secret and optional PIN, replacing any previous enrollment
+pub async fn enroll(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    tenant: &str,
+    user_id: &str,
+    pin: Option<&str>,
+    now: u64,
+) -> Result<Enrollment, String> {
+    // The thread-local RNG isn't Send, so it must be gone before the await
+    let (secret, pin_hash) = {
+        let mut rng = rand::rngs::ThreadRng::default();
+        let mut secret = vec![0u8; 20];
+        rng.fill(&mut secret[..]);
+        let pin_hash = pin.map(|pin| hash_pin(rng.random(), pin)).transpose()?;
+        (secret, pin_hash)
+    };
+
+    let enrollment = Enrollment {
+        user_id: user_id.to_string(),
+        secret: hex::encode(secret),
+        pin_hash,
+        created_at: now,
+    };
+    save(store, secrets, tenant, &enrollment).await?;
+    Ok(enrollment)
+}
+
+/// Rehash a correctly entered PIN stored in the legacy format with Argon2
+pub async fn upgrade_pin(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    tenant: &str,
+    enrollment: &Enrollment,
+    pin: &str,
+) -> Result<(), String> {
+    let pin_hash = hash_pin(rand::random(), pin)?;
+    let upgraded = Enrollment {
+        pin_hash: Some(pin_hash),
+        ..enrollment.clone()
+    };
+    save(store, secrets, tenant, &upgraded).await
+}
+
+async fn save(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    tenant: &str,
+    enrollment: &Enrollment,
+) -> Result<(), String> {
+    let stored = StoredEnrollment {
+        user_id: enrollment.user_id.clone(),
+        sealed_secret: secrets.seal(
+            &seal_context(tenant, &enrollment.user_id),
+            enrollment.secret.as_bytes(),
+        ),
+        secret: None,
+        pin_hash: enrollment.pin_hash.clone(),
+        created_at: enrollment.created_at,
+    };
+    let json = serde_json::to_string(&stored).map_err(|e| e.to_string())?;
+    store
+        .set_value(tenant, &record_key(&enrollment.user_id), &json)
+        .await
+}
+
+/// A user's enrollment. Secrets stored in plaintext or sealed under the previous key are
+/// resealed under the current one.
+pub async fn get(
+    store: &dyn OtpStore,
+    secrets: &SecretBox,
+    tenant: &str,
+    user_id: &str,
+) -> Result<Option<Enrollment>, String> {
+    let Some(json) = store.get_value(tenant, &record_key(user_id)).await? else {
+        return Ok(None);
+    };
+    let stored: StoredEnrollment = serde_json::from_str(&json)
+        .map_err(|e| format!("Corrupt enrollment for {}: {}", user_id, e))?;
+
+    let (secret, stale) = match stored.secret {
+        Some(secret) => (secret, true),
+        None => {
+            let opened = secrets.open(&seal_context(tenant, user_id), &stored.sealed_secret)?;
+            let secret = String::from_utf8(opened.plaintext)
+                .map_err(|_| format!("Corrupt enrollment for {}", user_id))?;
+            (secret, opened.stale)
+        }
+    };
+    let enrollment = Enrollment {
+        user_id: stored.user_id,
+        secret,
+        pin_hash: stored.pin_hash,
+        created_at: stored.created_at,
+    };
+    if stale {
+        if let Err(e) = save(store, secrets, tenant, &enrollment).await {
+            log::warn!("Failed to reseal the secret of {}: {}", user_id, e);
+        }
+    }
+    Ok(Some(enrollment))
+}
+
+/// Whether a user is enrolled, without opening their secret
+pub async fn exists(store: &dyn OtpStore, tenant: &str, user_id: &str) -> Result<bool, String> {
+    Ok(store
+        .get_value(tenant, &record_key(user_id))
+        .await?
+        .is_some())
+}
+
+/// Delete a user's enrollment, returning whether it existed
+pub async fn remove(store: &dyn OtpStore, tenant: &str, user_id: &str) -> Result<bool, String> {
+    let existed = exists(store, tenant, user_id).await?;
+    store.remove(tenant, &[record_key(user_id)]).await?;
+    Ok(existed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::tests::{secrets, KEY, OTHER_KEY};
+    use crate::storage::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_enrollments_are_per_tenant() {
+        let store = MemoryStore::new();
+        let secrets = secrets(KEY, None);
+        let first = enroll(&store, &secrets, "acme", "alice", None, 0)
+            .await
+            .unwrap();
+        assert_eq!(first.secret.len(), 40);
+        assert!(get(&store, &secrets, "globex", "alice")
+            .await
+            .unwrap()
+            .is_none());
+
+        // Re-enrolling replaces the secret
+        let second = enroll(&store, &secrets, "acme", "alice", Some("4711"), 1)
+            .await
+            .unwrap();
+        let stored = get(&store, &secrets, "acme", "alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(first.secret, second.secret);
+        assert_eq!(stored.secret, second.secret);
+        assert!(!first.check_pin("4711"));
+        assert!(stored.check_pin("4711"));
+        assert!(!stored.check_pin("4712"));
+        assert!(!stored.pin_needs_upgrade());
+        let pin_hash = stored.pin_hash.unwrap();
+        assert!(pin_hash.starts_with("$argon2id$"));
+        assert!(!pin_hash.contains("4711"));
+
+        assert!(remove(&store, "acme", "alice").await.unwrap());
+        assert!(!remove(&store, "acme", "alice").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_pins_are_upgraded() {
+        let store = MemoryStore::new();
+        let secrets = secrets(KEY, None);
+        let legacy = Enrollment {
+            user_id: "alice".to_string(),
+            secret: "3132333435363738393031323334353637383930".to_string(),
+            pin_hash: Some(format!("salt${}", legacy_hash_pin("salt", "4711"))),
+            created_at: 0,
+        };
+        assert!(legacy.check_pin("4711"));
+        assert!(!legacy.check_pin("4712"));
+        assert!(legacy.pin_needs_upgrade());
+
+        upgrade_pin(&store, &secrets, "acme", &legacy, "4711")
+            .await
+            .unwrap();
+        let upgraded = get(&store, &secrets, "acme", "alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!upgraded.pin_needs_upgrade());
+        assert!(upgraded.check_pin("4711"));
+        assert_eq!(upgraded.secret, legacy.secret);
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_sealed_at_rest() {
+        let store = MemoryStore::new();
+        let enrollment = enroll(&store, &secrets(KEY, None), "acme", "alice", None, 0)
+            .await
+            .unwrap();
+        let raw = || async {
+            let json = store.get_value("acme", "enrollment:alice").await.unwrap();
+            serde_json::from_str::<serde_json::Value>(&json.unwrap()).unwrap()
+        };
+        assert!(!raw().await.to_string().contains(&enrollment.secret));
+
+        // After a key rotation the previous key still opens the secret, which is resealed
+        let rotated = secrets(OTHER_KEY, Some(KEY));
+        let sealed = raw().await["sealed_secret"].clone();
+        let opened = get(&store, &rotated, "acme", "alice").await.unwrap();
+        assert_eq!(opened.unwrap().secret, enrollment.secret);
+        assert_ne!(raw().await["sealed_secret"], sealed);
+        let opened = get(&store, &secrets(OTHER_KEY, None), "acme", "alice")
+            .await
+            .unwrap();
+        assert_eq!(opened.unwrap().secret, enrollment.secret);
+
+        // Plaintext secrets from before sealing are sealed when read
+        let legacy = serde_json::json!({
+            "user_id": "bob",
+            "secret": "3132333435363738393031323334353637383930",
+            "created_at": 0,
+        });
+        store
+            .set_value("acme", "enrollment:bob", &legacy.to_string())
+            .await
+            .unwrap();
+        let opened = get(&store, &rotated, "acme", "bob").await.unwrap().unwrap();
+        assert_eq!(opened.secret, "3132333435363738393031323334353637383930");
+        let json = store.get_value("acme", "enrollment:bob").await.unwrap();
+        assert!(!json.unwrap().contains(&opened.secret));
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let enrollment = Enrollment {
+            user_id: "alice@example.com".to_string(),
+            secret: "3132333435363738393031323334353637383930".to_string(),
+            pin_hash: None,
+            created_at: 0,
+        };
+        assert_eq!(
+            enrollment.otpauth_uri("Internal Tools", 6, 30),
+            "otpauth://totp/Internal%20Tools:alice%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Internal%20Tools&digits=6&period=30"
+        );
+    }
+}
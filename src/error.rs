@@ -1,46 +1,164 @@
+//! Errors returned by the API, rendered as RFC 7807 problem details.
+//!
+//! Every error carries a stable `code` that clients can branch on; `title` and `detail`
+//! are for humans and may change.
+
+use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
+pub use otp_types::{Problem, PROBLEM_JSON};
 use std::fmt;
 
 #[derive(Debug)]
-#[allow(clippy::enum_variant_names)] // Keep original names for now, or rename as below
 pub enum AppError {
+    /// An unexpected failure inside the server
     Internal(String),
+    /// The request is malformed or fails validation
     Validation(String),
-    // NotFoundError(String), // Clippy reported this variant is never constructed
+    /// No credentials, or credentials that aren't valid
+    Unauthorized(String),
+    /// Valid credentials that don't allow the request
+    Forbidden(String),
+    /// The resource the request names doesn't exist
+    NotFound(String),
+    /// The request conflicts with the current state of the resource
+    Conflict(String),
+    /// The credential or user is locked out after too many failed attempts
+    Locked { message: String, retry_after: u64 },
+    /// The client or its network has sent too many requests
+    TooManyRequests { message: String, retry_after: u64 },
+    /// Storage is unreachable and the request can't be served safely without it
+    StorageUnavailable(String),
 }
 
-impl fmt::Display for AppError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl AppError {
+    /// Stable machine-readable code of the error
+    pub fn code(&self) -> &'static str {
         match self {
-            AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
-            AppError::Validation(msg) => write!(f, "Validation error: {}", msg),
-            // AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            AppError::Internal(_) => "internal_error",
+            AppError::Validation(_) => "validation_error",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Locked { .. } => "locked",
+            AppError::TooManyRequests { .. } => "too_many_requests",
+            AppError::StorageUnavailable(_) => "storage_unavailable",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::Internal(_) => "Internal error",
+            AppError::Validation(_) => "Validation error",
+            AppError::Unauthorized(_) => "Unauthorized",
+            AppError::Forbidden(_) => "Forbidden",
+            AppError::NotFound(_) => "Not found",
+            AppError::Conflict(_) => "Conflict",
+            AppError::Locked { .. } => "Locked",
+            AppError::TooManyRequests { .. } => "Too many requests",
+            AppError::StorageUnavailable(_) => "Storage unavailable",
+        }
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            AppError::Internal(msg)
+            | AppError::Validation(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Forbidden(msg)
+            | AppError::NotFound(msg)
+            | AppError::Conflict(msg)
+            | AppError::StorageUnavailable(msg) => msg,
+            AppError::Locked { message, .. } | AppError::TooManyRequests { message, .. } => message,
+        }
+    }
+
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            AppError::Locked { retry_after, .. }
+            | AppError::TooManyRequests { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// Problem details describing the error
+    pub fn problem(&self) -> Problem {
+        Problem {
+            problem_type: format!("urn:otp-server:problem:{}", self.code()),
+            title: self.title().to_string(),
+            status: self.status_code().as_u16(),
+            detail: self.detail().to_string(),
+            code: self.code().to_string(),
+            retry_after: self.retry_after(),
         }
     }
 }
 
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title(), self.detail())
+    }
+}
+
 impl ResponseError for AppError {
-    fn error_response(&self) -> HttpResponse {
+    fn status_code(&self) -> StatusCode {
         match self {
-            AppError::Internal(msg) => {
-                HttpResponse::InternalServerError().json(json_error("internal_error", msg))
-            }
-            AppError::Validation(msg) => {
-                HttpResponse::BadRequest().json(json_error("validation_error", msg))
-            } // AppError::NotFound(msg) => {
-              //     HttpResponse::NotFound().json(json_error("not_found", msg))
-              // }
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Locked { .. } => StatusCode::LOCKED,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::StorageUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
-}
 
-fn json_error(error_code: &str, message: &str) -> serde_json::Value {
-    serde_json::json!({
-        "error": {
-            "code": error_code,
-            "message": message
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.content_type(PROBLEM_JSON);
+        if let AppError::Unauthorized(_) = self {
+            response.insert_header(("WWW-Authenticate", "ApiKey"));
         }
-    })
+        if let Some(retry_after) = self.retry_after() {
+            response.insert_header(("Retry-After", retry_after.to_string()));
+        }
+        response.json(self.problem())
+    }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    #[actix_web::test]
+    async fn test_errors_render_as_problem_json() {
+        let error = AppError::Locked {
+            message: "Too many failed attempts, try again later".to_string(),
+            retry_after: 30,
+        };
+        let resp = error.error_response();
+        assert_eq!(resp.status(), StatusCode::LOCKED);
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), PROBLEM_JSON);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "30");
+
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let problem: Problem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.problem_type, "urn:otp-server:problem:locked");
+        assert_eq!(problem.status, 423);
+        assert_eq!(problem.code, "locked");
+        assert_eq!(problem.retry_after, Some(30));
+        assert_eq!(problem.detail, "Too many failed attempts, try again later");
+
+        let resp = AppError::StorageUnavailable("connection refused".to_string()).error_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "storage_unavailable");
+        assert!(problem.get("retry_after").is_none());
+    }
+}
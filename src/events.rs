@@ -0,0 +1,47 @@
+//! Security events, recorded in the audit log and sent to webhooks.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::error::AppResult;
+use crate::telemetry;
+use crate::webhooks::Webhooks;
+use std::sync::Arc;
+
+/// Where security events go: the audit log, webhooks, or both
+pub struct Events {
+    audit: Option<AuditLog>,
+    webhooks: Option<Arc<Webhooks>>,
+}
+
+impl Events {
+    /// `None` when neither the audit log nor webhooks are configured
+    pub fn new(audit: Option<AuditLog>, webhooks: Option<Arc<Webhooks>>) -> Option<Self> {
+        (audit.is_some() || webhooks.is_some()).then_some(Self { audit, webhooks })
+    }
+
+    /// Record an event under the current request ID.
+    ///
+    /// The event goes to both webhooks and the audit log even when one of them fails, and
+    /// either failure fails the request under a fail-closed policy.
+    pub async fn record(&self, mut event: AuditEvent) -> AppResult<()> {
+        if event.request_id.is_none() {
+            event.request_id = telemetry::current_ids().0;
+        }
+        let queued = match &self.webhooks {
+            Some(webhooks) => webhooks.notify(&event).await,
+            None => Ok(()),
+        };
+        let audited = match &self.audit {
+            Some(audit) => audit.record(event).await,
+            None => Ok(()),
+        };
+        queued.and(audited)
+    }
+}
+
+/// Record an event when events are enabled
+pub async fn record(events: Option<&Events>, event: AuditEvent) -> AppResult<()> {
+    match events {
+        Some(events) => events.record(event).await,
+        None => Ok(()),
+    }
+}
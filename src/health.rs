@@ -0,0 +1,189 @@
+use crate::config::Config;
+use crate::storage::OtpStore;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+/// Health of a single component, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+    Unhealthy,
+}
+
+/// Result of checking one component
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Aggregated health of the server; the overall status is the worst component status
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub version: &'static str,
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+/// Run every component check and aggregate the results
+pub async fn check(config: &Config, storage: &dyn OtpStore) -> HealthReport {
+    let mut components = BTreeMap::new();
+    components.insert("storage", check_storage(config, storage).await);
+    components.insert("clock", check_clock(config, storage).await);
+
+    let status = components
+        .values()
+        .map(|c| c.status)
+        .max()
+        .unwrap_or(HealthStatus::Ok);
+
+    HealthReport {
+        status,
+        version: env!("CARGO_PKG_VERSION"),
+        components,
+    }
+}
+
+/// Ping storage and classify the round-trip latency
+async fn check_storage(config: &Config, storage: &dyn OtpStore) -> ComponentHealth {
+    let started = Instant::now();
+    let result = storage.ping().await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    match result {
+        Ok(()) => {
+            let degraded = latency_ms > config.health_storage_latency_degraded_ms as f64;
+            ComponentHealth {
+                status: if degraded {
+                    HealthStatus::Degraded
+                } else {
+                    HealthStatus::Ok
+                },
+                latency_ms: Some(latency_ms),
+                offset_seconds: None,
+                message: degraded.then(|| {
+                    format!(
+                        "Latency above {}ms",
+                        config.health_storage_latency_degraded_ms
+                    )
+                }),
+            }
+        }
+        Err(e) => ComponentHealth {
+            status: HealthStatus::Unhealthy,
+            latency_ms: Some(latency_ms),
+            offset_seconds: None,
+            message: Some(e),
+        },
+    }
+}
+
+/// Compare the local wall clock against the storage backend's clock
+async fn check_clock(config: &Config, storage: &dyn OtpStore) -> ComponentHealth {
+    let local = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(e) => {
+            return ComponentHealth {
+                status: HealthStatus::Unhealthy,
+                latency_ms: None,
+                offset_seconds: None,
+                message: Some(format!("System clock is before the Unix epoch: {}", e)),
+            }
+        }
+    };
+
+    let reference = storage.server_time().await;
+    classify_clock(local, reference, config.health_clock_tolerance_seconds)
+}
+
+/// Classify the local clock against a reference time.
+///
+/// An offset above half the tolerance is degraded; above the tolerance TOTP codes
+/// start failing, so the clock is unhealthy. Without a reference the check is degraded.
+fn classify_clock(local: u64, reference: Result<u64, String>, tolerance: u64) -> ComponentHealth {
+    let reference = match reference {
+        Ok(reference) => reference,
+        Err(e) => {
+            return ComponentHealth {
+                status: HealthStatus::Degraded,
+                latency_ms: None,
+                offset_seconds: None,
+                message: Some(format!("No reference time available: {}", e)),
+            }
+        }
+    };
+
+    let offset = local as i64 - reference as i64;
+    let drift = offset.unsigned_abs();
+    let status = if drift > tolerance {
+        HealthStatus::Unhealthy
+    } else if drift * 2 > tolerance {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Ok
+    };
+
+    ComponentHealth {
+        status,
+        latency_ms: None,
+        offset_seconds: Some(offset),
+        message: (status != HealthStatus::Ok)
+            .then(|| format!("Clock offset {}s, tolerance {}s", offset, tolerance)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_classify_clock() {
+        assert_eq!(classify_clock(1000, Ok(1000), 30).status, HealthStatus::Ok);
+        assert_eq!(classify_clock(1015, Ok(1000), 30).status, HealthStatus::Ok);
+        assert_eq!(
+            classify_clock(1000, Ok(1020), 30).status,
+            HealthStatus::Degraded
+        );
+        let skewed = classify_clock(1000, Ok(1100), 30);
+        assert_eq!(skewed.status, HealthStatus::Unhealthy);
+        assert_eq!(skewed.offset_seconds, Some(-100));
+        assert_eq!(
+            classify_clock(1000, Err("down".to_string()), 30).status,
+            HealthStatus::Degraded
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_healthy_store() {
+        let config = Config::default();
+        let report = check(&config, &MemoryStore::new()).await;
+        assert_eq!(report.status, HealthStatus::Ok);
+        assert_eq!(report.components["storage"].status, HealthStatus::Ok);
+        assert!(report.components["storage"].latency_ms.is_some());
+        assert_eq!(report.components["clock"].status, HealthStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_check_unreachable_store() {
+        let config = Config::default();
+        let faulty = FaultInjectingStore::new(Arc::new(MemoryStore::new()));
+        faulty.set_failing(true);
+
+        let report = check(&config, &faulty).await;
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert_eq!(report.components["storage"].status, HealthStatus::Unhealthy);
+        assert_eq!(report.components["clock"].status, HealthStatus::Degraded);
+    }
+}
@@ -0,0 +1,22 @@
+//! OTP server: the HTTP, gRPC and RADIUS front ends, storage and security features,
+//! built into the `otp` binary and used in-process by the client SDK's tests.
+
+pub mod api_keys;
+pub mod audit;
+pub mod config;
+pub mod enrollment;
+pub mod error;
+pub mod events;
+pub mod health;
+pub mod jwt;
+pub mod lockout;
+pub mod logging;
+pub mod metrics;
+pub mod otp;
+pub mod receipts;
+pub mod secrets;
+pub mod server;
+pub mod storage;
+pub mod stuffing;
+pub mod telemetry;
+pub mod webhooks;
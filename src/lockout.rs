@@ -0,0 +1,344 @@
+use crate::config::Config;
+use crate::storage::OtpStore;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long consecutive lockouts are remembered for the exponential backoff
+pub const STRIKE_MEMORY_SECONDS: u64 = 86_400;
+
+/// Something failed attempts are counted against
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    /// A single OTP secret, identified by a client-supplied ID or a fingerprint of the secret
+    Credential(String),
+    /// Every credential of a user
+    User(String),
+}
+
+impl Subject {
+    fn name(&self) -> String {
+        match self {
+            Subject::Credential(id) => format!("credential:{}", id),
+            Subject::User(id) => format!("user:{}", id),
+        }
+    }
+
+    fn failures_key(&self) -> String {
+        format!("lockout:failures:{}", self.name())
+    }
+
+    fn strikes_key(&self) -> String {
+        format!("lockout:strikes:{}", self.name())
+    }
+
+    fn locked_key(&self) -> String {
+        format!("lockout:locked:{}", self.name())
+    }
+}
+
+/// Identify a credential without exposing its secret in storage keys
+pub fn credential_fingerprint(pepper: &str, secret: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(pepper.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(b"credential\0");
+    mac.update(secret.to_lowercase().as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..16])
+}
+
+/// Client-supplied credential ID, or the fingerprint of the secret without one
+pub fn credential_id(config: &Config, secret: &str, credential_id: Option<&str>) -> String {
+    credential_id
+        .map(str::to_string)
+        .unwrap_or_else(|| credential_fingerprint(&config.replay_key_pepper, secret))
+}
+
+/// Subjects an attempt counts against: always the credential, plus the user when known
+pub fn subjects(
+    config: &Config,
+    secret: &str,
+    credential_id: Option<&str>,
+    user_id: Option<&str>,
+) -> Vec<Subject> {
+    let mut subjects = vec![Subject::Credential(self::credential_id(
+        config,
+        secret,
+        credential_id,
+    ))];
+    if let Some(user_id) = user_id {
+        subjects.push(Subject::User(user_id.to_string()));
+    }
+    subjects
+}
+
+/// Failed-attempt limits and lockout durations.
+///
+/// A subject is locked once it reaches its failure threshold within the failure window.
+/// Each lockout within `STRIKE_MEMORY_SECONDS` doubles the duration, up to the maximum.
+/// All state lives in the shared store, so limits hold across replicas.
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    /// Failures before a credential is locked; 0 disables
+    pub max_credential_failures: u32,
+    /// Failures before a user is locked; 0 disables
+    pub max_user_failures: u32,
+    pub failure_window_seconds: u64,
+    pub base_lockout_seconds: u64,
+    pub max_lockout_seconds: u64,
+}
+
+impl LockoutPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_credential_failures: config.lockout_max_credential_failures,
+            max_user_failures: config.lockout_max_user_failures,
+            failure_window_seconds: config.lockout_failure_window_seconds,
+            base_lockout_seconds: config.lockout_base_seconds,
+            max_lockout_seconds: config.lockout_max_seconds,
+        }
+    }
+
+    fn threshold(&self, subject: &Subject) -> u32 {
+        match subject {
+            Subject::Credential(_) => self.max_credential_failures,
+            Subject::User(_) => self.max_user_failures,
+        }
+    }
+
+    /// Lockout duration for the given (1-based) consecutive lockout
+    pub fn lockout_seconds(&self, strikes: u64) -> u64 {
+        let exponent = strikes.saturating_sub(1).min(32) as u32;
+        self.base_lockout_seconds
+            .saturating_mul(2u64.saturating_pow(exponent))
+            .min(self.max_lockout_seconds)
+    }
+
+    /// Longest remaining lockout across the subjects, `None` if none is locked
+    pub async fn locked_for(
+        &self,
+        store: &dyn OtpStore,
+        tenant: &str,
+        subjects: &[Subject],
+    ) -> Result<Option<u64>, String> {
+        let mut longest = None;
+        for subject in subjects {
+            if self.threshold(subject) == 0 {
+                continue;
+            }
+            if let Some(ttl) = store.flag_ttl(tenant, &subject.locked_key()).await? {
+                longest = longest.max(Some(ttl));
+            }
+        }
+        Ok(longest)
+    }
+
+    /// Count a failed attempt, locking any subject that reaches its threshold.
+    ///
+    /// Returns the longest lockout started by this failure.
+    pub async fn record_failure(
+        &self,
+        store: &dyn OtpStore,
+        tenant: &str,
+        subjects: &[Subject],
+    ) -> Result<Option<u64>, String> {
+        let mut started = None;
+        for subject in subjects {
+            let threshold = self.threshold(subject);
+            if threshold == 0 {
+                continue;
+            }
+
+            let failures = store
+                .increment(tenant, &subject.failures_key(), self.failure_window_seconds)
+                .await?;
+            if failures < u64::from(threshold) {
+                continue;
+            }
+
+            let strikes = store
+                .increment(tenant, &subject.strikes_key(), STRIKE_MEMORY_SECONDS)
+                .await?;
+            let seconds = self.lockout_seconds(strikes);
+            store
+                .set_flag(tenant, &subject.locked_key(), seconds)
+                .await?;
+            // Start counting afresh once the lockout ends
+            store.remove(tenant, &[subject.failures_key()]).await?;
+
+            log::warn!(
+                "Locked {} in tenant {} for {}s after {} failed attempts (lockout #{})",
+                subject.name(),
+                tenant,
+                seconds,
+                failures,
+                strikes
+            );
+            started = started.max(Some(seconds));
+        }
+        Ok(started)
+    }
+
+    /// Clear failure counts after a successful attempt. Strikes are kept so
+    /// an attacker can't reset the backoff with an occasional valid code.
+    pub async fn record_success(
+        &self,
+        store: &dyn OtpStore,
+        tenant: &str,
+        subjects: &[Subject],
+    ) -> Result<(), String> {
+        let keys: Vec<String> = subjects.iter().map(Subject::failures_key).collect();
+        store.remove(tenant, &keys).await
+    }
+
+    /// Lift any lockout and forget all failures and strikes
+    pub async fn unlock(
+        &self,
+        store: &dyn OtpStore,
+        tenant: &str,
+        subjects: &[Subject],
+    ) -> Result<(), String> {
+        let keys: Vec<String> = subjects
+            .iter()
+            .flat_map(|s| [s.failures_key(), s.strikes_key(), s.locked_key()])
+            .collect();
+        store.remove(tenant, &keys).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_credential_failures: 3,
+            max_user_failures: 5,
+            failure_window_seconds: 60,
+            base_lockout_seconds: 30,
+            max_lockout_seconds: 100,
+        }
+    }
+
+    #[test]
+    fn test_lockout_seconds_backs_off_exponentially() {
+        let policy = policy();
+        assert_eq!(policy.lockout_seconds(1), 30);
+        assert_eq!(policy.lockout_seconds(2), 60);
+        assert_eq!(policy.lockout_seconds(3), 100);
+        assert_eq!(policy.lockout_seconds(64), 100);
+    }
+
+    #[test]
+    fn test_fingerprint_hides_secret() {
+        let fingerprint = credential_fingerprint("pepper", "ABCDEF");
+        assert_eq!(fingerprint.len(), 32);
+        assert_eq!(fingerprint, credential_fingerprint("pepper", "abcdef"));
+        assert_ne!(fingerprint, credential_fingerprint("other", "abcdef"));
+    }
+
+    #[tokio::test]
+    async fn test_locks_after_threshold() {
+        let store = MemoryStore::new();
+        let policy = policy();
+        let subjects = vec![Subject::Credential("cred".to_string())];
+
+        assert_eq!(
+            policy.record_failure(&store, "t", &subjects).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            policy.record_failure(&store, "t", &subjects).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            policy.locked_for(&store, "t", &subjects).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            policy.record_failure(&store, "t", &subjects).await.unwrap(),
+            Some(30)
+        );
+        assert!(policy
+            .locked_for(&store, "t", &subjects)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Lockouts are per tenant
+        assert_eq!(
+            policy.locked_for(&store, "other", &subjects).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_second_lockout_doubles() {
+        let store = MemoryStore::new();
+        let policy = policy();
+        let subjects = vec![Subject::Credential("cred".to_string())];
+
+        for _ in 0..3 {
+            policy.record_failure(&store, "t", &subjects).await.unwrap();
+        }
+        let mut last = None;
+        for _ in 0..3 {
+            last = policy.record_failure(&store, "t", &subjects).await.unwrap();
+        }
+        assert_eq!(last, Some(60));
+    }
+
+    #[tokio::test]
+    async fn test_user_lockout_spans_credentials() {
+        let store = MemoryStore::new();
+        let policy = policy();
+        let user = Subject::User("alice".to_string());
+
+        // Two failures on each of three credentials: no credential reaches 3, the user reaches 5
+        let mut locked = None;
+        for cred in ["a", "b", "c"] {
+            let subjects = vec![Subject::Credential(cred.to_string()), user.clone()];
+            for _ in 0..2 {
+                locked = locked.or(policy.record_failure(&store, "t", &subjects).await.unwrap());
+            }
+        }
+        assert_eq!(locked, Some(30));
+
+        let fresh_credential = vec![Subject::Credential("d".to_string()), user.clone()];
+        assert!(policy
+            .locked_for(&store, "t", &fresh_credential)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_success_resets_failures_and_unlock_lifts_lockout() {
+        let store = MemoryStore::new();
+        let policy = policy();
+        let subjects = vec![Subject::Credential("cred".to_string())];
+
+        policy.record_failure(&store, "t", &subjects).await.unwrap();
+        policy.record_failure(&store, "t", &subjects).await.unwrap();
+        policy.record_success(&store, "t", &subjects).await.unwrap();
+        assert_eq!(
+            policy.record_failure(&store, "t", &subjects).await.unwrap(),
+            None
+        );
+
+        policy.record_failure(&store, "t", &subjects).await.unwrap();
+        policy.record_failure(&store, "t", &subjects).await.unwrap();
+        assert!(policy
+            .locked_for(&store, "t", &subjects)
+            .await
+            .unwrap()
+            .is_some());
+
+        policy.unlock(&store, "t", &subjects).await.unwrap();
+        assert_eq!(
+            policy.locked_for(&store, "t", &subjects).await.unwrap(),
+            None
+        );
+    }
+}
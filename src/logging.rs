@@ -0,0 +1,323 @@
+//! Structured logging with redaction.
+//!
+//! Log lines are JSON objects by default (`LOG_FORMAT=text` for a human-readable format)
+//! carrying the request ID and trace ID of the request being handled, plus any key-value
+//! fields given at the call site. Fields with sensitive names are replaced, and messages
+//! are scrubbed of URL credentials, API keys, bearer tokens and hex-encoded secrets, so
+//! a careless log call can't leak them.
+
+use crate::telemetry;
+use log::kv::{self, VisitSource};
+use log::Record;
+use serde::Serializer;
+use serde_json::Value;
+use std::env;
+use std::io::Write;
+
+/// Replacement for redacted values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Field names whose values are never logged
+const SENSITIVE_FIELDS: &[&str] = &[
+    "otp",
+    "code",
+    "secret",
+    "password",
+    "pin",
+    "token",
+    "api_key",
+    "authorization",
+    "cookie",
+    "pepper",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Text,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "text" => LogFormat::Text,
+            _ => LogFormat::Json,
+        }
+    }
+}
+
+/// Install the logger. The filter comes from `RUST_LOG`, then `LOG_LEVEL`, then `info`.
+pub fn init() {
+    let format = LogFormat::parse(&env::var("LOG_FORMAT").unwrap_or_default());
+    let filter = env::var("RUST_LOG")
+        .or_else(|_| env::var("LOG_LEVEL"))
+        .unwrap_or_else(|_| "info".to_string());
+
+    env_logger::Builder::new()
+        .parse_filters(&filter)
+        .format(move |buf, record| writeln!(buf, "{}", render(format, record, &timestamp())))
+        .init();
+}
+
+fn timestamp() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
+
+fn sensitive_field(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SENSITIVE_FIELDS
+        .iter()
+        .any(|name| key == *name || key.ends_with(&format!("_{}", name)))
+}
+
+/// Collects a record's key-value fields, redacting as it goes
+#[derive(Default)]
+struct Fields(Vec<(String, Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if sensitive_field(key.as_str()) {
+            Value::String(REDACTED.to_string())
+        } else {
+            match serde_json::to_value(&value) {
+                Ok(Value::String(s)) => Value::String(redact(&s)),
+                Ok(value) => value,
+                Err(_) => Value::String(redact(&value.to_string())),
+            }
+        };
+        self.0.push((key.as_str().to_string(), value));
+        Ok(())
+    }
+}
+
+/// Format a record as one line
+fn render(format: LogFormat, record: &Record, timestamp: &str) -> String {
+    let mut fields = Fields::default();
+    let (request_id, trace_id) = telemetry::current_ids();
+    if let Some(id) = request_id {
+        fields.0.push(("request_id".to_string(), Value::String(id)));
+    }
+    if let Some(id) = trace_id {
+        fields.0.push(("trace_id".to_string(), Value::String(id)));
+    }
+    let _ = record.key_values().visit(&mut fields);
+    let message = redact(&record.args().to_string());
+
+    match format {
+        LogFormat::Json => {
+            let mut line = serde_json::Map::new();
+            line.insert("timestamp".to_string(), timestamp.into());
+            line.insert(
+                "level".to_string(),
+                record.level().as_str().to_lowercase().into(),
+            );
+            line.insert("target".to_string(), record.target().into());
+            line.insert("message".to_string(), message.into());
+            for (key, value) in fields.0 {
+                line.entry(key).or_insert(value);
+            }
+            Value::Object(line).to_string()
+        }
+        LogFormat::Text => {
+            let mut line = format!(
+                "{} {:<5} {}: {}",
+                timestamp,
+                record.level(),
+                record.target(),
+                message
+            );
+            for (key, value) in fields.0 {
+                match value {
+                    Value::String(s) => line.push_str(&format!(" {}={}", key, s)),
+                    other => line.push_str(&format!(" {}={}", key, other)),
+                }
+            }
+            line
+        }
+    }
+}
+
+/// Scrub credentials and secrets from free text
+pub fn redact(text: &str) -> String {
+    redact_tokens(&redact_url(text))
+}
+
+/// Replace the user info of any URLs, e.g. `redis://:password@host` becomes
+/// `redis://[REDACTED]@host`
+pub fn redact_url(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(scheme_end) = rest.find("://") {
+        let (head, tail) = rest.split_at(scheme_end + 3);
+        redacted.push_str(head);
+        let authority_end = tail
+            .find(|c: char| c == '/' || c == '"' || c == '\'' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        let authority = &tail[..authority_end];
+        match authority.rfind('@') {
+            Some(at) => {
+                redacted.push_str(REDACTED);
+                redacted.push_str(&authority[at..]);
+            }
+            None => redacted.push_str(authority),
+        }
+        rest = &tail[authority_end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// API keys (`otpk_...`), JWTs and long hex strings such as OTP secrets
+fn sensitive_token(token: &str) -> bool {
+    token.starts_with("otpk_")
+        || (token.starts_with("eyJ") && token.len() >= 16)
+        || (token.len() >= 40 && token.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn redact_tokens(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut start = None;
+    // A trailing separator flushes the last token
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+    {
+        if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') {
+            start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = start.take() {
+            // Dots join JWT segments; one ending a sentence isn't part of the token
+            let token = text[start..i].trim_end_matches('.');
+            redacted.push_str(if sensitive_token(token) {
+                REDACTED
+            } else {
+                token
+            });
+            redacted.push_str(&text[start + token.len()..i]);
+        }
+        if i < text.len() {
+            redacted.push(c);
+        }
+    }
+    redacted
+}
+
+/// `serialize_with` for configuration secrets: shows whether one is set, never its value
+pub fn serialize_secret<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(if value.is_empty() { "" } else { REDACTED })
+}
+
+/// `serialize_with` for optional configuration secrets
+pub fn serialize_optional_secret<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(_) => serializer.serialize_str(REDACTED),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// `serialize_with` for URLs that may embed credentials
+pub fn serialize_url<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&redact_url(value))
+}
+
+/// `serialize_with` for optional URLs that may embed credentials
+pub fn serialize_optional_url<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(url) => serializer.serialize_str(&redact_url(url)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_line(format: LogFormat, fields: &[(&str, &str)], message: &str) -> String {
+        let fields: Vec<(&str, kv::Value)> = fields
+            .iter()
+            .map(|(k, v)| (*k, kv::Value::from(*v)))
+            .collect();
+        render(
+            format,
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(log::Level::Warn)
+                .target("otp::test")
+                .key_values(&fields)
+                .build(),
+            "2026-01-01T00:00:00Z",
+        )
+    }
+
+    #[test]
+    fn test_redacts_credentials_in_messages() {
+        assert_eq!(
+            redact("Connecting to redis://:hunter2@redis:6379/0 and rediss://cache"),
+            "Connecting to redis://[REDACTED]@redis:6379/0 and rediss://cache"
+        );
+        assert_eq!(
+            redact("key otpk_k1_0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"),
+            "key [REDACTED]"
+        );
+        assert_eq!(
+            redact("secret=3132333435363738393031323334353637383930, id 4bf92f3577b34da6."),
+            "secret=[REDACTED], id 4bf92f3577b34da6."
+        );
+        assert_eq!(
+            redact("Bearer eyJhbGciOiJFUzI1NiJ9.eyJzdWIiOiJhIn0.c2ln."),
+            "Bearer [REDACTED]."
+        );
+    }
+
+    #[test]
+    fn test_config_summary_redacts_secrets() {
+        let config = crate::config::Config {
+            redis_url: "redis://:hunter2@redis:6379".to_string(),
+            replay_key_pepper: "pepper".to_string(),
+            admin_api_key: Some("otpk_admin_secret".to_string()),
+            ..crate::config::Config::default()
+        };
+        let summary = serde_json::to_value(&config).unwrap();
+        assert_eq!(summary["redis_url"], "redis://[REDACTED]@redis:6379");
+        assert_eq!(summary["replay_key_pepper"], REDACTED);
+        assert_eq!(summary["admin_api_key"], REDACTED);
+        assert_eq!(summary["forward_auth_session_secret"], Value::Null);
+        assert_eq!(summary["otp_length"], 6);
+    }
+
+    #[test]
+    fn test_json_lines_carry_redacted_fields() {
+        let line = record_line(
+            LogFormat::Json,
+            &[
+                ("tenant", "acme"),
+                ("outcome", "reused"),
+                ("otp", "287082"),
+                ("api_key", "otpk_x_y"),
+            ],
+            "OTP verification",
+        );
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["message"], "OTP verification");
+        assert_eq!(line["tenant"], "acme");
+        assert_eq!(line["outcome"], "reused");
+        assert_eq!(line["otp"], REDACTED);
+        assert_eq!(line["api_key"], REDACTED);
+
+        let text = record_line(LogFormat::Text, &[("pin", "4711")], "Checked");
+        assert_eq!(
+            text,
+            "2026-01-01T00:00:00Z WARN  otp::test: Checked pin=[REDACTED]"
+        );
+    }
+}
@@ -1,75 +1,270 @@
-mod config;
-mod error;
-mod otp;
-mod server;
-mod storage;
-
-use actix_web::{App, HttpServer};
+use actix_web::{middleware, App, HttpServer};
+use audit::AuditLog;
 use config::Config;
 use dotenv::dotenv;
-use env_logger::Env;
+use events::Events;
+use jwt::JwtValidator;
+use otp::webhooks;
+use otp::{audit, config, events, jwt, logging, metrics, receipts, server, storage, telemetry};
+use receipts::ReceiptSigner;
+use server::access_policy::AccessPolicyHandle;
+use server::forward_auth::ForwardAuth;
+use server::grpc::{GrpcServer, OtpGrpc};
+use server::radius::RadiusServer;
+use server::tls::{self, ClientIdentities};
 use std::sync::Arc;
 use storage::OtpStorage;
+use webhooks::Webhooks;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Print a message to stderr for debugging
-    eprintln!("Starting OTP server application...");
-
     // Load environment variables
     dotenv().ok();
 
     // Initialize logger
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
-
-    // Print environment variables for debugging
-    eprintln!("Environment variables:");
-    for (key, value) in std::env::vars() {
-        eprintln!("  {}={}", key, value);
-    }
+    logging::init();
 
     // Load configuration
     let config = Arc::new(Config::from_env());
     let server_address = config.server_address();
 
-    eprintln!("Server address: {}", server_address);
-    eprintln!("Storage type: {:?}", config.storage_type);
+    // Effective configuration with secrets and URL credentials redacted
+    log::info!(config:serde = config.as_ref(); "Loaded configuration");
 
-    // Initialize OTP storage
-    eprintln!("Initializing OTP storage...");
-    let otp_storage = match OtpStorage::new(&config).await {
-        Ok(storage) => {
-            eprintln!("OTP storage initialized successfully");
-            storage
+    // `audit-verify [FILE]` checks the audit chain instead of starting the server
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("audit-verify") {
+        match audit::verify(&config, args.get(2).map(String::as_str)).await {
+            Ok((count, head)) => {
+                println!(
+                    "Audit chain intact: {} records, head {} at seq {}",
+                    count, head.hash, head.seq
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Audit chain verification failed: {}", e);
+                std::process::exit(1);
+            }
         }
+    }
+
+    if !config.api_auth_enabled && !config.binds_loopback_only() {
+        log::warn!(
+            "API authentication is disabled on a non-loopback address: anyone who can reach \
+             the server can generate secrets and codes and verify them. Remove \
+             API_AUTH_ENABLED=false unless a gateway in front of it authenticates callers"
+        );
+    }
+
+    // Initialize OTP storage; the connection is established in the background
+    let (otp_storage, storage_status) = match OtpStorage::new(&config) {
+        Ok(storage) => storage,
         Err(e) => {
-            eprintln!("Failed to initialize OTP storage: {}", e);
             log::error!("Failed to initialize OTP storage: {}", e);
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+            return Err(std::io::Error::other(e));
         }
     };
 
-    log::info!("Starting OTP server on {}", server_address);
-    eprintln!("Starting HTTP server on {}", server_address);
+    // Spans are exported over OTLP when an endpoint is configured
+    let tracer_provider = match telemetry::init(&config) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log::error!("Failed to set up tracing: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+
+    // Secrets kept in storage, such as API key signing secrets, are encrypted with this key
+    if let Err(e) = otp::secrets::SecretBox::from_config(&config) {
+        log::error!("Invalid secrets encryption key: {}", e);
+        return Err(std::io::Error::other(e));
+    }
+
+    let access_policy = match AccessPolicyHandle::from_config(&config) {
+        Ok(handle) => handle,
+        Err(e) => {
+            log::error!("Failed to load access policy: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+
+    // Bearer tokens are accepted alongside API keys when a JWKS is configured
+    let jwt_validator = match JwtValidator::from_config(&config) {
+        Ok(validator) => validator.map(Arc::new),
+        Err(e) => {
+            log::error!("Invalid bearer token configuration: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+
+    let tls_config = match tls::server_config(&config) {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            log::error!("Failed to load TLS configuration: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    let receipt_signer = match ReceiptSigner::from_config(&config) {
+        Ok(signer) => signer.map(actix_web::web::Data::new),
+        Err(e) => {
+            log::error!("Failed to load receipt signing keys: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    let forward_auth = match ForwardAuth::from_config(&config) {
+        Ok(forward_auth) => forward_auth.map(actix_web::web::Data::new),
+        Err(e) => {
+            log::error!("Failed to load forward-auth configuration: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    let client_identities = match ClientIdentities::from_config(&config) {
+        Ok(identities) => actix_web::web::Data::new(identities),
+        Err(e) => {
+            log::error!("Failed to load client certificate identities: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+
+    let audit_log = match AuditLog::from_config(&config).await {
+        Ok(audit_log) => audit_log,
+        Err(e) => {
+            log::error!("Failed to open audit log: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    // Webhook deliveries are queued in storage and sent by a background task
+    let webhooks = match Webhooks::from_config(&config, otp_storage.clone()) {
+        Ok(webhooks) => webhooks.map(Arc::new),
+        Err(e) => {
+            log::error!("Failed to load webhook endpoints: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    if let Some(webhooks) = &webhooks {
+        tokio::spawn(webhooks.clone().run());
+    }
+    let events = Events::new(audit_log, webhooks).map(actix_web::web::Data::new);
+
+    // The RADIUS listener shares storage and security events with the HTTP handlers
+    match RadiusServer::from_config(
+        config.clone(),
+        otp_storage.clone(),
+        events.clone().map(|events| events.into_inner()),
+    )
+    .await
+    {
+        Ok(Some(radius)) => {
+            tokio::spawn(radius.run());
+        }
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Failed to start RADIUS listener: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    }
+
+    // The gRPC listener shares storage, credentials and policies with the HTTP handlers
+    let grpc = OtpGrpc {
+        config: config.clone(),
+        storage: otp_storage.clone(),
+        access_policy: access_policy.clone(),
+        jwt_validator: jwt_validator.clone(),
+        client_identities: client_identities.clone().into_inner(),
+        receipts: receipt_signer.clone().map(|signer| signer.into_inner()),
+        events: events.clone().map(|events| events.into_inner()),
+    };
+    match GrpcServer::from_config(grpc, storage_status.clone(), tls_config.clone()).await {
+        Ok(Some(grpc)) => {
+            tokio::spawn(grpc.run());
+        }
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Failed to start gRPC listener: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    }
+
+    // Metrics get their own listener when METRICS_BIND is set, e.g. to keep them off the
+    // public port; otherwise they are served at /metrics alongside the API
+    let metrics_on_api = config.metrics_enabled && config.metrics_bind.is_none();
+    if let (true, Some(metrics_bind)) = (config.metrics_enabled, &config.metrics_bind) {
+        let status = storage_status.clone();
+        let metrics_server = HttpServer::new(move || {
+            App::new()
+                .app_data(actix_web::web::Data::new(status.clone()))
+                .route("/metrics", actix_web::web::get().to(metrics::export))
+        })
+        .workers(1)
+        .bind(metrics_bind)
+        .inspect_err(|e| log::error!("Failed to bind metrics listener: {}", e))?;
+        log::info!("Serving metrics on {}", metrics_bind);
+        actix_web::rt::spawn(metrics_server.run());
+    }
+
+    log::info!(
+        "Starting {} server on {}",
+        if tls_config.is_some() {
+            "HTTPS"
+        } else {
+            "HTTP"
+        },
+        server_address
+    );
 
     // Start HTTP server
-    eprintln!("Creating HTTP server...");
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(metrics::track))
+            // Outermost, so the request ID and trace cover everything else
+            .wrap(middleware::from_fn(telemetry::trace_request))
             .app_data(actix_web::web::Data::new(config.clone()))
             .app_data(actix_web::web::Data::new(otp_storage.clone()))
+            .app_data(actix_web::web::Data::new(storage_status.clone()))
+            .app_data(actix_web::web::Data::new(access_policy.clone()))
+            .app_data(actix_web::web::Data::new(jwt_validator.clone()))
+            .app_data(client_identities.clone())
+            .configure(|cfg| {
+                // Receipts are only offered when signing keys are configured
+                if let Some(signer) = &receipt_signer {
+                    cfg.app_data(signer.clone());
+                }
+                // Forward auth is only served when a session secret is configured
+                if let Some(forward_auth) = &forward_auth {
+                    cfg.app_data(forward_auth.clone());
+                }
+                // Security events are recorded when an audit log or webhooks are configured
+                if let Some(events) = &events {
+                    cfg.app_data(events.clone());
+                }
+            })
+            .configure(|cfg| {
+                if metrics_on_api {
+                    cfg.route("/metrics", actix_web::web::get().to(metrics::export));
+                }
+            })
             .configure(server::routes::configure_routes)
     })
-    .bind(server_address);
+    .on_connect(tls::on_connect);
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(server_address, tls_config),
+        None => server.bind(server_address),
+    };
 
-    match server {
-        Ok(server) => {
-            eprintln!("HTTP server created successfully, starting...");
-            server.run().await
-        }
+    let result = match server {
+        Ok(server) => server.run().await,
         Err(e) => {
-            eprintln!("Failed to bind HTTP server: {}", e);
+            log::error!("Failed to bind HTTP server: {}", e);
             Err(e)
         }
+    };
+
+    // Flush spans still waiting in the batch
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            log::warn!("Failed to flush traces: {}", e);
+        }
     }
+    result
 }
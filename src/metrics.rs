@@ -0,0 +1,363 @@
+use crate::error::AppError;
+use crate::storage::{StorageState, StorageStatus};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// Latency buckets for storage calls, which are mostly sub-millisecond round trips
+const STORAGE_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Kind of code an operation works on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtpType {
+    Totp,
+    Hotp,
+}
+
+impl OtpType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OtpType::Totp => "totp",
+            OtpType::Hotp => "hotp",
+        }
+    }
+}
+
+/// How a verification ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Valid,
+    Invalid,
+    /// The code was valid once but has already been used
+    Reused,
+    /// Refused by a lockout or credential-stuffing block without looking at the code
+    Locked,
+    Error,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Valid => "valid",
+            Outcome::Invalid => "invalid",
+            Outcome::Reused => "reused",
+            Outcome::Locked => "locked",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// Process-wide metrics, exported in the Prometheus text format
+pub struct Metrics {
+    registry: Registry,
+    operations: IntCounterVec,
+    http_duration: HistogramVec,
+    storage_duration: HistogramVec,
+    storage_state: IntGaugeVec,
+    circuit_open: IntGauge,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// The metrics every part of the server records into
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let operations = IntCounterVec::new(
+            Opts::new(
+                "otp_operations_total",
+                "Generate and verify calls by code type and outcome",
+            ),
+            &["operation", "type", "outcome"],
+        )
+        .expect("valid metric");
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "otp_http_request_duration_seconds",
+                "Time to answer HTTP requests by route",
+            ),
+            &["handler", "method", "status"],
+        )
+        .expect("valid metric");
+        let storage_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "otp_storage_operation_duration_seconds",
+                "Time taken by storage operations",
+            )
+            .buckets(STORAGE_BUCKETS.to_vec()),
+            &["operation", "result"],
+        )
+        .expect("valid metric");
+        let storage_state = IntGaugeVec::new(
+            Opts::new(
+                "otp_storage_state",
+                "Connection state of the storage backend, 1 for the current state",
+            ),
+            &["state"],
+        )
+        .expect("valid metric");
+        let circuit_open = IntGauge::new(
+            "otp_storage_circuit_open",
+            "Whether the storage circuit breaker is rejecting calls",
+        )
+        .expect("valid metric");
+        let build_info = IntGauge::with_opts(
+            Opts::new("otp_build_info", "Build information, always 1")
+                .const_label("version", env!("CARGO_PKG_VERSION")),
+        )
+        .expect("valid metric");
+        build_info.set(1);
+
+        for collector in [
+            Box::new(operations.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http_duration.clone()),
+            Box::new(storage_duration.clone()),
+            Box::new(storage_state.clone()),
+            Box::new(circuit_open.clone()),
+            Box::new(build_info),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Self {
+            registry,
+            operations,
+            http_duration,
+            storage_duration,
+            storage_state,
+            circuit_open,
+        }
+    }
+
+    pub fn generated(&self, otp_type: OtpType, ok: bool) {
+        let outcome = if ok { "generated" } else { "error" };
+        self.operations
+            .with_label_values(&["generate", otp_type.as_str(), outcome])
+            .inc();
+    }
+
+    /// Count a verification by its result, classifying errors as locked or error
+    pub fn verified(&self, otp_type: OtpType, result: &Result<Outcome, AppError>) -> Outcome {
+        let outcome = match result {
+            Ok(outcome) => *outcome,
+            Err(AppError::Locked { .. } | AppError::TooManyRequests { .. }) => Outcome::Locked,
+            Err(_) => Outcome::Error,
+        };
+        self.operations
+            .with_label_values(&["verify", otp_type.as_str(), outcome.as_str()])
+            .inc();
+        outcome
+    }
+
+    pub fn storage_call(&self, operation: &str, ok: bool, started: Instant) {
+        self.storage_duration
+            .with_label_values(&[operation, if ok { "ok" } else { "error" }])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    pub fn set_circuit_open(&self, open: bool) {
+        self.circuit_open.set(open as i64);
+    }
+
+    /// Render every metric, with the storage state as of now
+    pub fn render(&self, status: Option<&StorageStatus>) -> String {
+        if let Some(status) = status {
+            let current = status.state();
+            for state in [
+                StorageState::Connecting,
+                StorageState::Ready,
+                StorageState::Failed,
+            ] {
+                self.storage_state
+                    .with_label_values(&[state_label(state)])
+                    .set((state == current) as i64);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            log::error!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+fn state_label(state: StorageState) -> &'static str {
+    match state {
+        StorageState::Connecting => "connecting",
+        StorageState::Ready => "ready",
+        StorageState::Failed => "failed",
+    }
+}
+
+/// Label for a request method; clients can send any token as a method, so anything
+/// outside the standard ones shares a series
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::PATCH => "PATCH",
+        Method::TRACE => "TRACE",
+        _ => "other",
+    }
+}
+
+/// Time every request, labelled with the route pattern so IDs in paths don't multiply series
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = method_label(req.method());
+    let result = next.call(req).await;
+
+    let (handler, status) = match &result {
+        Ok(res) => (
+            res.request()
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".to_string()),
+            res.status(),
+        ),
+        Err(e) => ("unmatched".to_string(), e.as_response_error().status_code()),
+    };
+    metrics()
+        .http_duration
+        .with_label_values(&[handler.as_str(), method, status.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+/// `GET /metrics` in the Prometheus text format
+pub async fn export(status: Option<web::Data<StorageStatus>>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(TextEncoder::new().format_type())
+        .body(metrics().render(status.as_ref().map(|s| s.get_ref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::otp::totp::Totp;
+    use crate::server::routes::configure_routes;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::OtpStore;
+    use actix_web::{middleware, test, App};
+    use std::sync::Arc;
+
+    /// Current value of a sample in rendered metrics, 0 if it isn't there
+    fn sample(rendered: &str, series: &str) -> f64 {
+        rendered
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.trim().parse().ok())
+            .unwrap_or(0.0)
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_count_outcomes_and_time_requests() {
+        let config = Arc::new(Config {
+            otp_length: 6,
+            otp_expiry_seconds: 30,
+            ..Config::default()
+        });
+        let storage: Arc<dyn OtpStore> = Arc::new(MemoryStore::new());
+        let status = StorageStatus::default();
+        status.set_ready();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(track))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(storage))
+                .app_data(web::Data::new(status))
+                .route("/metrics", web::get().to(export))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let valid = r#"otp_operations_total{operation="verify",outcome="valid",type="totp"}"#;
+        let reused = r#"otp_operations_total{operation="verify",outcome="reused",type="totp"}"#;
+        let before = {
+            let req = test::TestRequest::get().uri("/metrics").to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success());
+            String::from_utf8(test::read_body(res).await.to_vec()).unwrap()
+        };
+
+        let secret = "3132333435363738393031323334353637383930";
+        let otp = Totp::new(hex::decode(secret).unwrap(), 6, 30)
+            .generate()
+            .unwrap();
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/api/otp/verify")
+                .set_json(serde_json::json!({ "secret": secret, "otp": otp }))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+
+        // Other tests share the process-wide counters, so only look at increases
+        let after = {
+            let req = test::TestRequest::get().uri("/metrics").to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success());
+            String::from_utf8(test::read_body(res).await.to_vec()).unwrap()
+        };
+        assert!(sample(&after, valid) >= sample(&before, valid) + 1.0);
+        assert!(sample(&after, reused) >= sample(&before, reused) + 1.0);
+        assert!(after.contains(
+            r#"otp_http_request_duration_seconds_count{handler="/api/otp/verify",method="POST",status="200"}"#
+        ));
+        assert!(after.contains(r#"otp_storage_state{state="ready"} 1"#));
+        assert!(after.contains(r#"otp_storage_state{state="failed"} 0"#));
+        assert!(after.contains(&format!(
+            r#"otp_build_info{{version="{}"}} 1"#,
+            env!("CARGO_PKG_VERSION")
+        )));
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_methods_share_a_label() {
+        assert_eq!(method_label(&Method::PATCH), "PATCH");
+        assert_eq!(method_label(&Method::from_bytes(b"BREW").unwrap()), "other");
+
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(track))
+                .route("/metrics", web::get().to(export))
+                .default_service(web::to(HttpResponse::NotFound)),
+        )
+        .await;
+        let req = test::TestRequest::default()
+            .method(Method::from_bytes(b"BREW").unwrap())
+            .uri("/teapot")
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let rendered =
+            String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        assert!(rendered.contains(r#"method="other""#));
+        assert!(!rendered.contains("BREW"));
+    }
+}
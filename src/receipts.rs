@@ -0,0 +1,317 @@
+use crate::config::Config;
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+    PublicKeyUse,
+};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::PrivateKeyDer;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Authentication method reference for one-time passwords (RFC 8176)
+const AMR_OTP: &str = "otp";
+
+/// How the code in a receipt was checked
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Totp,
+    Hotp,
+}
+
+/// Claims of a verification receipt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptClaims {
+    pub iss: String,
+    /// User the code was verified for, when the caller named one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+    pub tenant: String,
+    /// Client-supplied credential ID, or the fingerprint of the secret
+    pub credential_id: String,
+    pub method: Method,
+    pub amr: Vec<String>,
+    pub acr: String,
+}
+
+/// A successful verification to issue a receipt for
+pub struct Verification<'a> {
+    pub tenant: &'a str,
+    pub user_id: Option<&'a str>,
+    pub credential_id: &'a str,
+    pub method: Method,
+}
+
+struct SigningKey {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    jwk: Jwk,
+}
+
+impl SigningKey {
+    /// Load a PKCS#8 Ed25519 or P-256 private key
+    fn load(path: &Path) -> Result<Self, String> {
+        let pkcs8 = match PrivateKeyDer::from_pem_file(path) {
+            Ok(PrivateKeyDer::Pkcs8(key)) => key,
+            Ok(_) => return Err(format!("{} must be a PKCS#8 private key", path.display())),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read private key from {}: {}",
+                    path.display(),
+                    e
+                ))
+            }
+        };
+        let der = pkcs8.secret_pkcs8_der();
+
+        if let Ok(pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+            let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: BASE64URL_NOPAD.encode(pair.public_key().as_ref()),
+            });
+            return Ok(Self::new(
+                Algorithm::EdDSA,
+                KeyAlgorithm::EdDSA,
+                EncodingKey::from_ed_der(der),
+                params,
+            ));
+        }
+
+        let pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der, &SystemRandom::new())
+                .map_err(|_| format!("{} is neither an Ed25519 nor a P-256 key", path.display()))?;
+        // Uncompressed point: 0x04 || x || y
+        let point = pair.public_key().as_ref();
+        let params = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+            key_type: EllipticCurveKeyType::EC,
+            curve: EllipticCurve::P256,
+            x: BASE64URL_NOPAD.encode(&point[1..33]),
+            y: BASE64URL_NOPAD.encode(&point[33..]),
+        });
+        Ok(Self::new(
+            Algorithm::ES256,
+            KeyAlgorithm::ES256,
+            EncodingKey::from_ec_der(der),
+            params,
+        ))
+    }
+
+    fn new(
+        algorithm: Algorithm,
+        key_algorithm: KeyAlgorithm,
+        encoding: EncodingKey,
+        params: AlgorithmParameters,
+    ) -> Self {
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm),
+                key_id: Some(thumbprint(&params)),
+                ..CommonParameters::default()
+            },
+            algorithm: params,
+        };
+        Self {
+            algorithm,
+            encoding,
+            jwk,
+        }
+    }
+
+    fn kid(&self) -> Option<String> {
+        self.jwk.common.key_id.clone()
+    }
+}
+
+/// JWK thumbprint (RFC 7638), used as the key ID so it's stable across restarts
+fn thumbprint(params: &AlgorithmParameters) -> String {
+    // Required members only, in lexicographic order and without whitespace
+    let canonical = match params {
+        AlgorithmParameters::OctetKeyPair(p) => {
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, p.x)
+        }
+        AlgorithmParameters::EllipticCurve(p) => {
+            format!(
+                r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+                p.x, p.y
+            )
+        }
+        _ => unreachable!("receipt keys are Ed25519 or P-256"),
+    };
+    BASE64URL_NOPAD.encode(&Sha256::digest(canonical.as_bytes()))
+}
+
+/// Signs short-lived JWS receipts for successful verifications.
+///
+/// The first configured key signs; the others are only published, so a new key can be
+/// published before it signs and a retired one stays verifiable until receipts expire.
+pub struct ReceiptSigner {
+    keys: Vec<SigningKey>,
+    issuer: String,
+    audience: Option<String>,
+    acr: String,
+    ttl_seconds: u64,
+}
+
+impl ReceiptSigner {
+    /// `None` when no signing keys are configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        if config.receipt_signing_key_files.is_empty() {
+            return Ok(None);
+        }
+        let keys = config
+            .receipt_signing_key_files
+            .iter()
+            .map(|path| SigningKey::load(Path::new(path)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(Self {
+            keys,
+            issuer: config.receipt_issuer.clone(),
+            audience: config.receipt_audience.clone(),
+            acr: config.receipt_acr.clone(),
+            ttl_seconds: config.receipt_ttl_seconds,
+        }))
+    }
+
+    /// Public keys receipts can be checked against
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().map(|key| key.jwk.clone()).collect(),
+        }
+    }
+
+    pub fn sign(&self, verification: &Verification<'_>, now: u64) -> Result<String, String> {
+        let key = &self.keys[0];
+        let claims = ReceiptClaims {
+            iss: self.issuer.clone(),
+            sub: verification.user_id.map(str::to_string),
+            aud: self.audience.clone(),
+            iat: now,
+            exp: now + self.ttl_seconds,
+            jti: hex::encode(rand::random::<[u8; 16]>()),
+            tenant: verification.tenant.to_string(),
+            credential_id: verification.credential_id.to_string(),
+            method: verification.method,
+            amr: vec![AMR_OTP.to_string()],
+            acr: self.acr.clone(),
+        };
+        let mut header = Header::new(key.algorithm);
+        header.kid = key.kid();
+
+        jsonwebtoken::encode(&header, &claims, &key.encoding)
+            .map_err(|e| format!("Failed to sign receipt: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{DecodingKey, Validation};
+
+    /// Write a freshly generated PKCS#8 key and return its path
+    fn key_file(name: &str, alg: &'static rcgen::SignatureAlgorithm) -> String {
+        let key = rcgen::KeyPair::generate_for(alg).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("otp-receipt-{}-{}.pem", name, std::process::id()));
+        std::fs::write(&path, key.serialize_pem()).unwrap();
+        path.display().to_string()
+    }
+
+    fn decode(signer: &ReceiptSigner, receipt: &str) -> ReceiptClaims {
+        let header = jsonwebtoken::decode_header(receipt).unwrap();
+        let jwk = signer.jwks().find(&header.kid.unwrap()).cloned().unwrap();
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&["otp-server"]);
+        validation.set_audience(&["billing"]);
+        jsonwebtoken::decode(receipt, &DecodingKey::from_jwk(&jwk).unwrap(), &validation)
+            .unwrap()
+            .claims
+    }
+
+    fn verification() -> Verification<'static> {
+        Verification {
+            tenant: "acme",
+            user_id: Some("alice"),
+            credential_id: "phone",
+            method: Method::Totp,
+        }
+    }
+
+    #[test]
+    fn test_receipts_verify_against_published_keys() {
+        for (name, alg) in [
+            ("ed25519", &rcgen::PKCS_ED25519),
+            ("p256", &rcgen::PKCS_ECDSA_P256_SHA256),
+        ] {
+            let path = key_file(name, alg);
+            let config = Config {
+                receipt_signing_key_files: vec![path.clone()],
+                receipt_audience: Some("billing".to_string()),
+                ..Config::default()
+            };
+            let signer = ReceiptSigner::from_config(&config).unwrap().unwrap();
+
+            let now = jsonwebtoken::get_current_timestamp();
+            let claims = decode(&signer, &signer.sign(&verification(), now).unwrap());
+            assert_eq!(claims.sub.as_deref(), Some("alice"));
+            assert_eq!(claims.tenant, "acme");
+            assert_eq!(claims.credential_id, "phone");
+            assert_eq!(claims.method, Method::Totp);
+            assert_eq!(claims.amr, vec!["otp"]);
+            assert_eq!(claims.exp, now + config.receipt_ttl_seconds);
+
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn test_rotation_publishes_every_key_and_signs_with_the_first() {
+        let (old, new) = (
+            key_file("old", &rcgen::PKCS_ED25519),
+            key_file("new", &rcgen::PKCS_ECDSA_P256_SHA256),
+        );
+        let signer = |files: Vec<String>| {
+            ReceiptSigner::from_config(&Config {
+                receipt_signing_key_files: files,
+                ..Config::default()
+            })
+            .unwrap()
+            .unwrap()
+        };
+        let before = signer(vec![old.clone(), new.clone()]);
+        let after = signer(vec![new.clone(), old.clone()]);
+
+        // Key IDs are thumbprints, so the same key keeps its ID across restarts
+        let kids = |s: &ReceiptSigner| -> Vec<String> {
+            s.jwks()
+                .keys
+                .iter()
+                .filter_map(|k| k.common.key_id.clone())
+                .collect()
+        };
+        assert_eq!(kids(&before).len(), 2);
+        assert_eq!(kids(&before)[0], kids(&after)[1]);
+        assert_eq!(kids(&before)[1], kids(&after)[0]);
+
+        let receipt = after.sign(&verification(), 0).unwrap();
+        let header = jsonwebtoken::decode_header(&receipt).unwrap();
+        assert_eq!(header.alg, Algorithm::ES256);
+        assert_eq!(header.kid, Some(kids(&after)[0].clone()));
+
+        std::fs::remove_file(old).ok();
+        std::fs::remove_file(new).ok();
+    }
+}
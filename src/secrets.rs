@@ -0,0 +1,204 @@
+//! Encryption of secrets kept in storage, such as API key signing secrets.
+//!
+//! Values are sealed with XChaCha20-Poly1305 under `SECRETS_KEY` and bound to the record
+//! they belong to, so a sealed value copied into another record doesn't open. During a
+//! key rotation `SECRETS_KEY_PREVIOUS` still opens values sealed under the old key;
+//! callers reseal them under the current key when they read them.
+
+use crate::config::Config;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+/// Format version of sealed values
+const VERSION: &str = "v1";
+const NONCE_LEN: usize = 24;
+
+/// Keys that were published with the example configuration and protect nothing
+const PUBLISHED_KEYS: &[&str] =
+    &["2f5d7e81fb47953d0471092c878d830c7b192fbc930a3c6b8ce12b0875513516"];
+
+struct Key {
+    /// Short fingerprint of the key, stored with each value to pick the key that opens it
+    id: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl Key {
+    fn new(name: &str, hex_key: &str) -> Result<Self, String> {
+        let bytes =
+            hex::decode(hex_key.trim()).map_err(|_| format!("{} must be hex-encoded", name))?;
+        if bytes.len() != 32 {
+            return Err(format!("{} must be 32 bytes (64 hex characters)", name));
+        }
+        if PUBLISHED_KEYS.contains(&hex::encode(&bytes).as_str()) {
+            return Err(format!(
+                "{} is a published example key; generate your own with `openssl rand -hex 32`",
+                name
+            ));
+        }
+        Ok(Self {
+            id: hex::encode(&Sha256::digest(&bytes)[..4]),
+            cipher: XChaCha20Poly1305::new_from_slice(&bytes).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+/// A value opened by [`SecretBox::open`]
+pub struct Opened {
+    pub plaintext: Vec<u8>,
+    /// Sealed under the previous key, so it should be resealed
+    pub stale: bool,
+}
+
+/// Seals and opens secrets stored at rest
+pub struct SecretBox {
+    current: Key,
+    previous: Option<Key>,
+}
+
+impl SecretBox {
+    /// Keys from `SECRETS_KEY` and `SECRETS_KEY_PREVIOUS`; the current key is required
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let current = config
+            .secrets_key
+            .as_deref()
+            .ok_or_else(|| "SECRETS_KEY is not set".to_string())?;
+        Ok(Self {
+            current: Key::new("SECRETS_KEY", current)?,
+            previous: config
+                .secrets_key_previous
+                .as_deref()
+                .map(|key| Key::new("SECRETS_KEY_PREVIOUS", key))
+                .transpose()?,
+        })
+    }
+
+    /// Seal a value for the record identified by `context`
+    pub fn seal(&self, context: &str, plaintext: &[u8]) -> String {
+        let nonce: [u8; NONCE_LEN] = rand::rngs::ThreadRng::default().random();
+        let ciphertext = self
+            .current
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .expect("encryption of in-memory values doesn't fail");
+        format!(
+            "{}:{}:{}{}",
+            VERSION,
+            self.current.id,
+            hex::encode(nonce),
+            hex::encode(ciphertext)
+        )
+    }
+
+    /// Open a value sealed for the record identified by `context`
+    pub fn open(&self, context: &str, sealed: &str) -> Result<Opened, String> {
+        let mut parts = sealed.splitn(3, ':');
+        let (version, key_id, data) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(version), Some(key_id), Some(data)) => (version, key_id, data),
+            _ => return Err(format!("Malformed sealed value for {}", context)),
+        };
+        if version != VERSION {
+            return Err(format!("Unsupported sealed value version {}", version));
+        }
+        let (key, stale) = if key_id == self.current.id {
+            (&self.current, false)
+        } else {
+            match &self.previous {
+                Some(previous) if key_id == previous.id => (previous, true),
+                _ => return Err(format!("No configured key opens the value for {}", context)),
+            }
+        };
+
+        let data =
+            hex::decode(data).map_err(|_| format!("Malformed sealed value for {}", context))?;
+        if data.len() < NONCE_LEN {
+            return Err(format!("Malformed sealed value for {}", context));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = key
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("Sealed value for {} failed authentication", context))?;
+        Ok(Opened { plaintext, stale })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    pub const OTHER_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    pub fn secrets(current: &str, previous: Option<&str>) -> SecretBox {
+        SecretBox::from_config(&Config {
+            secrets_key: Some(current.to_string()),
+            secrets_key_previous: previous.map(str::to_string),
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let secrets = secrets(KEY, None);
+        let sealed = secrets.seal("enrollment:acme:alice", b"seed");
+        assert!(!sealed.contains(&hex::encode(b"seed")));
+        assert_ne!(sealed, secrets.seal("enrollment:acme:alice", b"seed"));
+
+        let opened = secrets.open("enrollment:acme:alice", &sealed).unwrap();
+        assert_eq!(opened.plaintext, b"seed");
+        assert!(!opened.stale);
+        // Bound to the record it was sealed for
+        assert!(secrets.open("enrollment:acme:bob", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_published_keys_are_refused() {
+        let config = |key: &str| Config {
+            secrets_key: Some(key.to_string()),
+            ..Config::default()
+        };
+        let published = PUBLISHED_KEYS[0];
+        let error = SecretBox::from_config(&config(published)).err().unwrap();
+        assert!(error.contains("published"), "{}", error);
+        assert!(SecretBox::from_config(&config(&published.to_uppercase())).is_err());
+        assert!(SecretBox::from_config(&config(KEY)).is_ok());
+    }
+
+    #[test]
+    fn test_previous_key_opens_stale_values() {
+        let sealed = secrets(KEY, None).seal("apikey:1", b"secret");
+
+        let rotated = secrets(OTHER_KEY, Some(KEY));
+        let opened = rotated.open("apikey:1", &sealed).unwrap();
+        assert_eq!(opened.plaintext, b"secret");
+        assert!(opened.stale);
+
+        assert!(secrets(OTHER_KEY, None).open("apikey:1", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_key_is_required_and_checked() {
+        assert!(SecretBox::from_config(&Config::default()).is_err());
+        let config = Config {
+            secrets_key: Some("abcd".to_string()),
+            ..Config::default()
+        };
+        assert!(SecretBox::from_config(&config).is_err());
+    }
+}
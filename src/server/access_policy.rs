@@ -0,0 +1,403 @@
+use crate::api_keys::ApiClient;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::server::client_ip::client_ip;
+use crate::server::tenant::{self, TENANT_HEADER};
+use crate::server::versioning;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// CIDR allow/deny rule for requests under a path scope, optionally for one tenant only
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessRule {
+    /// Path prefix the rule applies to, matched on segment boundaries
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    /// Tenant the rule applies to; all tenants when absent
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// When non-empty, only these networks are accepted
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub allow: Vec<IpNet>,
+    /// Networks that are always refused
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub deny: Vec<IpNet>,
+}
+
+fn default_scope() -> String {
+    "/".to_string()
+}
+
+/// Accept CIDRs and bare addresses, rejecting anything else so typos don't silently open access
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|entry| {
+            let entry = entry.trim();
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| serde::de::Error::custom(format!("invalid network '{}'", entry)))
+        })
+        .collect()
+}
+
+impl AccessRule {
+    fn applies_to(&self, path: &str, tenant: &str) -> bool {
+        let scope = self.scope.trim_end_matches('/');
+        let in_scope = path == scope || path.starts_with(&format!("{}/", scope));
+        in_scope && self.tenant.as_deref().is_none_or(|t| t == tenant)
+    }
+
+    fn permits(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|net| net.contains(&ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+            }
+            // An unknown client can't be shown to be on an allow list
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+/// IP access policy; a request must satisfy every rule that applies to it
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AccessPolicy {
+    #[serde(default)]
+    pub rules: Vec<AccessRule>,
+}
+
+impl AccessPolicy {
+    /// Read a policy from a JSON file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid access policy {}: {}", path.display(), e))
+    }
+
+    pub fn permits(&self, path: &str, tenant: &str, ip: Option<IpAddr>) -> bool {
+        self.permits_client(path, ip) && self.permits_tenant(path, tenant, ip)
+    }
+
+    /// Check the rules that apply to every tenant, which need no authentication
+    pub fn permits_client(&self, path: &str, ip: Option<IpAddr>) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| rule.tenant.is_none() && rule.applies_to(path, ""))
+            .all(|rule| rule.permits(ip))
+    }
+
+    /// Check the rules for one tenant; callers resolve the tenant after authentication,
+    /// as a client bound to a tenant needn't name it
+    pub fn permits_tenant(&self, path: &str, tenant: &str, ip: Option<IpAddr>) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| rule.tenant.is_some() && rule.applies_to(path, tenant))
+            .all(|rule| rule.permits(ip))
+    }
+}
+
+/// Shared, hot-reloadable access policy
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicyHandle {
+    current: Arc<RwLock<Arc<AccessPolicy>>>,
+}
+
+impl AccessPolicyHandle {
+    pub fn new(policy: AccessPolicy) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(policy))),
+        }
+    }
+
+    /// Load the configured policy file, if any, and keep it up to date in the background
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let path = match &config.access_policy_file {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(Self::default()),
+        };
+
+        let handle = Self::new(AccessPolicy::load(&path)?);
+        log::info!("Loaded access policy from {}", path.display());
+        if config.access_policy_reload_seconds > 0 {
+            handle.spawn_reloader(
+                path,
+                Duration::from_secs(config.access_policy_reload_seconds),
+            );
+        }
+        Ok(handle)
+    }
+
+    pub fn current(&self) -> Arc<AccessPolicy> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn replace(&self, policy: AccessPolicy) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(policy);
+    }
+
+    /// Poll the policy file and swap in new versions; invalid files keep the previous policy
+    fn spawn_reloader(&self, path: PathBuf, interval: Duration) {
+        let handle = self.clone();
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last_modified: Option<SystemTime> = modified(&path);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+
+                match AccessPolicy::load(&path) {
+                    Ok(policy) => {
+                        handle.replace(policy);
+                        log::info!("Reloaded access policy from {}", path.display());
+                    }
+                    Err(e) => log::error!("Keeping previous access policy: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Refuse a request the policy doesn't permit
+fn refuse(req: ServiceRequest, ip: Option<IpAddr>, tenant: Option<&str>) -> ServiceResponse {
+    log::warn!(
+        "Access policy refused {} {} from {:?} for tenant {}",
+        req.method(),
+        req.path(),
+        ip,
+        tenant.unwrap_or("*")
+    );
+    let error = AppError::Forbidden("Access denied for this client address".to_string());
+    req.into_response(error.error_response())
+}
+
+/// Current policy and configuration, unless there are no rules to enforce
+fn policy(req: &ServiceRequest) -> Option<(Arc<AccessPolicy>, Arc<Config>)> {
+    let handle = req.app_data::<web::Data<AccessPolicyHandle>>()?;
+    let config = req.app_data::<web::Data<Arc<Config>>>()?;
+    let policy = handle.current();
+    (!policy.rules.is_empty()).then(|| (policy, config.as_ref().clone()))
+}
+
+/// Access-policy middleware: refuse requests whose client IP the rules for all tenants
+/// don't permit. Runs before authentication, so refused clients cost nothing more.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let (policy, config) = match policy(&req) {
+        Some(found) => found,
+        None => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    let ip = client_ip(req.request(), &config.trusted_proxies);
+    let path = versioning::unversioned(versioning::routed_path(&req)).into_owned();
+    if policy.permits_client(&path, ip) {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    }
+    Ok(refuse(req, ip, None))
+}
+
+/// Access-policy middleware for tenant rules: runs after authentication and checks the
+/// tenant the request acts on, which may come from the client's key or token rather
+/// than the tenant header
+pub async fn enforce_tenant(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let (policy, config) = match policy(&req) {
+        Some(found) => found,
+        None => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    // A tenant that doesn't resolve is refused by the handler with the same error
+    let requested = req
+        .headers()
+        .get(TENANT_HEADER)
+        .map(|v| v.to_str().map(str::trim).unwrap_or_default().to_string());
+    let resolved = tenant::resolve(
+        requested.as_deref(),
+        req.extensions().get::<ApiClient>(),
+        &config.default_tenant,
+    );
+    let tenant = match resolved {
+        Ok(tenant) => tenant,
+        Err(_) => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    let ip = client_ip(req.request(), &config.trusted_proxies);
+    let path = versioning::unversioned(versioning::routed_path(&req)).into_owned();
+    if policy.permits_tenant(&path, tenant.as_str(), ip) {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    }
+    Ok(refuse(req, ip, Some(tenant.as_str())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::SecretBox;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::OtpStore;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+
+    fn policy(json: serde_json::Value) -> AccessPolicy {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_allow_and_deny_by_scope_and_tenant() {
+        let policy = policy(serde_json::json!({
+            "rules": [
+                { "scope": "/api/otp", "allow": ["10.0.0.0/8"], "deny": ["10.9.0.0/16"] },
+                { "scope": "/api", "tenant": "acme", "deny": ["198.51.100.7"] }
+            ]
+        }));
+
+        assert!(policy.permits("/api/otp/verify", "default", ip("10.1.2.3")));
+        assert!(!policy.permits("/api/otp/verify", "default", ip("10.9.0.1")));
+        assert!(!policy.permits("/api/otp/verify", "default", ip("203.0.113.5")));
+        assert!(!policy.permits("/api/otp/verify", "default", None));
+        // Scopes match whole segments
+        assert!(policy.permits("/api/otpx", "default", ip("203.0.113.5")));
+        assert!(policy.permits("/api/hotp/verify", "default", ip("203.0.113.5")));
+
+        // Tenant rules only apply to their tenant
+        assert!(!policy.permits("/api/hotp/verify", "acme", ip("198.51.100.7")));
+        assert!(policy.permits("/api/hotp/verify", "globex", ip("198.51.100.7")));
+    }
+
+    #[test]
+    fn test_invalid_network_is_rejected() {
+        let result: Result<AccessPolicy, _> =
+            serde_json::from_value(serde_json::json!({ "rules": [{ "allow": ["10.0.0/8x"] }] }));
+        assert!(result.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_uses_forwarded_client_ip_and_reloads() {
+        let config = Config {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Config::default()
+        };
+        let handle = AccessPolicyHandle::new(policy(serde_json::json!({
+            "rules": [{ "scope": "/api/hotp", "allow": ["192.0.2.0/24"] }]
+        })));
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>
+                ))
+                .app_data(web::Data::new(handle.clone()))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let generate = |client: &str| {
+            actix_test::TestRequest::post()
+                .uri("/api/hotp/generate")
+                .peer_addr("10.0.0.2:4000".parse().unwrap())
+                .insert_header(("X-Forwarded-For", client))
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "counter": 1
+                }))
+                .to_request()
+        };
+
+        let resp = actix_test::call_service(&app, generate("192.0.2.10")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = actix_test::call_service(&app, generate("203.0.113.5")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        // Encoded paths are checked as the route they reach
+        let req = actix_test::TestRequest::post()
+            .uri("/api/v2/%68otp/generate")
+            .peer_addr("10.0.0.2:4000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.5"))
+            .to_request();
+        let resp = actix_test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // A reloaded policy takes effect on the next request
+        handle.replace(AccessPolicy::default());
+        let resp = actix_test::call_service(&app, generate("203.0.113.5")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_tenant_rules_apply_to_the_clients_bound_tenant() {
+        let config = Config {
+            api_auth_enabled: true,
+            admin_api_key: Some("admin-key".to_string()),
+            secrets_key: Some(crate::secrets::tests::KEY.to_string()),
+            ..Config::default()
+        };
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let issued = crate::api_keys::create(
+            storage.as_ref(),
+            &SecretBox::from_config(&config).unwrap(),
+            Some("acme".to_string()),
+            vec![crate::api_keys::Scope::Generate],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+        let handle = AccessPolicyHandle::new(policy(serde_json::json!({
+            "rules": [{ "scope": "/api", "tenant": "acme", "allow": ["192.0.2.0/24"] }]
+        })));
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(storage))
+                .app_data(web::Data::new(handle))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        // No tenant header: the key's tenant decides which rules apply
+        let generate = |peer: &str| {
+            actix_test::TestRequest::post()
+                .uri("/api/v2/secret")
+                .peer_addr(format!("{}:4000", peer).parse().unwrap())
+                .insert_header((
+                    crate::server::rate_limit::API_KEY_HEADER,
+                    issued.api_key.as_str(),
+                ))
+                .to_request()
+        };
+        let resp = actix_test::call_service(&app, generate("192.0.2.10")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = actix_test::call_service(&app, generate("203.0.113.5")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
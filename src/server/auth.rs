@@ -0,0 +1,655 @@
+use crate::api_keys::{self, ApiClient, Scope};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::jwt::JwtValidator;
+use crate::secrets::SecretBox;
+use crate::server::rate_limit::API_KEY_HEADER;
+use crate::server::tls::{ClientCertificate, ClientIdentities};
+use crate::server::versioning;
+use crate::storage::{OtpStore, SYSTEM_TENANT};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use otp_types::{SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER};
+
+/// Scope a route requires, by its unversioned pattern; `None` for public routes such as
+/// health checks
+pub fn required_scope(route: &str) -> Option<Scope> {
+    match route {
+        "/api/secret" | "/api/otp/generate" | "/api/hotp/generate" => Some(Scope::Generate),
+        "/api/otp/verify" | "/api/hotp/verify" => Some(Scope::Verify),
+        _ if route.starts_with("/api/admin/") => Some(Scope::Admin),
+        _ => None,
+    }
+}
+
+/// Refusal of admin routes while authentication is disabled, as anyone could then manage
+/// API keys and enrollments
+pub fn admin_requires_auth() -> AppError {
+    AppError::Forbidden("Admin endpoints require API_AUTH_ENABLED=true".to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Resolve the client a presented key belongs to, checking the bootstrap admin key first
+pub async fn resolve_client(
+    config: &Config,
+    storage: &dyn OtpStore,
+    api_key: &str,
+) -> Result<Option<ApiClient>, AppError> {
+    if let Some(admin_key) = &config.admin_api_key {
+        if api_keys::constant_time_eq(api_key.as_bytes(), admin_key.as_bytes()) {
+            return Ok(Some(ApiClient::bootstrap()));
+        }
+    }
+
+    let secrets = SecretBox::from_config(config).map_err(AppError::Internal)?;
+    // Authentication always fails closed, whatever the storage failure policy
+    api_keys::authenticate(storage, &secrets, api_key, now())
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))
+}
+
+/// Check the request signature and timestamp, and that the signature hasn't been seen before
+async fn verify_signature(
+    config: &Config,
+    storage: &dyn OtpStore,
+    client: &ApiClient,
+    req: &mut ServiceRequest,
+) -> Result<(), AppError> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    if client.signing_key.is_empty() {
+        return Err(AppError::Unauthorized(
+            "These credentials have no signing secret; rotate the API key to get one".to_string(),
+        ));
+    }
+    let (signature, timestamp) =
+        match (header(SIGNATURE_HEADER), header(SIGNATURE_TIMESTAMP_HEADER)) {
+            (Some(signature), Some(timestamp)) => (signature, timestamp),
+            _ => {
+                return Err(AppError::Unauthorized(format!(
+                    "{} and {} headers are required",
+                    SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER
+                )))
+            }
+        };
+    let timestamp: u64 = timestamp
+        .parse()
+        .map_err(|_| AppError::Unauthorized("Invalid signature timestamp".to_string()))?;
+    if now().abs_diff(timestamp) > config.request_signature_tolerance_seconds {
+        return Err(AppError::Unauthorized(
+            "Signature timestamp outside the allowed window".to_string(),
+        ));
+    }
+
+    // Read the body to sign it, then put it back for the handler
+    let body = req
+        .extract::<web::Bytes>()
+        .await
+        .map_err(|e| AppError::Validation(format!("Failed to read request body: {}", e)))?;
+    req.set_payload(Payload::from(body.clone()));
+
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or_else(|| req.path());
+    let expected = api_keys::signature(
+        &client.signing_key,
+        timestamp,
+        req.method().as_str(),
+        path_and_query,
+        &body,
+    );
+    if !api_keys::constant_time_eq(expected.as_bytes(), signature.to_lowercase().as_bytes()) {
+        return Err(AppError::Unauthorized(
+            "Invalid request signature".to_string(),
+        ));
+    }
+
+    // A signature is only accepted once while its timestamp is valid; checking and
+    // recording it in one step stops concurrent copies of a request both getting through
+    let nonce = format!("signature:{}", expected);
+    let first_use = storage
+        .mark_used_once(
+            SYSTEM_TENANT,
+            &nonce,
+            config.request_signature_tolerance_seconds * 2,
+        )
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    if !first_use {
+        return Err(AppError::Unauthorized(
+            "Request signature has already been used".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Token from an `Authorization: Bearer` header
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim().to_string())
+}
+
+async fn authorize(
+    config: &Config,
+    storage: &dyn OtpStore,
+    scope: Scope,
+    req: &mut ServiceRequest,
+) -> Result<ApiClient, AppError> {
+    let client = match bearer_token(req) {
+        Some(token) => {
+            let validator = req
+                .app_data::<web::Data<Option<Arc<JwtValidator>>>>()
+                .and_then(|v| v.as_ref().as_ref().cloned())
+                .ok_or_else(|| {
+                    AppError::Unauthorized("Bearer tokens are not accepted".to_string())
+                })?;
+            validator.authenticate(&token).await?
+        }
+        None if req.headers().contains_key(API_KEY_HEADER) => {
+            authorize_api_key(config, storage, req).await?
+        }
+        None => match req.conn_data::<ClientCertificate>() {
+            Some(cert) => req
+                .app_data::<web::Data<ClientIdentities>>()
+                .and_then(|identities| identities.identify(cert))
+                .ok_or_else(|| {
+                    AppError::Unauthorized(
+                        "Client certificate is not mapped to an identity".to_string(),
+                    )
+                })?,
+            None => {
+                return Err(AppError::Unauthorized(format!(
+                    "{} header, bearer token or client certificate is required",
+                    API_KEY_HEADER
+                )))
+            }
+        },
+    };
+
+    if !client.has_scope(scope) {
+        return Err(AppError::Forbidden(format!(
+            "Credentials lack the {:?} scope",
+            scope
+        )));
+    }
+    Ok(client)
+}
+
+async fn authorize_api_key(
+    config: &Config,
+    storage: &dyn OtpStore,
+    req: &mut ServiceRequest,
+) -> Result<ApiClient, AppError> {
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| AppError::Unauthorized(format!("Invalid {} header", API_KEY_HEADER)))?;
+
+    let client = resolve_client(config, storage, &api_key)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Invalid API key".to_string()))?;
+
+    // Signatures are verified whenever they are sent, and required for keys that demand them
+    if client.require_signature || req.headers().contains_key(SIGNATURE_HEADER) {
+        verify_signature(config, storage, &client, req).await?;
+    }
+    Ok(client)
+}
+
+/// Authentication middleware: require an API key, bearer token or mapped client
+/// certificate with the route's scope when enabled.
+///
+/// The authenticated `ApiClient` is stored in the request extensions for the tenant
+/// extractor and admin handlers.
+pub async fn authenticate(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let config = req.app_data::<web::Data<Arc<Config>>>().cloned();
+    let storage = req.app_data::<web::Data<Arc<dyn OtpStore>>>().cloned();
+    let (config, storage) = match (config, storage) {
+        (Some(config), Some(storage)) => (config, storage),
+        _ => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    // Classified by the route that will serve the request, so encoded paths such as
+    // `/api/%61dmin/unlock` need the same scope as the route they reach
+    let route = versioning::route_pattern(&req);
+    if !config.api_auth_enabled {
+        if route.as_deref().and_then(required_scope) == Some(Scope::Admin) {
+            log::warn!(
+                "Refused {} {}: authentication is disabled",
+                req.method(),
+                req.path()
+            );
+            return Ok(req.into_response(admin_requires_auth().error_response()));
+        }
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    }
+    let scope = match route {
+        Some(route) => required_scope(&route),
+        None => {
+            let error = AppError::NotFound("No such API endpoint".to_string());
+            return Ok(req.into_response(error.error_response()));
+        }
+    };
+    let scope = match scope {
+        Some(scope) => scope,
+        None => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    match authorize(&config, storage.as_ref().as_ref(), scope, &mut req).await {
+        Ok(client) => {
+            log::debug!("Authenticated {} for {}", client.key_id, req.path());
+            req.extensions_mut().insert(client);
+            next.call(req).await.map(|res| res.map_into_boxed_body())
+        }
+        Err(error) => {
+            log::warn!("Refused {} {}: {}", req.method(), req.path(), error);
+            Ok(req.into_response(error.error_response()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+    use sha2::{Digest, Sha256};
+    use std::time::Duration;
+
+    const ADMIN_KEY: &str = "bootstrap-admin-key";
+
+    fn config() -> Config {
+        Config {
+            api_auth_enabled: true,
+            admin_api_key: Some(ADMIN_KEY.to_string()),
+            secrets_key: Some(crate::secrets::tests::KEY.to_string()),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_required_scopes() {
+        assert_eq!(required_scope("/api/secret"), Some(Scope::Generate));
+        assert_eq!(required_scope("/api/hotp/verify"), Some(Scope::Verify));
+        assert_eq!(required_scope("/api/admin/unlock"), Some(Scope::Admin));
+        assert_eq!(required_scope("/api/health"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_api_key_scopes_and_tenant_binding() {
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config())))
+                .app_data(web::Data::new(storage.clone()))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        // Health checks stay public; everything else needs a key
+        let req = actix_test::TestRequest::get()
+            .uri("/api/health")
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::OK
+        );
+        for uri in ["/api/secret", "/api/v2/secret"] {
+            let req = actix_test::TestRequest::post().uri(uri).to_request();
+            assert_eq!(
+                actix_test::call_service(&app, req).await.status(),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        // The bootstrap admin key issues a verify-only key for one tenant
+        let req = actix_test::TestRequest::post()
+            .uri("/api/admin/api-keys")
+            .insert_header((API_KEY_HEADER, ADMIN_KEY))
+            .set_json(serde_json::json!({ "tenant": "acme", "scopes": ["verify"] }))
+            .to_request();
+        let created: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        let api_key = created["api_key"].as_str().unwrap().to_string();
+
+        let verify = |tenant: Option<&str>| {
+            let mut req = actix_test::TestRequest::post()
+                .uri("/api/hotp/verify")
+                .insert_header((API_KEY_HEADER, api_key.as_str()))
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "otp": "287082",
+                    "counter": 1
+                }));
+            if let Some(tenant) = tenant {
+                req = req.insert_header(("X-Tenant-Id", tenant));
+            }
+            req.to_request()
+        };
+
+        let resp = actix_test::call_service(&app, verify(None)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // The key's tenant is used when no header is sent
+        assert!(storage.is_used("acme", "hotp-287082-1").await.unwrap());
+
+        let resp = actix_test::call_service(&app, verify(Some("globex"))).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Out of scope
+        let req = actix_test::TestRequest::post()
+            .uri("/api/secret")
+            .insert_header((API_KEY_HEADER, api_key.as_str()))
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_encoded_paths_need_the_routes_scope() {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config())))
+                .app_data(web::Data::new(
+                    Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        // Each of these is routed to a protected handler once decoded
+        for (method, uri) in [
+            ("POST", "/api/%61dmin/api-keys"),
+            ("DELETE", "/api/%61dmin/tenants/acme"),
+            ("POST", "/api/%73ecret"),
+            ("POST", "/api/v2/%73ecret"),
+            ("POST", "/api/hotp/%76erify"),
+            ("POST", "/api/v2/otp/%76erify"),
+        ] {
+            let req = actix_test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(uri)
+                .set_json(serde_json::json!({ "scopes": ["admin"] }))
+                .to_request();
+            assert_eq!(
+                actix_test::call_service(&app, req).await.status(),
+                StatusCode::UNAUTHORIZED,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+
+        // Public routes, including those registered as services, stay public
+        for uri in ["/api/v2/docs", "/api/v2/openapi.json"] {
+            let req = actix_test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(
+                actix_test::call_service(&app, req).await.status(),
+                StatusCode::OK,
+                "{}",
+                uri
+            );
+        }
+
+        // Paths that match no route are refused before reaching any handler
+        let req = actix_test::TestRequest::post()
+            .uri("/api/admin%2Funlock")
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_admin_routes_are_refused_without_auth() {
+        let config = Config {
+            api_auth_enabled: false,
+            ..config()
+        };
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        for uri in ["/api/admin/api-keys", "/api/v2/%61dmin/api-keys"] {
+            let req = actix_test::TestRequest::post()
+                .uri(uri)
+                .set_json(serde_json::json!({ "tenant": "acme", "scopes": ["admin"] }))
+                .to_request();
+            assert_eq!(
+                actix_test::call_service(&app, req).await.status(),
+                StatusCode::FORBIDDEN
+            );
+        }
+        // Other routes stay open
+        let req = actix_test::TestRequest::post()
+            .uri("/api/v2/secret")
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_signed_requests() {
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let secrets = SecretBox::from_config(&config()).unwrap();
+        let issued = api_keys::create(
+            storage.as_ref(),
+            &secrets,
+            None,
+            vec![Scope::Generate],
+            true,
+            now(),
+        )
+        .await
+        .unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config())))
+                .app_data(web::Data::new(storage))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let body = r#"{"secret":"3132333435363738393031323334353637383930","counter":1}"#;
+        let timestamp = now();
+        let sig = api_keys::signature(
+            issued.signing_secret.as_bytes(),
+            timestamp,
+            "POST",
+            "/api/hotp/generate",
+            body.as_bytes(),
+        );
+        let request = |sig: &str| {
+            actix_test::TestRequest::post()
+                .uri("/api/hotp/generate")
+                .insert_header((API_KEY_HEADER, issued.api_key.as_str()))
+                .insert_header((SIGNATURE_TIMESTAMP_HEADER, timestamp.to_string()))
+                .insert_header((SIGNATURE_HEADER, sig))
+                .insert_header(("Content-Type", "application/json"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        // The handler still sees the body after it was read for the signature
+        let resp: serde_json::Value =
+            actix_test::call_and_read_body_json(&app, request(&sig)).await;
+        assert_eq!(resp["otp"], "287082");
+
+        // Replays and bad signatures are refused
+        let resp = actix_test::call_service(&app, request(&sig)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = actix_test::call_service(&app, request(&"0".repeat(64))).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        // Holding the key alone isn't enough to sign
+        let derived = api_keys::signature(
+            &Sha256::digest(issued.api_key.as_bytes()),
+            timestamp,
+            "POST",
+            "/api/hotp/generate",
+            body.as_bytes(),
+        );
+        let resp = actix_test::call_service(&app, request(&derived)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Keys that require signatures can't be used without one
+        let req = actix_test::TestRequest::post()
+            .uri("/api/hotp/generate")
+            .insert_header((API_KEY_HEADER, issued.api_key.as_str()))
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body)
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_concurrent_copies_of_a_signed_request() {
+        let store = Arc::new(FaultInjectingStore::new(Arc::new(MemoryStore::new())));
+        let storage = store.clone() as Arc<dyn OtpStore>;
+        let secrets = SecretBox::from_config(&config()).unwrap();
+        let issued = api_keys::create(
+            storage.as_ref(),
+            &secrets,
+            None,
+            vec![Scope::Generate],
+            true,
+            now(),
+        )
+        .await
+        .unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config())))
+                .app_data(web::Data::new(storage))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let timestamp = now();
+        let sig = api_keys::signature(
+            issued.signing_secret.as_bytes(),
+            timestamp,
+            "POST",
+            "/api/secret",
+            b"",
+        );
+        let request = || {
+            actix_test::TestRequest::post()
+                .uri("/api/secret")
+                .insert_header((API_KEY_HEADER, issued.api_key.as_str()))
+                .insert_header((SIGNATURE_TIMESTAMP_HEADER, timestamp.to_string()))
+                .insert_header((SIGNATURE_HEADER, sig.as_str()))
+                .to_request()
+        };
+
+        // Both copies are in flight at every storage round trip
+        store.set_delay(Duration::from_millis(20));
+        let (first, second) = tokio::join!(
+            actix_test::call_service(&app, request()),
+            actix_test::call_service(&app, request())
+        );
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::UNAUTHORIZED]);
+    }
+
+    #[actix_web::test]
+    async fn test_bearer_tokens() {
+        use crate::jwt::tests::{exp, jwks, token, KEY_1_PEM, KEY_1_X};
+
+        let path = std::env::temp_dir().join(format!("otp-auth-jwks-{}.json", std::process::id()));
+        std::fs::write(&path, jwks(&[("k1", KEY_1_X)]).to_string()).unwrap();
+        let config = Config {
+            jwt_jwks_file: Some(path.display().to_string()),
+            jwt_issuer: Some("https://issuer.example".to_string()),
+            jwt_audience: Some("otp".to_string()),
+            ..config()
+        };
+        let validator = JwtValidator::from_config(&config).unwrap().map(Arc::new);
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(storage.clone()))
+                .app_data(web::Data::new(validator))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let bearer = token(
+            KEY_1_PEM,
+            "k1",
+            serde_json::json!({
+                "sub": "svc",
+                "iss": "https://issuer.example",
+                "aud": "otp",
+                "exp": exp(),
+                "tenant": "acme",
+                "scope": "verify",
+            }),
+        );
+        let request = |uri: &str, token: &str| {
+            actix_test::TestRequest::post()
+                .uri(uri)
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "otp": "287082",
+                    "counter": 1
+                }))
+                .to_request()
+        };
+
+        let resp = actix_test::call_service(&app, request("/api/hotp/verify", &bearer)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(storage.is_used("acme", "hotp-287082-1").await.unwrap());
+
+        // The token's scopes gate the other routes
+        let resp = actix_test::call_service(&app, request("/api/hotp/generate", &bearer)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = actix_test::call_service(&app, request("/api/hotp/verify", "not-a-jwt")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::Config;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Header appended to by reverse proxies with the address they received the request from
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// Resolve the client IP of a request, honouring `X-Forwarded-For` only from trusted proxies.
+///
+/// Returns `None` when the peer address is unknown.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded_for = req
+        .headers()
+        .get(FORWARDED_FOR_HEADER)
+        .and_then(|v| v.to_str().ok());
+    Some(resolve(peer, forwarded_for, trusted_proxies))
+}
+
+/// Client IP of a request as seen through the configured trusted proxies, if known
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequest for ClientIp {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let trusted_proxies = req
+            .app_data::<web::Data<Arc<Config>>>()
+            .map(|config| config.trusted_proxies.as_slice())
+            .unwrap_or_default();
+        ready(Ok(ClientIp(client_ip(req, trusted_proxies))))
+    }
+}
+
+/// Walk the proxy chain from the nearest hop outwards and return the first untrusted address.
+///
+/// Each trusted proxy appends the address it received the request from, so the
+/// rightmost untrusted entry is the first one a trusted hop vouches for. Anything
+/// further left was supplied by the client and can't be trusted.
+pub fn resolve(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        let ip = match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => ip,
+            // A malformed entry ends the trustworthy part of the chain
+            Err(_) => break,
+        };
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Parse a comma-separated list of CIDRs or bare IP addresses, skipping invalid entries
+pub fn parse_networks(value: &str) -> Vec<IpNet> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from));
+            if parsed.is_err() {
+                log::warn!("Ignoring invalid network '{}'", entry);
+            }
+            parsed.ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_forwarded_for() {
+        let trusted = parse_networks("10.0.0.0/8");
+        assert_eq!(
+            resolve(ip("203.0.113.7"), Some("1.2.3.4"), &trusted),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn test_trusted_chain_yields_first_untrusted_hop() {
+        let trusted = parse_networks("10.0.0.0/8, 192.168.1.1");
+        // Client spoofed 1.2.3.4; the trusted edge proxy appended the real address
+        assert_eq!(
+            resolve(
+                ip("10.0.0.2"),
+                Some("1.2.3.4, 198.51.100.9, 192.168.1.1"),
+                &trusted
+            ),
+            ip("198.51.100.9")
+        );
+    }
+
+    #[test]
+    fn test_all_trusted_falls_back_to_leftmost() {
+        let trusted = parse_networks("10.0.0.0/8");
+        assert_eq!(
+            resolve(ip("10.0.0.2"), Some("10.1.1.1"), &trusted),
+            ip("10.1.1.1")
+        );
+        assert_eq!(resolve(ip("10.0.0.2"), None, &trusted), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_parse_networks() {
+        let networks = parse_networks("10.0.0.0/8, ::1, bogus,");
+        assert_eq!(networks.len(), 2);
+        assert!(networks[1].contains(&ip("::1")));
+    }
+}
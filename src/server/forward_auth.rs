@@ -0,0 +1,719 @@
+use crate::api_keys::constant_time_eq;
+use crate::config::Config;
+use crate::enrollment;
+use crate::error::AppError;
+use crate::events::Events;
+use crate::metrics::Outcome;
+use crate::server::client_ip::ClientIp;
+use crate::server::handlers::{check_totp, secret_box, TotpCheck};
+use crate::server::tenant::{validate_tenant_id, Tenant};
+use crate::storage::OtpStore;
+use actix_web::cookie::{time::Duration as CookieDuration, Cookie, SameSite};
+use actix_web::http::header::{self, ContentType};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use data_encoding::BASE64URL_NOPAD;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Response header naming the signed-in user, for proxies to pass upstream
+pub const USER_HEADER: &str = "X-Auth-Request-User";
+/// Response header naming the user's tenant
+pub const TENANT_HEADER: &str = "X-Auth-Request-Tenant";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Contents of a session cookie
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub user: String,
+    pub tenant: String,
+    /// When the user signed in
+    pub iat: u64,
+    pub exp: u64,
+}
+
+fn mac(secret: &[u8], payload: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl Session {
+    /// Cookie value: `base64url(json).base64url(HMAC-SHA256(secret, base64url(json)))`
+    pub fn encode(&self, secret: &[u8]) -> String {
+        let payload = BASE64URL_NOPAD.encode(&serde_json::to_vec(self).unwrap_or_default());
+        let signature = BASE64URL_NOPAD.encode(&mac(secret, &payload));
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Check the signature and expiry of a cookie value
+    pub fn decode(secret: &[u8], value: &str, now: u64) -> Option<Self> {
+        let (payload, signature) = value.split_once('.')?;
+        let signature = BASE64URL_NOPAD.decode(signature.as_bytes()).ok()?;
+        if !constant_time_eq(&signature, &mac(secret, payload)) {
+            return None;
+        }
+        let json = BASE64URL_NOPAD.decode(payload.as_bytes()).ok()?;
+        let session: Self = serde_json::from_slice(&json).ok()?;
+        (session.exp > now).then_some(session)
+    }
+}
+
+/// Who may reach a host behind the proxy
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostPolicy {
+    /// Exact host name, or `*.example.com` for any subdomain
+    pub host: String,
+    /// Users allowed in; any enrolled user when absent
+    #[serde(default)]
+    pub users: Option<Vec<String>>,
+    /// Require signing in again once the session is older than this
+    #[serde(default)]
+    pub max_session_age_seconds: Option<u64>,
+}
+
+impl HostPolicy {
+    fn matches(&self, host: &str) -> bool {
+        match self.host.strip_prefix("*.") {
+            Some(suffix) => host
+                .strip_suffix(suffix)
+                .is_some_and(|rest| rest.ends_with('.') && rest.len() > 1),
+            None => self.host.eq_ignore_ascii_case(host),
+        }
+    }
+}
+
+/// Per-host policies; without any, every signed-in user may reach every host
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HostPolicies {
+    #[serde(default)]
+    pub hosts: Vec<HostPolicy>,
+}
+
+/// Outcome of checking a session against the host policies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Allowed,
+    /// The session is too old for the host; the user must sign in again
+    Reauthenticate,
+    Denied,
+}
+
+impl HostPolicies {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid forward-auth policy {}: {}", path, e))
+    }
+
+    /// Exact host names take precedence over wildcards, longer wildcards over shorter
+    fn find(&self, host: &str) -> Option<&HostPolicy> {
+        let host = host.to_ascii_lowercase();
+        self.hosts
+            .iter()
+            .find(|p| !p.host.starts_with("*.") && p.matches(&host))
+            .or_else(|| {
+                self.hosts
+                    .iter()
+                    .filter(|p| p.host.starts_with("*.") && p.matches(&host))
+                    .max_by_key(|p| p.host.len())
+            })
+    }
+
+    pub fn access(&self, session: &Session, host: &str, now: u64) -> Access {
+        if self.hosts.is_empty() {
+            return Access::Allowed;
+        }
+        // Once policies are configured, hosts without one are closed
+        let policy = match self.find(host) {
+            Some(policy) => policy,
+            None => return Access::Denied,
+        };
+
+        if let Some(users) = &policy.users {
+            if !users.contains(&session.user) {
+                return Access::Denied;
+            }
+        }
+        match policy.max_session_age_seconds {
+            Some(max_age) if now.saturating_sub(session.iat) > max_age => Access::Reauthenticate,
+            _ => Access::Allowed,
+        }
+    }
+}
+
+/// Forward-auth settings, present when a session secret is configured
+pub struct ForwardAuth {
+    secret: Vec<u8>,
+    tenant: String,
+    session_seconds: u64,
+    cookie_name: String,
+    cookie_domain: Option<String>,
+    cookie_secure: bool,
+    login_url: Option<String>,
+    policies: HostPolicies,
+}
+
+impl ForwardAuth {
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let secret = match &config.forward_auth_session_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => return Ok(None),
+        };
+        let tenant = config
+            .forward_auth_tenant
+            .clone()
+            .unwrap_or_else(|| config.default_tenant.clone());
+        validate_tenant_id(&tenant).map_err(|e| e.to_string())?;
+        let policies = match &config.forward_auth_policy_file {
+            Some(path) => HostPolicies::load(path)?,
+            None => HostPolicies::default(),
+        };
+
+        Ok(Some(Self {
+            secret,
+            tenant,
+            session_seconds: config.forward_auth_session_seconds,
+            cookie_name: config.forward_auth_cookie_name.clone(),
+            cookie_domain: config.forward_auth_cookie_domain.clone(),
+            cookie_secure: config.forward_auth_cookie_secure,
+            login_url: config.forward_auth_login_url.clone(),
+            policies,
+        }))
+    }
+
+    fn session(&self, req: &HttpRequest, now: u64) -> Option<Session> {
+        let cookie = req.cookie(&self.cookie_name)?;
+        Session::decode(&self.secret, cookie.value(), now)
+    }
+
+    fn cookie(&self, value: String, max_age: i64) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.cookie_name.clone(), value)
+            .path("/")
+            .http_only(true)
+            .secure(self.cookie_secure)
+            .same_site(SameSite::Lax)
+            .max_age(CookieDuration::seconds(max_age))
+            .finish();
+        if let Some(domain) = &self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+
+    /// Only redirect after sign-in to paths on this host or to hosts the cookie covers,
+    /// so the login page can't be used as an open redirect
+    fn safe_redirect<'a>(&self, target: &'a str) -> Option<&'a str> {
+        if target.starts_with('/') && !target.starts_with("//") && !target.starts_with("/\\") {
+            return Some(target);
+        }
+        let url = reqwest::Url::parse(target).ok()?;
+        let host = url.host_str()?.to_ascii_lowercase();
+        let domain = self
+            .cookie_domain
+            .as_ref()?
+            .trim_start_matches('.')
+            .to_ascii_lowercase();
+        let covered = host == domain || host.ends_with(&format!(".{}", domain));
+        (matches!(url.scheme(), "http" | "https") && covered).then_some(target)
+    }
+
+    /// Where the proxied request was going, from the headers proxies add
+    fn original_url(req: &HttpRequest) -> String {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let info = req.connection_info();
+        let uri = header("X-Forwarded-Uri")
+            .or_else(|| header("X-Original-URI"))
+            .unwrap_or_else(|| "/".to_string());
+        format!("{}://{}{}", info.scheme(), info.host(), uri)
+    }
+}
+
+/// Host the proxied request was for, without a port
+fn forwarded_host(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    let host = info.host();
+    let name = match host.strip_prefix('[') {
+        // Bracketed IPv6 literal
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.to_ascii_lowercase()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    /// Answer unauthenticated requests with a redirect to the login page instead of 401,
+    /// for proxies such as Traefik that pass the response to the browser
+    #[serde(default)]
+    redirect: bool,
+}
+
+/// Check the session cookie of a request the proxy is about to forward
+pub async fn verify(
+    forward_auth: Option<web::Data<ForwardAuth>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    query: web::Query<VerifyQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let forward_auth = match forward_auth {
+        Some(forward_auth) => forward_auth,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let now = now();
+    let host = forwarded_host(&req);
+
+    let unauthenticated = || {
+        let login_url = forward_auth
+            .login_url
+            .as_deref()
+            .filter(|_| query.redirect)
+            .and_then(|url| reqwest::Url::parse(url).ok());
+        match login_url {
+            Some(mut login_url) => {
+                login_url
+                    .query_pairs_mut()
+                    .append_pair("rd", &ForwardAuth::original_url(&req));
+                HttpResponse::Found()
+                    .insert_header((header::LOCATION, login_url.to_string()))
+                    .finish()
+            }
+            None => HttpResponse::Unauthorized().finish(),
+        }
+    };
+
+    let session = match forward_auth.session(&req, now) {
+        Some(session) if session.tenant == forward_auth.tenant => session,
+        _ => return Ok(unauthenticated()),
+    };
+
+    // Removing a user's enrollment ends their sessions
+    let enrolled = enrollment::exists(storage.as_ref().as_ref(), &session.tenant, &session.user)
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    if !enrolled {
+        return Ok(unauthenticated());
+    }
+
+    match forward_auth.policies.access(&session, &host, now) {
+        Access::Allowed => Ok(HttpResponse::Ok()
+            .insert_header((USER_HEADER, session.user))
+            .insert_header((TENANT_HEADER, session.tenant))
+            .finish()),
+        Access::Reauthenticate => Ok(unauthenticated()),
+        Access::Denied => {
+            log::warn!("Forward auth refused {} for host {}", session.user, host);
+            Ok(HttpResponse::Forbidden().finish())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    /// URL to return to after signing in
+    #[serde(default)]
+    rd: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    user: String,
+    code: String,
+    #[serde(default)]
+    rd: Option<String>,
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn login_page(
+    status: actix_web::http::StatusCode,
+    rd: Option<&str>,
+    error: Option<&str>,
+) -> HttpResponse {
+    let error = error
+        .map(|e| format!(r#"<p class="error">{}</p>"#, escape_html(e)))
+        .unwrap_or_default();
+    let rd = escape_html(rd.unwrap_or_default());
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Sign in</title>
+<style>
+body {{ font-family: sans-serif; max-width: 20rem; margin: 4rem auto; }}
+label, input, button {{ display: block; width: 100%; margin-top: 0.5rem; }}
+.error {{ color: #b00020; }}
+</style>
+</head>
+<body>
+<h1>Sign in</h1>
+{error}
+<form method="post" action="login">
+<label>User <input name="user" autocomplete="username" required autofocus></label>
+<label>Code <input name="code" inputmode="numeric" autocomplete="one-time-code" required></label>
+<input type="hidden" name="rd" value="{rd}">
+<button type="submit">Sign in</button>
+</form>
+</body>
+</html>
+"#
+        ))
+}
+
+/// Built-in login page
+pub async fn login_form(
+    forward_auth: Option<web::Data<ForwardAuth>>,
+    query: web::Query<LoginQuery>,
+) -> HttpResponse {
+    if forward_auth.is_none() {
+        return HttpResponse::NotFound().finish();
+    }
+    login_page(actix_web::http::StatusCode::OK, query.rd.as_deref(), None)
+}
+
+/// Check the submitted code against the user's enrollment and start a session
+pub async fn login(
+    forward_auth: Option<web::Data<ForwardAuth>>,
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    events: Option<web::Data<Events>>,
+    client_ip: ClientIp,
+    form: web::Form<LoginForm>,
+) -> Result<HttpResponse, AppError> {
+    let forward_auth = match forward_auth {
+        Some(forward_auth) => forward_auth,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let rd = form.rd.as_deref().filter(|rd| !rd.is_empty());
+    let refuse = |status, message: &str| Ok(login_page(status, rd, Some(message)));
+
+    let user = form.user.trim();
+    let secrets = secret_box(&config)?;
+    let enrollment = enrollment::get(
+        storage.as_ref().as_ref(),
+        &secrets,
+        &forward_auth.tenant,
+        user,
+    )
+    .await
+    .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    // Unknown users get the same answer as wrong codes
+    let enrollment = match enrollment {
+        Some(enrollment) => enrollment,
+        None => {
+            log::warn!("Forward-auth login for unknown user {}", user);
+            return refuse(
+                actix_web::http::StatusCode::UNAUTHORIZED,
+                "Invalid user or code",
+            );
+        }
+    };
+
+    let tenant = Tenant(forward_auth.tenant.clone());
+    let checked = check_totp(
+        &config,
+        &storage,
+        events.as_ref().map(|e| e.get_ref()),
+        &tenant,
+        client_ip,
+        TotpCheck {
+            secret: &enrollment.secret,
+            otp: form.code.trim(),
+            credential_id: None,
+            user_id: Some(user),
+            pin_matches: true,
+        },
+    )
+    .await;
+    match checked {
+        Ok(Outcome::Valid) => {}
+        Ok(_) => {
+            return refuse(
+                actix_web::http::StatusCode::UNAUTHORIZED,
+                "Invalid user or code",
+            )
+        }
+        Err(e @ (AppError::Locked { .. } | AppError::TooManyRequests { .. })) => {
+            return refuse(e.status_code(), "Too many failed attempts, try again later")
+        }
+        Err(e) => return Err(e),
+    }
+
+    let now = now();
+    let session = Session {
+        user: user.to_string(),
+        tenant: forward_auth.tenant.clone(),
+        iat: now,
+        exp: now + forward_auth.session_seconds,
+    };
+    let cookie = forward_auth.cookie(
+        session.encode(&forward_auth.secret),
+        forward_auth.session_seconds as i64,
+    );
+    log::info!(tenant = session.tenant.as_str(), user_id = user; "Forward-auth session started");
+
+    match rd.and_then(|rd| forward_auth.safe_redirect(rd)) {
+        Some(target) => Ok(HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, target))
+            .cookie(cookie)
+            .finish()),
+        None => Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .cookie(cookie)
+            .body("<!DOCTYPE html><html><body><p>Signed in.</p></body></html>\n")),
+    }
+}
+
+/// End the session
+pub async fn logout(forward_auth: Option<web::Data<ForwardAuth>>) -> HttpResponse {
+    match forward_auth {
+        Some(forward_auth) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .cookie(forward_auth.cookie(String::new(), 0))
+            .body("<!DOCTYPE html><html><body><p>Signed out.</p></body></html>\n"),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::totp::Totp;
+    use crate::secrets::SecretBox;
+    use crate::storage::memory::MemoryStore;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+
+    const SECRET: &[u8] = b"session-secret";
+
+    fn session(user: &str, iat: u64) -> Session {
+        Session {
+            user: user.to_string(),
+            tenant: "default".to_string(),
+            iat,
+            exp: iat + 3600,
+        }
+    }
+
+    #[test]
+    fn test_session_cookie_is_signed_and_expires() {
+        let value = session("alice", 1000).encode(SECRET);
+        assert_eq!(
+            Session::decode(SECRET, &value, 1000),
+            Some(session("alice", 1000))
+        );
+        assert_eq!(Session::decode(SECRET, &value, 4600), None);
+        assert_eq!(Session::decode(b"other-secret", &value, 1000), None);
+
+        // A payload for another user with the original signature is refused
+        let (_, signature) = value.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            BASE64URL_NOPAD.encode(&serde_json::to_vec(&session("mallory", 1000)).unwrap()),
+            signature
+        );
+        assert_eq!(Session::decode(SECRET, &forged, 1000), None);
+    }
+
+    #[test]
+    fn test_host_policies() {
+        let policies: HostPolicies = serde_json::from_value(serde_json::json!({
+            "hosts": [
+                { "host": "*.tools.internal" },
+                { "host": "grafana.tools.internal", "users": ["alice"] },
+                { "host": "*.admin.tools.internal", "max_session_age_seconds": 600 }
+            ]
+        }))
+        .unwrap();
+        let alice = session("alice", 1000);
+        let bob = session("bob", 1000);
+
+        assert_eq!(
+            policies.access(&bob, "wiki.tools.internal", 1000),
+            Access::Allowed
+        );
+        assert_eq!(
+            policies.access(&alice, "grafana.tools.internal", 1000),
+            Access::Allowed
+        );
+        assert_eq!(
+            policies.access(&bob, "GRAFANA.tools.internal", 1000),
+            Access::Denied
+        );
+        assert_eq!(
+            policies.access(&bob, "db.admin.tools.internal", 1500),
+            Access::Allowed
+        );
+        assert_eq!(
+            policies.access(&bob, "db.admin.tools.internal", 1601),
+            Access::Reauthenticate
+        );
+        // Hosts without a policy, and the bare wildcard domain, are closed
+        assert_eq!(
+            policies.access(&bob, "tools.internal", 1000),
+            Access::Denied
+        );
+        assert_eq!(policies.access(&bob, "example.com", 1000), Access::Denied);
+
+        assert_eq!(
+            HostPolicies::default().access(&bob, "example.com", 1000),
+            Access::Allowed
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_login_and_verify() {
+        let config = Config {
+            otp_length: 6,
+            otp_expiry_seconds: 30,
+            forward_auth_session_secret: Some("session-secret".to_string()),
+            forward_auth_cookie_domain: Some("tools.internal".to_string()),
+            forward_auth_login_url: Some("https://auth.tools.internal/auth/login".to_string()),
+            secrets_key: Some(crate::secrets::tests::KEY.to_string()),
+            ..Config::default()
+        };
+        let forward_auth = web::Data::new(ForwardAuth::from_config(&config).unwrap().unwrap());
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let enrollment = enrollment::enroll(
+            storage.as_ref(),
+            &SecretBox::from_config(&config).unwrap(),
+            "default",
+            "alice",
+            None,
+            0,
+        )
+        .await
+        .unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config.clone())))
+                .app_data(web::Data::new(storage.clone()))
+                .app_data(forward_auth)
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        // No session: 401, or a redirect to the login page that remembers the target
+        let req = actix_test::TestRequest::get()
+            .uri("/auth/verify")
+            .insert_header(("X-Forwarded-Host", "wiki.tools.internal"))
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let req = actix_test::TestRequest::get()
+            .uri("/auth/verify?redirect=true")
+            .insert_header(("X-Forwarded-Proto", "https"))
+            .insert_header(("X-Forwarded-Host", "wiki.tools.internal"))
+            .insert_header(("X-Forwarded-Uri", "/page?id=1"))
+            .to_request();
+        let resp = actix_test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let rd = reqwest::Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .find(|(name, _)| name == "rd")
+            .unwrap()
+            .1
+            .into_owned();
+        assert_eq!(rd, "https://wiki.tools.internal/page?id=1");
+
+        let login = |code: String| {
+            actix_test::TestRequest::post()
+                .uri("/auth/login")
+                .set_form([
+                    ("user", "alice"),
+                    ("code", code.as_str()),
+                    ("rd", rd.as_str()),
+                ])
+                .to_request()
+        };
+        let resp = actix_test::call_service(&app, login("000000".to_string())).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let totp = Totp::new(hex::decode(&enrollment.secret).unwrap(), 6, 30);
+        let resp = actix_test::call_service(&app, login(totp.generate().unwrap())).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            resp.headers().get(header::LOCATION).unwrap(),
+            "https://wiki.tools.internal/page?id=1"
+        );
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.domain(), Some("tools.internal"));
+
+        let verify = |cookie: Cookie<'static>| {
+            actix_test::TestRequest::get()
+                .uri("/auth/verify")
+                .insert_header(("X-Forwarded-Host", "wiki.tools.internal"))
+                .cookie(cookie)
+                .to_request()
+        };
+        let resp = actix_test::call_service(&app, verify(cookie.clone())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(USER_HEADER).unwrap(), "alice");
+
+        // Removing the enrollment ends the session
+        enrollment::remove(storage.as_ref(), "default", "alice")
+            .await
+            .unwrap();
+        let resp = actix_test::call_service(&app, verify(cookie)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_redirects_stay_within_the_cookie_domain() {
+        let config = Config {
+            forward_auth_session_secret: Some("session-secret".to_string()),
+            forward_auth_cookie_domain: Some("tools.internal".to_string()),
+            ..Config::default()
+        };
+        let forward_auth = ForwardAuth::from_config(&config).unwrap().unwrap();
+        assert!(forward_auth.safe_redirect("/dashboard").is_some());
+        assert!(forward_auth
+            .safe_redirect("https://wiki.tools.internal/")
+            .is_some());
+        assert!(forward_auth.safe_redirect("//evil.example/").is_none());
+        assert!(forward_auth
+            .safe_redirect("https://evil.example/")
+            .is_none());
+        assert!(forward_auth
+            .safe_redirect("https://eviltools.internal/")
+            .is_none());
+        assert!(forward_auth.safe_redirect("javascript:alert(1)").is_none());
+    }
+}
@@ -0,0 +1,975 @@
+//! gRPC front end for internal services.
+//!
+//! Serves `otp.v1.OtpService` from `proto/otp/v1/otp.proto` next to the standard health
+//! and reflection services. Each method is checked as the REST route it mirrors, so API
+//! key scopes, rate limits and access policies apply as they do over HTTP, and the work
+//! is done by the same functions as the REST handlers.
+//!
+//! With `TLS_CERT_FILE` set the listener uses the HTTP server's TLS configuration,
+//! including client certificate verification, and mapped client certificates
+//! authenticate calls like they do requests.
+
+use crate::api_keys::{ApiClient, Scope};
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::events::Events;
+use crate::jwt::JwtValidator;
+use crate::lockout;
+use crate::metrics::Outcome;
+use crate::receipts::{self, ReceiptSigner, Verification};
+use crate::server::access_policy::AccessPolicyHandle;
+use crate::server::auth;
+use crate::server::client_ip::{self, ClientIp};
+use crate::server::handlers::{self, HotpCheck, TotpCheck};
+use crate::server::rate_limit::{self, RouteGroup};
+use crate::server::tenant::{self, Tenant};
+use crate::server::tls::{ClientCertificate, ClientIdentities};
+use crate::storage::{OtpStore, StorageState, StorageStatus};
+use crate::telemetry;
+use data_encoding::BASE32;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use proto::otp_service_server::{OtpService, OtpServiceServer};
+use proto::*;
+use rustls::ServerConfig;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Code, Request, Response, Status};
+use tonic_health::server::HealthReporter;
+
+pub mod proto {
+    tonic::include_proto!("otp.v1");
+
+    /// Encoded descriptors of the protos, for the reflection service
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/otp_descriptor.bin"));
+}
+
+/// Metadata carrying the caller's API key
+pub const API_KEY_METADATA: &str = "x-api-key";
+/// Metadata selecting the tenant a call operates on
+pub const TENANT_METADATA: &str = "x-tenant-id";
+/// Metadata carrying the stable code of an error, as in problem details
+pub const ERROR_CODE_METADATA: &str = "x-error-code";
+
+/// How often storage readiness is copied to the health service
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a client may take to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        let code = match &error {
+            AppError::Internal(_) => Code::Internal,
+            AppError::Validation(_) => Code::InvalidArgument,
+            AppError::Unauthorized(_) => Code::Unauthenticated,
+            AppError::Forbidden(_) => Code::PermissionDenied,
+            AppError::NotFound(_) => Code::NotFound,
+            AppError::Conflict(_) => Code::FailedPrecondition,
+            AppError::Locked { .. } | AppError::TooManyRequests { .. } => Code::ResourceExhausted,
+            AppError::StorageUnavailable(_) => Code::Unavailable,
+        };
+        let problem = error.problem();
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            ERROR_CODE_METADATA,
+            MetadataValue::from_static(error.code()),
+        );
+        if let Some(Ok(retry_after)) = problem.retry_after.map(|s| s.to_string().parse()) {
+            metadata.insert("retry-after", retry_after);
+        }
+        Status::with_metadata(code, problem.detail, metadata)
+    }
+}
+
+/// State the gRPC service shares with the HTTP handlers
+#[derive(Clone)]
+pub struct OtpGrpc {
+    pub config: Arc<Config>,
+    pub storage: Arc<dyn OtpStore>,
+    pub access_policy: AccessPolicyHandle,
+    pub jwt_validator: Option<Arc<JwtValidator>>,
+    pub client_identities: Arc<ClientIdentities>,
+    pub receipts: Option<Arc<ReceiptSigner>>,
+    pub events: Option<Arc<Events>>,
+}
+
+/// A call admitted by [`OtpGrpc::admit`]
+struct Caller {
+    tenant: Tenant,
+    client: Option<ApiClient>,
+    client_ip: ClientIp,
+}
+
+impl Caller {
+    /// Key ID of the API client making the call
+    fn actor(&self) -> Option<&str> {
+        self.client.as_ref().map(|client| client.key_id.as_str())
+    }
+}
+
+impl OtpGrpc {
+    /// Apply the access policy, rate limit and authentication of the REST route at `path`,
+    /// in the order the middleware does, and resolve the tenant the call acts on
+    async fn admit<T>(&self, request: &Request<T>, path: &str) -> AppResult<Caller> {
+        let metadata = request.metadata();
+        let text = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+        };
+        let ip = request.remote_addr().map(|peer| {
+            client_ip::resolve(
+                peer.ip(),
+                text("x-forwarded-for"),
+                &self.config.trusted_proxies,
+            )
+        });
+        let requested_tenant = text(TENANT_METADATA);
+
+        let policy = self.access_policy.current();
+        let refused = |tenant: &str| {
+            log::warn!(
+                "Access policy refused gRPC call to {} from {:?} for tenant {}",
+                path,
+                ip,
+                tenant
+            );
+            AppError::Forbidden("Access denied for this client address".to_string())
+        };
+        if !policy.permits_client(path, ip) {
+            return Err(refused("*"));
+        }
+
+        let group = RouteGroup::for_path(path);
+        if let Some((group, limit)) = group.and_then(|g| g.limit(&self.config).map(|l| (g, l))) {
+            let decision = rate_limit::decide(
+                self.storage.as_ref(),
+                group,
+                limit,
+                ip,
+                text(API_KEY_METADATA),
+            )
+            .await;
+            if let Some(decision) = decision.filter(|d| !d.allowed) {
+                log::warn!("Rate limit exceeded for {} routes", group.name());
+                return Err(AppError::TooManyRequests {
+                    message: "Rate limit exceeded".to_string(),
+                    retry_after: decision.reset,
+                });
+            }
+        }
+
+        let client = match auth::required_scope(path) {
+            Some(scope) if self.config.api_auth_enabled => {
+                let cert = request
+                    .peer_certs()
+                    .and_then(|certs| certs.first().and_then(|c| ClientCertificate::from_der(c)));
+                Some(self.authorize(metadata, cert.as_ref(), scope).await?)
+            }
+            Some(Scope::Admin) => return Err(auth::admin_requires_auth()),
+            _ => None,
+        };
+        let tenant = tenant::resolve(
+            requested_tenant,
+            client.as_ref(),
+            &self.config.default_tenant,
+        )?;
+        // Tenant rules apply to the tenant the client acts on, known once authenticated
+        if !policy.permits_tenant(path, tenant.as_str(), ip) {
+            return Err(refused(tenant.as_str()));
+        }
+        Ok(Caller {
+            tenant,
+            client,
+            client_ip: ClientIp(ip),
+        })
+    }
+
+    /// Authenticate an API key, bearer token or mapped client certificate and check it has
+    /// `scope`
+    async fn authorize(
+        &self,
+        metadata: &MetadataMap,
+        cert: Option<&ClientCertificate>,
+        scope: Scope,
+    ) -> AppResult<ApiClient> {
+        let text = |name: &str| metadata.get(name).and_then(|v| v.to_str().ok());
+        let bearer = text("authorization")
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim());
+
+        let client = match (bearer, text(API_KEY_METADATA)) {
+            (Some(token), _) => {
+                let validator = self.jwt_validator.as_ref().ok_or_else(|| {
+                    AppError::Unauthorized("Bearer tokens are not accepted".to_string())
+                })?;
+                validator.authenticate(token).await?
+            }
+            (None, Some(api_key)) => {
+                let client = auth::resolve_client(&self.config, self.storage.as_ref(), api_key)
+                    .await?
+                    .ok_or_else(|| AppError::Unauthorized("Invalid API key".to_string()))?;
+                // Signatures cover an HTTP method, path and body, which gRPC calls don't have
+                if client.require_signature {
+                    return Err(AppError::Unauthorized(
+                        "API keys that require request signatures can't be used over gRPC"
+                            .to_string(),
+                    ));
+                }
+                client
+            }
+            (None, None) => match cert {
+                Some(cert) => self.client_identities.identify(cert).ok_or_else(|| {
+                    AppError::Unauthorized(
+                        "Client certificate is not mapped to an identity".to_string(),
+                    )
+                })?,
+                None => {
+                    return Err(AppError::Unauthorized(format!(
+                        "{} or bearer token metadata, or a client certificate, is required",
+                        API_KEY_METADATA
+                    )))
+                }
+            },
+        };
+
+        if !client.has_scope(scope) {
+            return Err(AppError::Forbidden(format!(
+                "Credentials lack the {:?} scope",
+                scope
+            )));
+        }
+        Ok(client)
+    }
+
+    /// Sign a receipt for a successful verification, if one was requested
+    fn receipt(
+        &self,
+        requested: bool,
+        caller: &Caller,
+        secret: &str,
+        credential_id: Option<&str>,
+        user_id: Option<&str>,
+        method: receipts::Method,
+    ) -> AppResult<Option<String>> {
+        handlers::issue_receipt(
+            self.receipts.as_deref(),
+            requested,
+            Verification {
+                tenant: caller.tenant.as_str(),
+                user_id,
+                credential_id: &lockout::credential_id(&self.config, secret, credential_id),
+                method,
+            },
+        )
+    }
+}
+
+/// Run a call in a server span and turn its errors into statuses
+async fn traced<T>(
+    method: &'static str,
+    call: impl Future<Output = AppResult<T>>,
+) -> Result<Response<T>, Status> {
+    let attributes = vec![
+        KeyValue::new("rpc.system", "grpc"),
+        KeyValue::new("rpc.service", "otp.v1.OtpService"),
+        KeyValue::new("rpc.method", method),
+    ];
+    let name = format!("grpc otp.v1.OtpService/{}", method);
+    telemetry::in_span(name, SpanKind::Server, attributes, async {
+        call.await.map(Response::new).map_err(|error| {
+            log::warn!("gRPC {} failed: {}", method, error);
+            telemetry::record_error(&error.to_string());
+            Status::from(error)
+        })
+    })
+    .await
+}
+
+#[tonic::async_trait]
+impl OtpService for OtpGrpc {
+    async fn generate_secret(
+        &self,
+        request: Request<GenerateSecretRequest>,
+    ) -> Result<Response<GenerateSecretResponse>, Status> {
+        traced("GenerateSecret", async {
+            self.admit(&request, "/api/secret").await?;
+            let secret = handlers::new_secret();
+            Ok(GenerateSecretResponse {
+                secret: hex::encode(&secret),
+                secret_base32: BASE32.encode(&secret),
+            })
+        })
+        .await
+    }
+
+    async fn enroll(
+        &self,
+        request: Request<EnrollRequest>,
+    ) -> Result<Response<EnrollResponse>, Status> {
+        traced("Enroll", async {
+            let caller = self.admit(&request, "/api/admin/enrollments").await?;
+            let req = request.get_ref();
+            let enrollment = handlers::enroll_user(
+                &self.storage,
+                &handlers::secret_box(&self.config)?,
+                self.events.as_deref(),
+                &caller.tenant,
+                caller.actor(),
+                &req.user_id,
+                req.pin.as_deref(),
+            )
+            .await?;
+
+            let secret = hex::decode(&enrollment.secret).unwrap_or_default();
+            Ok(EnrollResponse {
+                otpauth_uri: enrollment.otpauth_uri(
+                    &self.config.enrollment_issuer,
+                    self.config.otp_length,
+                    self.config.otp_expiry_seconds,
+                ),
+                secret_base32: BASE32.encode(&secret),
+                user_id: enrollment.user_id,
+                secret: enrollment.secret,
+            })
+        })
+        .await
+    }
+
+    async fn generate_totp(
+        &self,
+        request: Request<GenerateTotpRequest>,
+    ) -> Result<Response<GenerateTotpResponse>, Status> {
+        traced("GenerateTotp", async {
+            self.admit(&request, "/api/otp/generate").await?;
+            Ok(GenerateTotpResponse {
+                otp: handlers::totp_code(&self.config, &request.get_ref().secret)?,
+                expires_in: self.config.otp_expiry_seconds,
+            })
+        })
+        .await
+    }
+
+    async fn verify_totp(
+        &self,
+        request: Request<VerifyTotpRequest>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        traced("VerifyTotp", async {
+            let caller = self.admit(&request, "/api/otp/verify").await?;
+            let req = request.get_ref();
+            handlers::ensure_receipts_available(req.receipt, self.receipts.as_deref())?;
+
+            let outcome = handlers::check_totp(
+                &self.config,
+                &self.storage,
+                self.events.as_deref(),
+                &caller.tenant,
+                caller.client_ip,
+                TotpCheck {
+                    secret: &req.secret,
+                    otp: &req.otp,
+                    credential_id: req.credential_id.as_deref(),
+                    user_id: req.user_id.as_deref(),
+                    pin_matches: true,
+                },
+            )
+            .await?;
+            let valid = outcome == Outcome::Valid;
+            let receipt = match valid {
+                true => self.receipt(
+                    req.receipt,
+                    &caller,
+                    &req.secret,
+                    req.credential_id.as_deref(),
+                    req.user_id.as_deref(),
+                    receipts::Method::Totp,
+                )?,
+                false => None,
+            };
+            Ok(VerifyResponse {
+                valid,
+                reason: outcome.as_str().to_string(),
+                receipt,
+            })
+        })
+        .await
+    }
+
+    async fn generate_hotp(
+        &self,
+        request: Request<GenerateHotpRequest>,
+    ) -> Result<Response<GenerateHotpResponse>, Status> {
+        traced("GenerateHotp", async {
+            self.admit(&request, "/api/hotp/generate").await?;
+            let req = request.get_ref();
+            Ok(GenerateHotpResponse {
+                otp: handlers::hotp_code(&self.config, &req.secret, req.counter)?,
+            })
+        })
+        .await
+    }
+
+    async fn verify_hotp(
+        &self,
+        request: Request<VerifyHotpRequest>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        traced("VerifyHotp", async {
+            let caller = self.admit(&request, "/api/hotp/verify").await?;
+            let req = request.get_ref();
+            handlers::ensure_receipts_available(req.receipt, self.receipts.as_deref())?;
+
+            let outcome = handlers::check_hotp(
+                &self.config,
+                &self.storage,
+                self.events.as_deref(),
+                &caller.tenant,
+                caller.client_ip,
+                HotpCheck {
+                    secret: &req.secret,
+                    otp: &req.otp,
+                    counter: req.counter,
+                    credential_id: req.credential_id.as_deref(),
+                    user_id: req.user_id.as_deref(),
+                },
+            )
+            .await?;
+            let valid = outcome == Outcome::Valid;
+            let receipt = match valid {
+                true => self.receipt(
+                    req.receipt,
+                    &caller,
+                    &req.secret,
+                    req.credential_id.as_deref(),
+                    req.user_id.as_deref(),
+                    receipts::Method::Hotp,
+                )?,
+                false => None,
+            };
+            Ok(VerifyResponse {
+                valid,
+                reason: outcome.as_str().to_string(),
+                receipt,
+            })
+        })
+        .await
+    }
+}
+
+/// Report the OTP service as serving while storage is ready, like the readiness endpoint.
+/// The server as a whole (the empty service name) is always reported as serving.
+async fn report_readiness(reporter: HealthReporter, status: StorageStatus) {
+    let mut ticker = tokio::time::interval(HEALTH_POLL_INTERVAL);
+    let mut reported = None;
+    loop {
+        ticker.tick().await;
+        let ready = status.state() == StorageState::Ready;
+        if reported == Some(ready) {
+            continue;
+        }
+        reported = Some(ready);
+        if ready {
+            reporter.set_serving::<OtpServiceServer<OtpGrpc>>().await;
+        } else {
+            reporter
+                .set_not_serving::<OtpServiceServer<OtpGrpc>>()
+                .await;
+        }
+    }
+}
+
+/// TLS connections accepted on `listener`. Handshakes run concurrently, so a slow client
+/// doesn't hold up the others; failed handshakes are dropped.
+fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> ReceiverStream<std::io::Result<TlsStream<TcpStream>>> {
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept gRPC connection: {}", e);
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => log::debug!("gRPC TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => log::debug!("gRPC TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+pub struct GrpcServer {
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    service: OtpGrpc,
+    status: StorageStatus,
+}
+
+impl GrpcServer {
+    /// Bind the listener when `GRPC_BIND` is set, serving TLS with the HTTP server's
+    /// configuration when it has one
+    pub async fn from_config(
+        service: OtpGrpc,
+        status: StorageStatus,
+        tls: Option<ServerConfig>,
+    ) -> Result<Option<Self>, String> {
+        let bind = match &service.config.grpc_bind {
+            Some(bind) => bind.clone(),
+            None => return Ok(None),
+        };
+        let listener = TcpListener::bind(&bind)
+            .await
+            .map_err(|e| format!("Failed to bind gRPC listener on {}: {}", bind, e))?;
+
+        // gRPC needs HTTP/2, which TLS clients negotiate with ALPN
+        let tls = tls.map(|mut tls| {
+            tls.alpn_protocols = vec![b"h2".to_vec()];
+            TlsAcceptor::from(Arc::new(tls))
+        });
+
+        Ok(Some(Self {
+            listener,
+            tls,
+            service,
+            status,
+        }))
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve calls until the task is dropped
+    pub async fn run(self) {
+        if let Ok(addr) = self.local_addr() {
+            let scheme = if self.tls.is_some() {
+                "TLS"
+            } else {
+                "plaintext"
+            };
+            log::info!("gRPC listener on {} ({})", addr, scheme);
+        }
+        let (reporter, health) = tonic_health::server::health_reporter();
+        let reflection = match tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+            .build_v1()
+        {
+            Ok(reflection) => reflection,
+            Err(e) => {
+                log::error!("Failed to build gRPC reflection service: {}", e);
+                return;
+            }
+        };
+
+        let router = tonic::transport::Server::builder()
+            .add_service(health)
+            .add_service(reflection)
+            .add_service(OtpServiceServer::new(self.service));
+        let server: Pin<Box<dyn Future<Output = Result<(), tonic::transport::Error>> + Send>> =
+            match self.tls {
+                Some(acceptor) => {
+                    Box::pin(router.serve_with_incoming(tls_incoming(self.listener, acceptor)))
+                }
+                None => Box::pin(router.serve_with_incoming(TcpListenerStream::new(self.listener))),
+            };
+        tokio::select! {
+            result = server => {
+                if let Err(e) = result {
+                    log::error!("gRPC server failed: {}", e);
+                }
+            }
+            () = report_readiness(reporter, self.status) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::totp::Totp;
+    use crate::storage::memory::MemoryStore;
+    use proto::otp_service_client::OtpServiceClient;
+    use tonic::transport::Channel;
+    use tonic_health::pb::health_check_response::ServingStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+    use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+    const ADMIN_KEY: &str = "grpc-admin-key";
+
+    /// A request carrying the admin key and a tenant
+    fn authorized<T>(message: T, tenant: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert(API_KEY_METADATA, ADMIN_KEY.parse().unwrap());
+        request
+            .metadata_mut()
+            .insert(TENANT_METADATA, tenant.parse().unwrap());
+        request
+    }
+
+    fn error_code(status: &Status) -> &str {
+        status
+            .metadata()
+            .get(ERROR_CODE_METADATA)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_errors_map_to_status() {
+        let status = Status::from(AppError::Locked {
+            message: "Too many failed attempts, try again later".to_string(),
+            retry_after: 30,
+        });
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            "Too many failed attempts, try again later"
+        );
+        assert_eq!(error_code(&status), "locked");
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "30");
+
+        let status = Status::from(AppError::StorageUnavailable("down".to_string()));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(status.metadata().get("retry-after").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_enroll_is_refused_without_auth() {
+        let service = OtpGrpc {
+            config: Arc::new(Config::default()),
+            storage: Arc::new(MemoryStore::new()),
+            access_policy: AccessPolicyHandle::default(),
+            jwt_validator: None,
+            client_identities: Arc::default(),
+            receipts: None,
+            events: None,
+        };
+        let status = OtpService::enroll(
+            &service,
+            Request::new(EnrollRequest {
+                user_id: "alice".to_string(),
+                pin: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(error_code(&status), "forbidden");
+    }
+
+    #[tokio::test]
+    async fn test_calls_over_grpc() {
+        let config = Arc::new(Config {
+            otp_length: 6,
+            otp_expiry_seconds: 30,
+            api_auth_enabled: true,
+            admin_api_key: Some(ADMIN_KEY.to_string()),
+            secrets_key: Some(crate::secrets::tests::KEY.to_string()),
+            grpc_bind: Some("127.0.0.1:0".to_string()),
+            ..Config::default()
+        });
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let status = StorageStatus::default();
+        status.set_ready();
+        let service = OtpGrpc {
+            config,
+            storage,
+            access_policy: AccessPolicyHandle::default(),
+            jwt_validator: None,
+            client_identities: Arc::default(),
+            receipts: None,
+            events: None,
+        };
+        let server = GrpcServer::from_config(service, status, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let task = tokio::spawn(server.run());
+
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = OtpServiceClient::new(channel.clone());
+
+        // Calls need credentials with the method's scope
+        let status = client
+            .generate_secret(GenerateSecretRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(error_code(&status), "unauthorized");
+        let secret = client
+            .generate_secret(authorized(GenerateSecretRequest {}, "acme"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(secret.secret.len(), 40);
+
+        // Enroll, then verify the user's current code; the same code again is a replay
+        let enrollment = client
+            .enroll(authorized(
+                EnrollRequest {
+                    user_id: "alice".to_string(),
+                    pin: None,
+                },
+                "acme",
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+        let otp = client
+            .generate_totp(authorized(
+                GenerateTotpRequest {
+                    secret: enrollment.secret.clone(),
+                },
+                "acme",
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .otp;
+        let expected = Totp::new(hex::decode(&enrollment.secret).unwrap(), 6, 30)
+            .generate()
+            .unwrap();
+        assert_eq!(otp, expected);
+        let verify = |tenant: &str| {
+            authorized(
+                VerifyTotpRequest {
+                    secret: enrollment.secret.clone(),
+                    otp: otp.clone(),
+                    credential_id: None,
+                    user_id: Some("alice".to_string()),
+                    receipt: false,
+                },
+                tenant,
+            )
+        };
+        let result = client
+            .verify_totp(verify("acme"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(result.valid);
+        assert_eq!(result.reason, "valid");
+        let result = client
+            .verify_totp(verify("acme"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!result.valid);
+        assert_eq!(result.reason, "reused");
+        // Replay protection is per tenant, as over HTTP
+        let result = client
+            .verify_totp(verify("other"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(result.reason, "valid");
+
+        // RFC 4226 test vector
+        let hotp = VerifyHotpRequest {
+            secret: "3132333435363738393031323334353637383930".to_string(),
+            otp: "287082".to_string(),
+            counter: 1,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
+        };
+        let result = client
+            .verify_hotp(authorized(hotp.clone(), "acme"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(result.valid);
+
+        // Errors carry the status and stable code of their REST counterparts
+        let status = client
+            .verify_hotp(authorized(
+                VerifyHotpRequest {
+                    secret: "not hex".to_string(),
+                    counter: 2,
+                    ..hotp.clone()
+                },
+                "acme",
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(error_code(&status), "validation_error");
+        let status = client
+            .verify_hotp(authorized(
+                VerifyHotpRequest {
+                    receipt: true,
+                    ..hotp
+                },
+                "acme",
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.message(), "Verification receipts are not enabled");
+
+        // The OTP service reports serving once storage is ready
+        let mut health = HealthClient::new(channel.clone());
+        let mut serving = None;
+        for _ in 0..50 {
+            let check = health
+                .check(HealthCheckRequest {
+                    service: "otp.v1.OtpService".to_string(),
+                })
+                .await;
+            if let Ok(response) = check {
+                serving = Some(response.into_inner().status);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(serving, Some(ServingStatus::Serving as i32));
+
+        // Reflection lists the services
+        let mut reflection = ServerReflectionClient::new(channel);
+        let request = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(MessageRequest::ListServices(String::new())),
+        };
+        let mut responses = reflection
+            .server_reflection_info(tokio_stream::iter(vec![request]))
+            .await
+            .unwrap()
+            .into_inner();
+        let response = responses.message().await.unwrap().unwrap();
+        let services: Vec<String> = match response.message_response {
+            Some(MessageResponse::ListServicesResponse(list)) => {
+                list.service.into_iter().map(|s| s.name).collect()
+            }
+            other => panic!("unexpected reflection response {:?}", other),
+        };
+        assert!(services.contains(&"otp.v1.OtpService".to_string()));
+        assert!(services.contains(&"grpc.health.v1.Health".to_string()));
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_calls_over_mutual_tls() {
+        use crate::server::tls::{self, tests::TestPki};
+        use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+        let pki = TestPki::new();
+        let dir = std::env::temp_dir().join(format!("otp-grpc-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        };
+        let server_cert = pki.issue(vec!["localhost".to_string()], "localhost");
+        let identities = serde_json::json!({
+            "identities": [{ "common_name": "billing", "tenant": "acme", "scopes": ["generate"] }]
+        });
+        let config = Config {
+            api_auth_enabled: true,
+            grpc_bind: Some("127.0.0.1:0".to_string()),
+            tls_cert_file: Some(write("server.pem", &server_cert.cert_pem)),
+            tls_key_file: Some(write("server.key", &server_cert.key_pem)),
+            tls_client_ca_file: Some(write("ca.pem", &pki.ca_pem)),
+            tls_client_identities_file: Some(write("identities.json", &identities.to_string())),
+            tls_reload_seconds: 0,
+            ..Config::default()
+        };
+        let tls = tls::server_config(&config).unwrap();
+        let service = OtpGrpc {
+            client_identities: Arc::new(ClientIdentities::from_config(&config).unwrap()),
+            config: Arc::new(config),
+            storage: Arc::new(MemoryStore::new()),
+            access_policy: AccessPolicyHandle::default(),
+            jwt_validator: None,
+            receipts: None,
+            events: None,
+        };
+        let server = GrpcServer::from_config(service, StorageStatus::default(), tls)
+            .await
+            .unwrap()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let task = tokio::spawn(server.run());
+
+        // Plaintext clients can't talk to the TLS listener
+        let plaintext = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        assert!(OtpServiceClient::new(plaintext)
+            .generate_secret(GenerateSecretRequest {})
+            .await
+            .is_err());
+
+        // A mapped client certificate authenticates the call without an API key
+        let client = pki.issue(Vec::new(), "billing");
+        let channel = Channel::from_shared(format!("https://localhost:{}", addr.port()))
+            .unwrap()
+            .tls_config(
+                ClientTlsConfig::new()
+                    .ca_certificate(Certificate::from_pem(&pki.ca_pem))
+                    .identity(Identity::from_pem(&client.cert_pem, &client.key_pem))
+                    .domain_name("localhost"),
+            )
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = OtpServiceClient::new(channel);
+        let secret = client
+            .generate_secret(GenerateSecretRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(secret.secret.len(), 40);
+        // ...with the identity's scopes only
+        let status = client
+            .enroll(EnrollRequest {
+                user_id: "alice".to_string(),
+                pin: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        task.abort();
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,72 +1,371 @@
-use crate::config::Config;
-use crate::error::{AppError, AppResult};
+use crate::api_keys::{self, ApiClient};
+use crate::audit::{Action, AuditEvent};
+use crate::config::{Config, FailurePolicy};
+use crate::enrollment;
+use crate::error::{AppError, AppResult, Problem};
+use crate::events::{self, Events};
+use crate::health::{self, HealthReport, HealthStatus};
+use crate::lockout::{self, LockoutPolicy, Subject};
+use crate::metrics::{metrics, OtpType, Outcome};
 use crate::otp::{hotp::Hotp, totp::Totp}; // Import Hotp
-use crate::storage::OtpStore;
+use crate::receipts::{self, ReceiptSigner, Verification};
+use crate::secrets::SecretBox;
+use crate::server::client_ip::ClientIp;
+use crate::server::tenant::{validate_tenant_id, Tenant};
+use crate::storage::{OtpStore, StorageState, StorageStatus};
+use crate::stuffing::{self, Source, StuffingDetector};
+use crate::telemetry;
 use actix_web::{web, HttpResponse};
 use data_encoding::BASE32;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use otp_types::{
+    ApiKeyResponse, CreateApiKeyRequest, EnrollRequest, EnrollmentResponse, GenerateHotpRequest,
+    GenerateHotpResponse, GenerateOtpRequest, GenerateOtpResponse, GenerateSecretResponse,
+    PurgeTenantResponse, UnlockRequest, VerifyHotpRequest, VerifyHotpResponse, VerifyOtpRequest,
+    VerifyOtpResponse,
+};
 use rand::Rng;
-use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+impl From<api_keys::IssuedKey> for ApiKeyResponse {
+    fn from(issued: api_keys::IssuedKey) -> Self {
+        Self {
+            id: issued.record.id,
+            api_key: issued.api_key,
+            tenant: issued.record.tenant,
+            scopes: issued.record.scopes,
+            require_signature: issued.record.require_signature,
+            signing_secret: issued.signing_secret,
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenerateSecretResponse {
-    secret: String,
-    secret_base32: String,
+/// Key for secrets kept in storage. It is checked at startup, so this only fails when
+/// the server was built without going through that check.
+pub fn secret_box(config: &Config) -> AppResult<SecretBox> {
+    SecretBox::from_config(config).map_err(AppError::Internal)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenerateOtpRequest {
-    secret: String,
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenerateOtpResponse {
-    otp: String,
-    expires_in: u64,
+/// Refuse admin actions on tenants outside the caller's API key binding.
+/// `None` stands for keys that span tenants. Without authentication there is no binding.
+fn ensure_tenant_access(
+    client: &Option<web::ReqData<ApiClient>>,
+    tenant: Option<&str>,
+) -> AppResult<()> {
+    match client {
+        Some(client) if !client.can_access(tenant) => Err(AppError::Forbidden(
+            "API key is not valid for this tenant".to_string(),
+        )),
+        _ => Ok(()),
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct VerifyOtpRequest {
-    secret: String,
-    otp: String,
+/// Apply the storage failure policy to an error from a storage operation.
+///
+/// Fail-closed turns the error into a response; fail-open raises an alert and lets
+/// the caller carry on without the protection the operation provided.
+fn on_storage_error(config: &Config, what: &str, error: String) -> AppResult<()> {
+    match config.storage_failure_policy {
+        FailurePolicy::Closed => Err(AppError::StorageUnavailable(format!(
+            "Storage error: {}",
+            error
+        ))),
+        FailurePolicy::Open => {
+            log::error!(
+                "ALERT: storage unavailable, {} skipped (fail-open): {}",
+                what,
+                error
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Result of a replay-protection lookup after the storage failure policy is applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReplayCheck {
+    /// The code has not been used yet
+    Fresh,
+    /// The code has already been used
+    Used,
+    /// Storage is unavailable and the fail-open policy allows verifying without replay protection
+    Unavailable,
+}
+
+/// Look up a replay key, applying the configured storage failure policy on errors
+async fn check_replay(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    tenant: &Tenant,
+    key: &str,
+) -> AppResult<ReplayCheck> {
+    match storage.is_used(tenant.as_str(), key).await {
+        Ok(true) => Ok(ReplayCheck::Used),
+        Ok(false) => Ok(ReplayCheck::Fresh),
+        Err(e) => {
+            on_storage_error(config, "replay protection", e)?;
+            Ok(ReplayCheck::Unavailable)
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct VerifyOtpResponse {
+/// Record a replay key as used, applying the configured storage failure policy on errors
+async fn record_replay(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    tenant: &Tenant,
+    check: ReplayCheck,
+    key: &str,
+) -> AppResult<()> {
+    // Storage was already down during the lookup; don't wait on it again
+    if check == ReplayCheck::Unavailable {
+        return Ok(());
+    }
+
+    match storage
+        .mark_used(tenant.as_str(), key, config.otp_expiry_seconds)
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(e) => on_storage_error(config, "replay protection", e),
+    }
+}
+
+/// Reject the attempt with 423 if any of its subjects is locked out
+async fn enforce_lockout(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    tenant: &Tenant,
+    subjects: &[Subject],
+) -> AppResult<()> {
+    let policy = LockoutPolicy::from_config(config);
+    match policy
+        .locked_for(storage.as_ref(), tenant.as_str(), subjects)
+        .await
+    {
+        Ok(None) => Ok(()),
+        Ok(Some(retry_after)) => Err(AppError::Locked {
+            message: "Too many failed attempts, try again later".to_string(),
+            retry_after,
+        }),
+        Err(e) => on_storage_error(config, "lockout check", e),
+    }
+}
+
+/// Update failed-attempt counters with the outcome of a verification
+async fn record_attempt(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    subjects: &[Subject],
     valid: bool,
+) -> AppResult<()> {
+    let policy = LockoutPolicy::from_config(config);
+    let result = if valid {
+        policy
+            .record_success(storage.as_ref(), tenant.as_str(), subjects)
+            .await
+            .map(|()| None)
+    } else {
+        policy
+            .record_failure(storage.as_ref(), tenant.as_str(), subjects)
+            .await
+    };
+
+    match result {
+        Ok(None) => Ok(()),
+        Ok(Some(seconds)) => {
+            events::record(
+                events,
+                subject_event(Action::Lockout, tenant, subjects)
+                    .detail(format!("locked for {}s", seconds)),
+            )
+            .await
+        }
+        Err(e) => on_storage_error(config, "failed-attempt tracking", e),
+    }
+}
+
+/// Audit event naming the credential and user of lockout subjects
+fn subject_event(action: Action, tenant: &Tenant, subjects: &[Subject]) -> AuditEvent {
+    subjects.iter().fold(
+        AuditEvent::new(action, Some(tenant.as_str())),
+        |event, subject| match subject {
+            Subject::Credential(id) => event.credential_id(Some(id)),
+            Subject::User(id) => event.user_id(Some(id)),
+        },
+    )
+}
+
+/// Key ID of the API client making the request
+fn actor(client: &Option<web::ReqData<ApiClient>>) -> Option<&str> {
+    client.as_ref().map(|client| client.key_id.as_str())
+}
+
+/// Refuse receipt requests up front when no signing key is configured
+pub fn ensure_receipts_available(
+    requested: bool,
+    receipts: Option<&ReceiptSigner>,
+) -> AppResult<()> {
+    if requested && receipts.is_none() {
+        return Err(AppError::Validation(
+            "Verification receipts are not enabled".to_string(),
+        ));
+    }
+    Ok(())
 }
 
-// --- HOTP Structs ---
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenerateHotpRequest {
-    secret: String,
-    counter: u64,
+/// Sign a receipt for a successful verification, if one was requested
+pub fn issue_receipt(
+    receipts: Option<&ReceiptSigner>,
+    requested: bool,
+    verification: Verification<'_>,
+) -> AppResult<Option<String>> {
+    match receipts {
+        Some(signer) if requested => signer
+            .sign(&verification, unix_now())
+            .map(Some)
+            .map_err(AppError::Internal),
+        _ => Ok(None),
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenerateHotpResponse {
-    otp: String,
+/// Sources a verification attempt is tracked under for credential-stuffing detection
+fn stuffing_sources(config: &Config, client_ip: ClientIp) -> Vec<Source> {
+    if !StuffingDetector::from_config(config).enabled() {
+        return Vec::new();
+    }
+    client_ip
+        .0
+        .map(|ip| stuffing::sources(config, ip))
+        .unwrap_or_default()
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)] // Added Clone
-pub struct VerifyHotpRequest {
-    secret: String,
-    otp: String,
-    counter: u64,
+/// Reject the attempt with 429 if its source is blocked for credential stuffing
+async fn enforce_source_block(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    sources: &[Source],
+) -> AppResult<()> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+
+    let detector = StuffingDetector::from_config(config);
+    match detector.blocked_for(storage.as_ref(), sources).await {
+        Ok(None) => Ok(()),
+        Ok(Some(retry_after)) => Err(AppError::TooManyRequests {
+            message: "Too many failed attempts from this network, try again later".to_string(),
+            retry_after,
+        }),
+        Err(e) => on_storage_error(config, "credential-stuffing check", e),
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct VerifyHotpResponse {
+/// Count the outcome of a verification against its sources, raising any resulting alerts
+async fn record_source_attempt(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    tenant: &Tenant,
+    sources: &[Source],
     valid: bool,
+) -> AppResult<()> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+
+    let detector = StuffingDetector::from_config(config);
+    match detector
+        .record(storage.as_ref(), tenant.as_str(), sources, !valid)
+        .await
+    {
+        Ok(alerts) => {
+            alerts.iter().for_each(|alert| alert.emit());
+            Ok(())
+        }
+        Err(e) => on_storage_error(config, "credential-stuffing tracking", e),
+    }
 }
-// --- End HOTP Structs ---
 
-/// Generate a new random secret
-pub async fn generate_secret() -> AppResult<HttpResponse> {
+/// Run a verification in its own span, counting, logging and auditing its outcome
+async fn traced_verification(
+    otp_type: OtpType,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    credential_id: &str,
+    user_id: Option<&str>,
+    verification: impl std::future::Future<Output = AppResult<Outcome>>,
+) -> AppResult<Outcome> {
+    let attributes = vec![
+        KeyValue::new("otp.type", otp_type.as_str()),
+        KeyValue::new("tenant", tenant.as_str().to_string()),
+    ];
+    telemetry::in_span("otp.verify", SpanKind::Internal, attributes, async {
+        let result = verification.await;
+        let outcome = metrics().verified(otp_type, &result);
+        telemetry::set_attribute(KeyValue::new("otp.outcome", outcome.as_str()));
+
+        let level = match outcome {
+            Outcome::Valid | Outcome::Invalid => log::Level::Info,
+            Outcome::Reused | Outcome::Locked | Outcome::Error => log::Level::Warn,
+        };
+        log::log!(
+            level,
+            tenant = tenant.as_str(),
+            credential_id,
+            otp_type = otp_type.as_str(),
+            outcome = outcome.as_str();
+            "OTP verification"
+        );
+
+        let audited = events::record(
+            events,
+            AuditEvent::new(Action::Verification, Some(tenant.as_str()))
+                .user_id(user_id)
+                .credential_id(Some(credential_id))
+                .outcome(outcome.as_str())
+                .detail(otp_type.as_str().to_string()),
+        )
+        .await;
+        result.and_then(|outcome| audited.map(|()| outcome))
+    })
+    .await
+}
+
+/// A new random 160-bit secret
+pub fn new_secret() -> Vec<u8> {
     // Use recommended way to get thread-local RNG
     let mut rng = rand::rngs::ThreadRng::default();
     let mut secret = vec![0u8; 20];
     rng.fill(&mut secret[..]);
+    secret
+}
+
+/// Generate a new random secret
+#[utoipa::path(
+    post,
+    path = "/api/v2/secret",
+    tag = "Secrets",
+    responses(
+        (status = 200, description = "New secret", body = GenerateSecretResponse),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Rate limited, or source blocked for credential stuffing", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn generate_secret() -> AppResult<HttpResponse> {
+    let secret = new_secret();
 
     // Encode the secret in base32 for easy sharing
     let secret_base32 = BASE32.encode(&secret);
@@ -79,20 +378,41 @@ pub async fn generate_secret() -> AppResult<HttpResponse> {
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Generate an OTP for the given secret
-pub async fn generate_otp(
-    config: web::Data<Arc<Config>>,
-    req: web::Json<GenerateOtpRequest>,
-) -> AppResult<HttpResponse> {
+/// Current TOTP for a hex-encoded secret
+pub fn totp_code(config: &Config, secret: &str) -> AppResult<String> {
     // Decode the secret from hex
-    let secret = hex::decode(&req.secret)
-        .map_err(|e| AppError::Validation(format!("Invalid secret: {}", e)))?; // Updated to AppError::Validation
+    let secret =
+        hex::decode(secret).map_err(|e| AppError::Validation(format!("Invalid secret: {}", e)))?; // Updated to AppError::Validation
 
     // Create a TOTP instance
     let totp = Totp::new(secret, config.otp_length, config.otp_expiry_seconds);
 
     // Generate the OTP
-    let otp = totp.generate()?;
+    let otp = totp.generate();
+    metrics().generated(OtpType::Totp, otp.is_ok());
+    otp
+}
+
+/// Generate an OTP for the given secret
+#[utoipa::path(
+    post,
+    path = "/api/v2/otp/generate",
+    tag = "TOTP",
+    request_body = GenerateOtpRequest,
+    responses(
+        (status = 200, description = "Current code for the secret", body = GenerateOtpResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Rate limited, or source blocked for credential stuffing", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn generate_otp(
+    config: web::Data<Arc<Config>>,
+    req: web::Json<GenerateOtpRequest>,
+) -> AppResult<HttpResponse> {
+    let otp = totp_code(&config, &req.secret)?;
 
     let response = GenerateOtpResponse {
         otp,
@@ -102,48 +422,172 @@ pub async fn generate_otp(
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Verify an OTP against the given secret
-pub async fn verify_otp(
-    config: web::Data<Arc<Config>>,
-    storage: web::Data<Arc<dyn OtpStore>>,
-    req: web::Json<VerifyOtpRequest>,
-) -> AppResult<HttpResponse> {
+/// A TOTP to check and what its failed attempts count against
+pub struct TotpCheck<'a> {
+    /// Hex-encoded secret
+    pub secret: &'a str,
+    pub otp: &'a str,
+    pub credential_id: Option<&'a str>,
+    pub user_id: Option<&'a str>,
+    /// Whether the PIN sent with the code matched; a mismatch fails the check like a
+    /// wrong code. `true` where no PIN is involved.
+    pub pin_matches: bool,
+}
+
+/// Check a TOTP with credential-stuffing blocks, lockouts and replay protection.
+///
+/// Shared by the verify endpoint and the other front ends that accept codes, so every
+/// way in is subject to the same limits.
+pub async fn check_totp(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    client_ip: ClientIp,
+    check: TotpCheck<'_>,
+) -> AppResult<Outcome> {
+    traced_verification(
+        OtpType::Totp,
+        events,
+        tenant,
+        &lockout::credential_id(config, check.secret, check.credential_id),
+        check.user_id,
+        totp_outcome(config, storage, events, tenant, client_ip, check),
+    )
+    .await
+}
+
+async fn totp_outcome(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    client_ip: ClientIp,
+    check: TotpCheck<'_>,
+) -> AppResult<Outcome> {
+    // Refuse blocked sources and locked-out credentials and users before looking at the code
+    let sources = stuffing_sources(config, client_ip);
+    enforce_source_block(config, storage, &sources).await?;
+    let subjects = lockout::subjects(config, check.secret, check.credential_id, check.user_id);
+    enforce_lockout(config, storage, tenant, &subjects).await?;
+
     // Check if OTP has been used before
-    let is_used = storage
-        .is_used(&req.otp)
-        .await
-        .map_err(|e| AppError::Internal(format!("Storage error: {}", e)))?; // Updated to AppError::Internal
+    let replay = check_replay(config, storage, tenant, check.otp).await?;
 
-    if is_used {
-        log::warn!("OTP reuse attempt detected: {}", req.otp);
-        return Ok(HttpResponse::Ok().json(VerifyOtpResponse { valid: false }));
+    if replay == ReplayCheck::Used {
+        return Ok(Outcome::Reused);
     }
 
     // Decode the secret from hex
-    let secret = hex::decode(&req.secret)
+    let secret = hex::decode(check.secret)
         .map_err(|e| AppError::Validation(format!("Invalid secret: {}", e)))?; // Updated to AppError::Validation
 
     // Create a TOTP instance
     let totp = Totp::new(secret, config.otp_length, config.otp_expiry_seconds);
 
-    // Verify the OTP
-    let valid = totp.verify(&req.otp)?;
+    // Verify the OTP; the code is checked even when the PIN is wrong, so responses don't
+    // reveal which of the two was
+    let valid = totp.verify(check.otp)? && check.pin_matches;
+    record_attempt(config, storage, events, tenant, &subjects, valid).await?;
+    record_source_attempt(config, storage, tenant, &sources, valid).await?;
 
     // If OTP is valid, mark it as used
     if valid {
-        storage
-            .mark_used(&req.otp, config.otp_expiry_seconds)
-            .await
-            .map_err(|e| AppError::Internal(format!("Storage error: {}", e)))?; // Updated to AppError::Internal
-        log::debug!("OTP marked as used: {}", req.otp);
+        record_replay(config, storage, tenant, replay, check.otp).await?;
+        log::debug!("OTP marked as used");
+    }
+
+    Ok(if valid {
+        Outcome::Valid
+    } else {
+        Outcome::Invalid
+    })
+}
+
+/// Verify an OTP against the given secret
+#[utoipa::path(
+    post,
+    path = "/api/v2/otp/verify",
+    tag = "TOTP",
+    params(Tenant),
+    request_body = VerifyOtpRequest,
+    responses(
+        (status = 200, description = "Verification result; `reason` says why a code was refused", body = VerifyOtpResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 423, description = "Credential or user locked out after failed attempts", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Rate limited, or source blocked for credential stuffing", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn verify_otp(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    tenant: Tenant,
+    client_ip: ClientIp,
+    receipts: Option<web::Data<ReceiptSigner>>,
+    events: Option<web::Data<Events>>,
+    req: web::Json<VerifyOtpRequest>,
+) -> AppResult<HttpResponse> {
+    let receipts = receipts.as_ref().map(|r| r.get_ref());
+    ensure_receipts_available(req.receipt, receipts)?;
+
+    let outcome = check_totp(
+        &config,
+        &storage,
+        events.as_ref().map(|e| e.get_ref()),
+        &tenant,
+        client_ip,
+        TotpCheck {
+            secret: &req.secret,
+            otp: &req.otp,
+            credential_id: req.credential_id.as_deref(),
+            user_id: req.user_id.as_deref(),
+            pin_matches: true,
+        },
+    )
+    .await?;
+    let valid = outcome == Outcome::Valid;
+
+    let mut receipt = None;
+    if valid {
+        receipt = issue_receipt(
+            receipts,
+            req.receipt,
+            Verification {
+                tenant: tenant.as_str(),
+                user_id: req.user_id.as_deref(),
+                credential_id: &lockout::credential_id(
+                    &config,
+                    &req.secret,
+                    req.credential_id.as_deref(),
+                ),
+                method: receipts::Method::Totp,
+            },
+        )?;
     }
 
-    let response = VerifyOtpResponse { valid };
+    let response = VerifyOtpResponse {
+        valid,
+        reason: outcome.as_str().to_string(),
+        receipt,
+    };
 
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Health check endpoint
+/// Health check endpoint (liveness): the process is up and serving HTTP
+#[utoipa::path(
+    get,
+    path = "/api/v2/health",
+    tag = "Health",
+    responses(
+        (status = 200, description = "The process is up", body = Object,
+            example = json!({ "status": "ok", "version": "0.1.0" })),
+    ),
+)]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().json(serde_json::json!({
         "status": "ok",
@@ -151,127 +595,734 @@ pub async fn health_check() -> HttpResponse {
     }))
 }
 
+/// Deep health endpoint: pings storage and checks the wall clock against the storage clock
+#[utoipa::path(
+    get,
+    path = "/api/v2/health/deep",
+    tag = "Health",
+    responses(
+        (status = 200, description = "Healthy or degraded", body = HealthReport),
+        (status = 503, description = "Unhealthy", body = HealthReport),
+    ),
+)]
+pub async fn deep_health_check(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+) -> HttpResponse {
+    let report = health::check(&config, storage.as_ref().as_ref()).await;
+
+    // Degraded still serves traffic; only unhealthy is reported as unavailable
+    if report.status == HealthStatus::Unhealthy {
+        HttpResponse::ServiceUnavailable().json(report)
+    } else {
+        HttpResponse::Ok().json(report)
+    }
+}
+
+/// Readiness endpoint: storage is answering and the server can verify OTPs
+#[utoipa::path(
+    get,
+    path = "/api/v2/ready",
+    tag = "Health",
+    responses(
+        (status = 200, description = "Ready to verify codes", body = Object,
+            example = json!({ "status": "ready", "storage": { "state": "ready", "error": null } })),
+        (status = 503, description = "Storage has not connected or stopped answering", body = Object,
+            example = json!({ "status": "not_ready", "storage": { "state": "connecting", "error": null } })),
+    ),
+)]
+pub async fn readiness_check(status: web::Data<StorageStatus>) -> HttpResponse {
+    let state = status.state();
+    let body = serde_json::json!({
+        "status": if state == StorageState::Ready { "ready" } else { "not_ready" },
+        "storage": {
+            "state": state,
+            "error": status.last_error(),
+        },
+    });
+
+    if state == StorageState::Ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Public keys verification receipts are signed with; empty when receipts are disabled
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "Receipts",
+    responses(
+        (status = 200, description = "JSON Web Key Set of the receipt signing keys", body = Object,
+            example = json!({ "keys": [] })),
+    ),
+)]
+pub async fn receipt_jwks(receipts: Option<web::Data<ReceiptSigner>>) -> HttpResponse {
+    let keys = receipts
+        .map(|signer| signer.jwks())
+        .unwrap_or(jsonwebtoken::jwk::JwkSet { keys: Vec::new() });
+
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(keys)
+}
+
 // --- HOTP Handlers ---
 
-/// Generate an HOTP for the given secret and counter
-pub async fn generate_hotp(
-    config: web::Data<Arc<Config>>,
-    req: web::Json<GenerateHotpRequest>,
-) -> AppResult<HttpResponse> {
+/// HOTP for a hex-encoded secret and counter
+pub fn hotp_code(config: &Config, secret: &str, counter: u64) -> AppResult<String> {
     // Decode the secret from hex
-    let secret = hex::decode(&req.secret)
-        .map_err(|e| AppError::Validation(format!("Invalid secret: {}", e)))?;
+    let secret =
+        hex::decode(secret).map_err(|e| AppError::Validation(format!("Invalid secret: {}", e)))?;
 
     // Create an HOTP instance
     let hotp = Hotp::new(secret, config.otp_length);
 
     // Generate the HOTP
-    let otp = hotp.generate(req.counter)?;
+    let otp = hotp.generate(counter);
+    metrics().generated(OtpType::Hotp, otp.is_ok());
+    otp
+}
+
+/// Generate an HOTP for the given secret and counter
+#[utoipa::path(
+    post,
+    path = "/api/v2/hotp/generate",
+    tag = "HOTP",
+    request_body = GenerateHotpRequest,
+    responses(
+        (status = 200, description = "Code for the secret and counter", body = GenerateHotpResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Rate limited, or source blocked for credential stuffing", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn generate_hotp(
+    config: web::Data<Arc<Config>>,
+    req: web::Json<GenerateHotpRequest>,
+) -> AppResult<HttpResponse> {
+    let otp = hotp_code(&config, &req.secret, req.counter)?;
 
     let response = GenerateHotpResponse { otp };
 
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Verify an HOTP against the given secret and counter
-pub async fn verify_hotp(
-    config: web::Data<Arc<Config>>,
-    storage: web::Data<Arc<dyn OtpStore>>,
-    req: web::Json<VerifyHotpRequest>,
-) -> AppResult<HttpResponse> {
+/// An HOTP to check and what its failed attempts count against
+pub struct HotpCheck<'a> {
+    /// Hex-encoded secret
+    pub secret: &'a str,
+    pub otp: &'a str,
+    pub counter: u64,
+    pub credential_id: Option<&'a str>,
+    pub user_id: Option<&'a str>,
+}
+
+/// Check an HOTP with credential-stuffing blocks, lockouts and replay protection,
+/// like [`check_totp`]
+pub async fn check_hotp(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    client_ip: ClientIp,
+    check: HotpCheck<'_>,
+) -> AppResult<Outcome> {
+    traced_verification(
+        OtpType::Hotp,
+        events,
+        tenant,
+        &lockout::credential_id(config, check.secret, check.credential_id),
+        check.user_id,
+        hotp_outcome(config, storage, events, tenant, client_ip, check),
+    )
+    .await
+}
+
+async fn hotp_outcome(
+    config: &Config,
+    storage: &Arc<dyn OtpStore>,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    client_ip: ClientIp,
+    check: HotpCheck<'_>,
+) -> AppResult<Outcome> {
+    // Refuse blocked sources and locked-out credentials and users before looking at the code
+    let sources = stuffing_sources(config, client_ip);
+    enforce_source_block(config, storage, &sources).await?;
+    let subjects = lockout::subjects(config, check.secret, check.credential_id, check.user_id);
+    enforce_lockout(config, storage, tenant, &subjects).await?;
+
     // Construct a unique key for HOTP reuse check (otp + counter) using hyphens
-    let reuse_key = format!("hotp-{}-{}", req.otp, req.counter);
+    let reuse_key = format!("hotp-{}-{}", check.otp, check.counter);
 
     // Check if this specific OTP+Counter combination has been used before
-    let is_used = storage
-        .is_used(&reuse_key)
-        .await
-        .map_err(|e| AppError::Internal(format!("Storage error: {}", e)))?;
+    let replay = check_replay(config, storage, tenant, &reuse_key).await?;
 
-    if is_used {
-        log::warn!(
-            "HOTP reuse attempt detected: otp={}, counter={}",
-            req.otp,
-            req.counter
-        );
-        return Ok(HttpResponse::Ok().json(VerifyHotpResponse { valid: false }));
+    if replay == ReplayCheck::Used {
+        return Ok(Outcome::Reused);
     }
 
     // Decode the secret from hex
-    let secret = hex::decode(&req.secret)
+    let secret = hex::decode(check.secret)
         .map_err(|e| AppError::Validation(format!("Invalid secret: {}", e)))?;
 
     // Create an HOTP instance
     let hotp = Hotp::new(secret, config.otp_length);
 
     // Verify the HOTP
-    let valid = hotp.verify(&req.otp, req.counter)?;
+    let valid = hotp.verify(check.otp, check.counter)?;
+    record_attempt(config, storage, events, tenant, &subjects, valid).await?;
+    record_source_attempt(config, storage, tenant, &sources, valid).await?;
 
     // If HOTP is valid, mark this OTP+Counter combination as used
     if valid {
         // Use OTP expiry seconds for consistency, although HOTP doesn't strictly expire
-        storage
-            .mark_used(&reuse_key, config.otp_expiry_seconds)
-            .await
-            .map_err(|e| AppError::Internal(format!("Storage error: {}", e)))?;
-        log::debug!(
-            "HOTP marked as used: otp={}, counter={}",
-            req.otp,
-            req.counter
-        );
+        record_replay(config, storage, tenant, replay, &reuse_key).await?;
+        log::debug!(counter = check.counter; "HOTP marked as used");
+    }
+
+    Ok(if valid {
+        Outcome::Valid
+    } else {
+        Outcome::Invalid
+    })
+}
+
+/// Verify an HOTP against the given secret and counter
+#[utoipa::path(
+    post,
+    path = "/api/v2/hotp/verify",
+    tag = "HOTP",
+    params(Tenant),
+    request_body = VerifyHotpRequest,
+    responses(
+        (status = 200, description = "Verification result; `reason` says why a code was refused", body = VerifyHotpResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 423, description = "Credential or user locked out after failed attempts", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Rate limited, or source blocked for credential stuffing", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn verify_hotp(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    tenant: Tenant,
+    client_ip: ClientIp,
+    receipts: Option<web::Data<ReceiptSigner>>,
+    events: Option<web::Data<Events>>,
+    req: web::Json<VerifyHotpRequest>,
+) -> AppResult<HttpResponse> {
+    let receipts = receipts.as_ref().map(|r| r.get_ref());
+    ensure_receipts_available(req.receipt, receipts)?;
+
+    let outcome = check_hotp(
+        &config,
+        &storage,
+        events.as_ref().map(|e| e.get_ref()),
+        &tenant,
+        client_ip,
+        HotpCheck {
+            secret: &req.secret,
+            otp: &req.otp,
+            counter: req.counter,
+            credential_id: req.credential_id.as_deref(),
+            user_id: req.user_id.as_deref(),
+        },
+    )
+    .await?;
+    let valid = outcome == Outcome::Valid;
+
+    let mut receipt = None;
+    if valid {
+        receipt = issue_receipt(
+            receipts,
+            req.receipt,
+            Verification {
+                tenant: tenant.as_str(),
+                user_id: req.user_id.as_deref(),
+                credential_id: &lockout::credential_id(
+                    &config,
+                    &req.secret,
+                    req.credential_id.as_deref(),
+                ),
+                method: receipts::Method::Hotp,
+            },
+        )?;
     }
 
-    let response = VerifyHotpResponse { valid };
+    let response = VerifyHotpResponse {
+        valid,
+        reason: outcome.as_str().to_string(),
+        receipt,
+    };
 
     Ok(HttpResponse::Ok().json(response))
 }
 
 // --- End HOTP Handlers ---
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::Config;
-    use actix_web::{body::to_bytes, http::StatusCode, test, web, App}; // Added to_bytes
-    use async_trait::async_trait;
-    use dashmap::DashMap;
-    use std::sync::Arc;
+// --- Admin Handlers ---
+
+/// Delete all stored data for a tenant
+#[utoipa::path(
+    delete,
+    path = "/api/v2/admin/tenants/{tenant}",
+    tag = "Admin",
+    params(("tenant" = String, Path, description = "Tenant to purge")),
+    responses(
+        (status = 200, description = "Tenant purged", body = PurgeTenantResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn purge_tenant(
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let tenant = path.into_inner();
+    validate_tenant_id(&tenant)?;
+    ensure_tenant_access(&client, Some(&tenant))?;
+
+    let deleted = storage
+        .purge_tenant(&tenant)
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    log::info!("Purged tenant {}: {} keys deleted", tenant, deleted);
+    events::record(
+        events.as_ref().map(|e| e.get_ref()),
+        AuditEvent::new(Action::TenantPurged, Some(&tenant))
+            .actor(actor(&client))
+            .detail(format!("{} keys deleted", deleted)),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PurgeTenantResponse { tenant, deleted }))
+}
 
-    // Mock OtpStore for testing handlers in isolation
-    #[derive(Debug)]
-    struct MockOtpStore {
-        used_otps: DashMap<String, ()>,
+/// Lift a lockout on a credential and/or user
+#[utoipa::path(
+    post,
+    path = "/api/v2/admin/unlock",
+    tag = "Admin",
+    params(Tenant),
+    request_body = UnlockRequest,
+    responses(
+        (status = 200, description = "Lockout lifted", body = Object,
+            example = json!({ "unlocked": true })),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn unlock(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    tenant: Tenant,
+    req: web::Json<UnlockRequest>,
+) -> AppResult<HttpResponse> {
+    let mut subjects = Vec::new();
+    if let Some(credential_id) = &req.credential_id {
+        subjects.push(Subject::Credential(credential_id.clone()));
+    } else if let Some(secret) = &req.secret {
+        subjects.push(Subject::Credential(lockout::credential_fingerprint(
+            &config.replay_key_pepper,
+            secret,
+        )));
+    }
+    if let Some(user_id) = &req.user_id {
+        subjects.push(Subject::User(user_id.clone()));
+    }
+    if subjects.is_empty() {
+        return Err(AppError::Validation(
+            "One of credential_id, secret or user_id is required".to_string(),
+        ));
     }
 
-    impl MockOtpStore {
-        fn new() -> Self {
-            Self {
-                used_otps: DashMap::new(),
-            }
-        }
+    LockoutPolicy::from_config(&config)
+        .unlock(storage.as_ref().as_ref(), tenant.as_str(), &subjects)
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    log::info!("Unlocked {:?} in tenant {}", subjects, tenant.as_str());
+    events::record(
+        events.as_ref().map(|e| e.get_ref()),
+        subject_event(Action::Unlock, &tenant, &subjects).actor(actor(&client)),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "unlocked": true })))
+}
+
+/// Issue an API key
+#[utoipa::path(
+    post,
+    path = "/api/v2/admin/api-keys",
+    tag = "Admin",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 201, description = "Key created; the plaintext key and signing secret are only returned here", body = ApiKeyResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn create_api_key(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    req: web::Json<CreateApiKeyRequest>,
+) -> AppResult<HttpResponse> {
+    let req = req.into_inner();
+    if let Some(tenant) = &req.tenant {
+        validate_tenant_id(tenant)?;
     }
+    if req.scopes.is_empty() {
+        return Err(AppError::Validation(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    ensure_tenant_access(&client, req.tenant.as_deref())?;
+
+    let issued = api_keys::create(
+        storage.as_ref().as_ref(),
+        &secret_box(&config)?,
+        req.tenant,
+        req.scopes,
+        req.require_signature,
+        unix_now(),
+    )
+    .await
+    .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    log::info!(
+        "Created API key {} for tenant {:?} with scopes {:?}",
+        issued.record.id,
+        issued.record.tenant,
+        issued.record.scopes
+    );
+    events::record(
+        events.as_ref().map(|e| e.get_ref()),
+        AuditEvent::new(Action::ApiKeyCreated, issued.record.tenant.as_deref())
+            .actor(actor(&client))
+            .api_key_id(&issued.record.id),
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(ApiKeyResponse::from(issued)))
+}
 
-    #[async_trait]
-    impl OtpStore for MockOtpStore {
-        async fn mark_used(&self, otp_key: &str, _expiry_seconds: u64) -> Result<(), String> {
-            self.used_otps.insert(otp_key.to_string(), ());
-            Ok(())
-        }
+/// Look up a key an admin action applies to, enforcing the caller's tenant binding
+async fn api_key_for_admin(
+    storage: &dyn OtpStore,
+    client: &Option<web::ReqData<ApiClient>>,
+    id: &str,
+) -> AppResult<api_keys::ApiKeyRecord> {
+    let record = api_keys::get(storage, id)
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Unknown API key: {}", id)))?;
+    ensure_tenant_access(client, record.tenant.as_deref())?;
+    Ok(record)
+}
+
+/// Replace an API key's secret; the old key stays valid for the rotation grace period
+#[utoipa::path(
+    post,
+    path = "/api/v2/admin/api-keys/{id}/rotate",
+    tag = "Admin",
+    params(("id" = String, Path, description = "API key ID")),
+    responses(
+        (status = 200, description = "Key rotated; the plaintext key and signing secret are only returned here", body = ApiKeyResponse),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown API key", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The previous key is still in its rotation grace period", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn rotate_api_key(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let id = path.into_inner();
+    let record = api_key_for_admin(storage.as_ref().as_ref(), &client, &id).await?;
+    // Rotating again would cut off the previous secret before its grace period ends
+    let now = unix_now();
+    if let Some(remaining) = record.rotation_grace_remaining(now) {
+        return Err(AppError::Conflict(format!(
+            "API key {} was rotated recently; its previous secret stays valid for another {}s",
+            id, remaining
+        )));
+    }
+
+    let issued = api_keys::rotate(
+        storage.as_ref().as_ref(),
+        &secret_box(&config)?,
+        &id,
+        config.api_key_rotation_grace_seconds,
+        now,
+    )
+    .await
+    .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?
+    .ok_or_else(|| AppError::NotFound(format!("Unknown API key: {}", id)))?;
+    log::info!("Rotated API key {}", id);
+    events::record(
+        events.as_ref().map(|e| e.get_ref()),
+        AuditEvent::new(Action::ApiKeyRotated, record.tenant.as_deref())
+            .actor(actor(&client))
+            .api_key_id(&id),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiKeyResponse::from(issued)))
+}
+
+/// Revoke an API key immediately
+#[utoipa::path(
+    delete,
+    path = "/api/v2/admin/api-keys/{id}",
+    tag = "Admin",
+    params(("id" = String, Path, description = "API key ID")),
+    responses(
+        (status = 200, description = "Key revoked", body = Object,
+            example = json!({ "revoked": true })),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown API key", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn revoke_api_key(
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let id = path.into_inner();
+    let record = api_key_for_admin(storage.as_ref().as_ref(), &client, &id).await?;
+
+    api_keys::revoke(storage.as_ref().as_ref(), &id)
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    log::info!("Revoked API key {}", id);
+    events::record(
+        events.as_ref().map(|e| e.get_ref()),
+        AuditEvent::new(Action::ApiKeyRevoked, record.tenant.as_deref())
+            .actor(actor(&client))
+            .api_key_id(&id),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "revoked": true })))
+}
 
-        async fn is_used(&self, otp_key: &str) -> Result<bool, String> {
-            Ok(self.used_otps.contains_key(otp_key))
+/// Validate and store a new enrollment, auditing who made it
+pub async fn enroll_user(
+    storage: &Arc<dyn OtpStore>,
+    secrets: &SecretBox,
+    events: Option<&Events>,
+    tenant: &Tenant,
+    actor: Option<&str>,
+    user_id: &str,
+    pin: Option<&str>,
+) -> AppResult<enrollment::Enrollment> {
+    let user_id = user_id.trim();
+    if user_id.is_empty() || user_id.len() > 256 {
+        return Err(AppError::Validation(
+            "user_id must be 1-256 characters".to_string(),
+        ));
+    }
+    if let Some(pin) = pin {
+        if pin.len() < 4 || pin.len() > 64 {
+            return Err(AppError::Validation(
+                "pin must be 4-64 characters".to_string(),
+            ));
         }
     }
 
+    let enrollment = enrollment::enroll(
+        storage.as_ref(),
+        secrets,
+        tenant.as_str(),
+        user_id,
+        pin,
+        unix_now(),
+    )
+    .await
+    .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    log::info!("Enrolled user {} in tenant {}", user_id, tenant.as_str());
+    events::record(
+        events,
+        AuditEvent::new(Action::EnrollmentCreated, Some(tenant.as_str()))
+            .actor(actor)
+            .user_id(Some(user_id)),
+    )
+    .await?;
+    Ok(enrollment)
+}
+
+/// Enroll a user with a new TOTP secret, replacing any existing enrollment
+#[utoipa::path(
+    post,
+    path = "/api/v2/admin/enrollments",
+    tag = "Admin",
+    params(Tenant),
+    request_body = EnrollRequest,
+    responses(
+        (status = 201, description = "User enrolled; the secret is only returned here", body = EnrollmentResponse),
+        (status = 400, description = "Malformed request", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn create_enrollment(
+    config: web::Data<Arc<Config>>,
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    tenant: Tenant,
+    req: web::Json<EnrollRequest>,
+) -> AppResult<HttpResponse> {
+    let enrollment = enroll_user(
+        &storage,
+        &secret_box(&config)?,
+        events.as_ref().map(|e| e.get_ref()),
+        &tenant,
+        actor(&client),
+        &req.user_id,
+        req.pin.as_deref(),
+    )
+    .await?;
+
+    let secret = hex::decode(&enrollment.secret).unwrap_or_default();
+    let response = EnrollmentResponse {
+        otpauth_uri: enrollment.otpauth_uri(
+            &config.enrollment_issuer,
+            config.otp_length,
+            config.otp_expiry_seconds,
+        ),
+        secret_base32: BASE32.encode(&secret),
+        user_id: enrollment.user_id,
+        secret: enrollment.secret,
+    };
+
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Remove a user's enrollment, which also ends their forward-auth sessions
+#[utoipa::path(
+    delete,
+    path = "/api/v2/admin/enrollments/{user_id}",
+    tag = "Admin",
+    params(("user_id" = String, Path, description = "Enrolled user"), Tenant),
+    responses(
+        (status = 200, description = "Enrollment removed", body = Object,
+            example = json!({ "deleted": true })),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Credentials don't allow the request", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown enrollment", body = Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable under the fail-closed policy", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn delete_enrollment(
+    storage: web::Data<Arc<dyn OtpStore>>,
+    client: Option<web::ReqData<ApiClient>>,
+    events: Option<web::Data<Events>>,
+    tenant: Tenant,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let user_id = path.into_inner();
+    let existed = enrollment::remove(storage.as_ref().as_ref(), tenant.as_str(), &user_id)
+        .await
+        .map_err(|e| AppError::StorageUnavailable(format!("Storage error: {}", e)))?;
+    if !existed {
+        return Err(AppError::NotFound(format!(
+            "Unknown enrollment: {}",
+            user_id
+        )));
+    }
+    log::info!(
+        "Removed enrollment of {} in tenant {}",
+        user_id,
+        tenant.as_str()
+    );
+    events::record(
+        events.as_ref().map(|e| e.get_ref()),
+        AuditEvent::new(Action::EnrollmentDeleted, Some(tenant.as_str()))
+            .actor(actor(&client))
+            .user_id(Some(&user_id)),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "deleted": true })))
+}
+
+// --- End Admin Handlers ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::config::Config;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore as MockOtpStore;
+    use actix_web::{body::to_bytes, http::StatusCode, test, web, App}; // Added to_bytes
+    use std::sync::Arc;
+
     // Helper to create default config for tests
     fn test_config() -> Config {
         Config {
             otp_length: 6,
             otp_expiry_seconds: 30,
+            secrets_key: Some(crate::secrets::tests::KEY.to_string()),
             ..Config::default()
         }
     }
 
+    const ADMIN_KEY: &str = "handlers-admin-key";
+
+    /// Admin routes are refused unless authentication is enabled
+    fn admin_config() -> Config {
+        Config {
+            api_auth_enabled: true,
+            admin_api_key: Some(ADMIN_KEY.to_string()),
+            ..test_config()
+        }
+    }
+
+    fn as_admin(req: test::TestRequest) -> test::TestRequest {
+        req.insert_header((crate::server::rate_limit::API_KEY_HEADER, ADMIN_KEY))
+    }
+
+    fn default_tenant() -> Tenant {
+        Tenant("default".to_string())
+    }
+
     #[actix_web::test]
     async fn test_generate_hotp_handler() {
         let config = web::Data::new(Arc::new(test_config()));
@@ -306,10 +1357,23 @@ mod tests {
             secret: secret_hex.to_string(),
             otp: otp.to_string(),
             counter,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
         };
         let req = web::Json(req_payload);
 
-        let resp = verify_hotp(config, storage.clone(), req).await.unwrap();
+        let resp = verify_hotp(
+            config,
+            storage.clone(),
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
+            req,
+        )
+        .await
+        .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
 
         let body_bytes = to_bytes(resp.into_body()).await.unwrap();
@@ -318,7 +1382,79 @@ mod tests {
 
         // Verify it was marked as used (using the new key format)
         let reuse_key = format!("hotp-{}-{}", otp, counter);
-        assert!(storage.is_used(&reuse_key).await.unwrap());
+        assert!(storage.is_used("default", &reuse_key).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_verify_hotp_handler_receipt() {
+        let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let key_path =
+            std::env::temp_dir().join(format!("otp-handler-receipt-{}.pem", std::process::id()));
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+        let config = Config {
+            receipt_signing_key_files: vec![key_path.display().to_string()],
+            ..test_config()
+        };
+        let signer = ReceiptSigner::from_config(&config)
+            .unwrap()
+            .map(web::Data::new);
+        let storage = web::Data::new(Arc::new(MockOtpStore::new()) as Arc<dyn OtpStore>);
+        let payload = |otp: &str, receipt: bool| {
+            web::Json(VerifyHotpRequest {
+                secret: "3132333435363738393031323334353637383930".to_string(),
+                otp: otp.to_string(),
+                counter: 1,
+                credential_id: Some("phone".to_string()),
+                user_id: Some("alice".to_string()),
+                receipt,
+            })
+        };
+        let verify = |signer: Option<web::Data<ReceiptSigner>>, otp: &'static str, receipt| {
+            verify_hotp(
+                web::Data::new(Arc::new(test_config())),
+                storage.clone(),
+                default_tenant(),
+                ClientIp(None),
+                signer,
+                None,
+                payload(otp, receipt),
+            )
+        };
+
+        // Receipts can't be requested while disabled
+        let err = verify(None, "287082", true).await.unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)));
+
+        // Failed checks never carry a receipt
+        let resp = verify(signer.clone(), "111111", true).await.unwrap();
+        let body: VerifyHotpResponse =
+            serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap();
+        assert!(!body.valid);
+        assert!(body.receipt.is_none());
+
+        let resp = verify(signer.clone(), "287082", true).await.unwrap();
+        let body: VerifyHotpResponse =
+            serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap();
+        assert!(body.valid);
+        let receipt = body.receipt.unwrap();
+
+        // The receipt checks out against the published keys
+        let header = jsonwebtoken::decode_header(&receipt).unwrap();
+        let jwks = signer.unwrap().jwks();
+        let jwk = jwks.find(header.kid.as_deref().unwrap()).unwrap();
+        let claims: receipts::ReceiptClaims = jsonwebtoken::decode(
+            &receipt,
+            &jsonwebtoken::DecodingKey::from_jwk(jwk).unwrap(),
+            &jsonwebtoken::Validation::new(header.alg),
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims.sub.as_deref(), Some("alice"));
+        assert_eq!(claims.credential_id, "phone");
+        assert_eq!(claims.tenant, "default");
+        assert_eq!(claims.method, receipts::Method::Hotp);
+
+        std::fs::remove_file(key_path).ok();
     }
 
     #[actix_web::test]
@@ -333,10 +1469,23 @@ mod tests {
             secret: secret_hex.to_string(),
             otp: otp.to_string(),
             counter,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
         };
         let req = web::Json(req_payload);
 
-        let resp = verify_hotp(config, storage.clone(), req).await.unwrap();
+        let resp = verify_hotp(
+            config,
+            storage.clone(),
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
+            req,
+        )
+        .await
+        .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
 
         let body_bytes = to_bytes(resp.into_body()).await.unwrap();
@@ -345,7 +1494,7 @@ mod tests {
 
         // Verify it was NOT marked as used (using the new key format)
         let reuse_key = format!("hotp-{}-{}", otp, counter);
-        assert!(!storage.is_used(&reuse_key).await.unwrap());
+        assert!(!storage.is_used("default", &reuse_key).await.unwrap());
     }
 
     #[actix_web::test]
@@ -360,12 +1509,19 @@ mod tests {
             secret: secret_hex.to_string(),
             otp: otp.to_string(),
             counter,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
         };
 
         // First verification (should be valid)
         let resp1 = verify_hotp(
             config.clone(),
             storage.clone(),
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
             web::Json(req_payload.clone()),
         )
         .await
@@ -376,13 +1532,479 @@ mod tests {
         assert!(body1.valid);
 
         // Second verification (should be invalid due to reuse)
-        let resp2 = verify_hotp(config, storage, web::Json(req_payload))
-            .await
-            .unwrap();
+        let resp2 = verify_hotp(
+            config,
+            storage,
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
+            web::Json(req_payload),
+        )
+        .await
+        .unwrap();
         assert_eq!(resp2.status(), StatusCode::OK);
         let body_bytes2 = to_bytes(resp2.into_body()).await.unwrap();
         let body2: VerifyOtpResponse = serde_json::from_slice(&body_bytes2).unwrap();
         assert!(!body2.valid);
+        assert_eq!(body2.reason, "reused");
+    }
+
+    #[actix_web::test]
+    async fn test_verify_hotp_fail_closed_on_storage_outage() {
+        let config = web::Data::new(Arc::new(test_config()));
+        let faulty = FaultInjectingStore::new(Arc::new(MockOtpStore::new()));
+        faulty.set_failing(true);
+        let storage = web::Data::new(Arc::new(faulty) as Arc<dyn OtpStore>);
+
+        let req_payload = VerifyHotpRequest {
+            secret: "3132333435363738393031323334353637383930".to_string(),
+            otp: "287082".to_string(),
+            counter: 1,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
+        };
+
+        let result = verify_hotp(
+            config,
+            storage,
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
+            web::Json(req_payload),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::StorageUnavailable(_))));
+    }
+
+    #[actix_web::test]
+    async fn test_verify_hotp_fail_open_on_storage_outage() {
+        let config = web::Data::new(Arc::new(Config {
+            storage_failure_policy: FailurePolicy::Open,
+            ..test_config()
+        }));
+        let faulty = Arc::new(FaultInjectingStore::new(Arc::new(MockOtpStore::new())));
+        faulty.set_failing(true);
+        let storage = web::Data::new(faulty.clone() as Arc<dyn OtpStore>);
+
+        let req_payload = VerifyHotpRequest {
+            secret: "3132333435363738393031323334353637383930".to_string(),
+            otp: "287082".to_string(),
+            counter: 1,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
+        };
+
+        // Correct code is accepted without replay protection
+        let resp = verify_hotp(
+            config.clone(),
+            storage.clone(),
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
+            web::Json(req_payload.clone()),
+        )
+        .await
+        .unwrap();
+        let body_bytes = to_bytes(resp.into_body()).await.unwrap();
+        let body: VerifyOtpResponse = serde_json::from_slice(&body_bytes).unwrap();
+        assert!(body.valid);
+        // Lockout check, replay lookup and attempt tracking reached storage;
+        // marking the code as used was skipped
+        assert_eq!(faulty.calls(), 3);
+
+        // Wrong code is still rejected
+        let wrong_payload = VerifyHotpRequest {
+            otp: "111111".to_string(),
+            ..req_payload
+        };
+        let resp = verify_hotp(
+            config,
+            storage,
+            default_tenant(),
+            ClientIp(None),
+            None,
+            None,
+            web::Json(wrong_payload),
+        )
+        .await
+        .unwrap();
+        let body_bytes = to_bytes(resp.into_body()).await.unwrap();
+        let body: VerifyOtpResponse = serde_json::from_slice(&body_bytes).unwrap();
+        assert!(!body.valid);
+    }
+
+    #[actix_web::test]
+    async fn test_readiness_reports_storage_state() {
+        let status = StorageStatus::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(status.clone()))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        // Liveness does not depend on storage
+        let req = test::TestRequest::get().uri("/api/health").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/api/ready").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["storage"]["state"], "connecting");
+
+        status.set_failed("connection refused".to_string());
+        let req = test::TestRequest::get().uri("/api/ready").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["storage"]["state"], "failed");
+        assert_eq!(body["storage"]["error"], "connection refused");
+
+        status.set_ready();
+        let req = test::TestRequest::get().uri("/api/ready").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "ready");
+    }
+
+    #[actix_web::test]
+    async fn test_deep_health_reports_storage_outage() {
+        let faulty = Arc::new(FaultInjectingStore::new(Arc::new(MockOtpStore::new())));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(test_config())))
+                .app_data(web::Data::new(faulty.clone() as Arc<dyn OtpStore>))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/health/deep")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["components"]["storage"]["status"], "ok");
+
+        faulty.set_failing(true);
+        let req = test::TestRequest::get()
+            .uri("/api/health/deep")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["components"]["storage"]["status"], "unhealthy");
+    }
+
+    #[actix_web::test]
+    async fn test_replay_protection_is_isolated_per_tenant() {
+        let storage = Arc::new(MockOtpStore::new()) as Arc<dyn OtpStore>;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(admin_config())))
+                .app_data(web::Data::new(storage.clone()))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let payload = VerifyHotpRequest {
+            secret: "3132333435363738393031323334353637383930".to_string(),
+            otp: "287082".to_string(),
+            counter: 1,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
+        };
+        let verify = |tenant: &'static str| {
+            as_admin(test::TestRequest::post())
+                .uri("/api/hotp/verify")
+                .insert_header(("X-Tenant-Id", tenant))
+                .set_json(&payload)
+                .to_request()
+        };
+
+        let resp: VerifyOtpResponse = test::call_and_read_body_json(&app, verify("acme")).await;
+        assert!(resp.valid);
+        let resp: VerifyOtpResponse = test::call_and_read_body_json(&app, verify("acme")).await;
+        assert!(!resp.valid);
+
+        // Another tenant's replay state is independent
+        let resp: VerifyOtpResponse = test::call_and_read_body_json(&app, verify("globex")).await;
+        assert!(resp.valid);
+
+        // Purging a tenant forgets its used codes without touching other tenants
+        let req = as_admin(test::TestRequest::delete())
+            .uri("/api/admin/tenants/acme")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["deleted"], 1);
+        assert!(!storage.is_used("acme", "hotp-287082-1").await.unwrap());
+        assert!(storage.is_used("globex", "hotp-287082-1").await.unwrap());
+
+        // Invalid tenant IDs are rejected
+        let req = as_admin(test::TestRequest::post())
+            .uri("/api/hotp/verify")
+            .insert_header(("X-Tenant-Id", "_system"))
+            .set_json(&payload)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_lockout_after_repeated_failures() {
+        let config = Config {
+            lockout_max_credential_failures: 3,
+            ..admin_config()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MockOtpStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let secret = "3132333435363738393031323334353637383930";
+        let verify = |otp: &str| {
+            as_admin(test::TestRequest::post())
+                .uri("/api/hotp/verify")
+                .set_json(serde_json::json!({ "secret": secret, "otp": otp, "counter": 1 }))
+                .to_request()
+        };
+
+        for _ in 0..3 {
+            let resp: VerifyOtpResponse =
+                test::call_and_read_body_json(&app, verify("111111")).await;
+            assert!(!resp.valid);
+        }
+
+        // Locked: even the correct code is refused
+        let resp = test::call_service(&app, verify("287082")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = resp
+            .headers()
+            .get("Retry-After")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+
+        // Admin unlock by secret lifts the lockout
+        let req = as_admin(test::TestRequest::post())
+            .uri("/api/admin/unlock")
+            .set_json(serde_json::json!({ "secret": secret }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp: VerifyOtpResponse = test::call_and_read_body_json(&app, verify("287082")).await;
+        assert!(resp.valid);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_actions_and_verifications_are_audited() {
+        let config = Config {
+            lockout_max_credential_failures: 2,
+            ..admin_config()
+        };
+        let sink = Arc::new(crate::audit::MemorySink::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MockOtpStore::new()) as Arc<dyn OtpStore>
+                ))
+                .app_data(web::Data::new(
+                    Events::new(
+                        Some(AuditLog::new(sink.clone(), FailurePolicy::Closed)),
+                        None,
+                    )
+                    .unwrap(),
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let req = as_admin(test::TestRequest::post())
+            .uri("/api/admin/enrollments")
+            .set_json(serde_json::json!({ "user_id": "alice" }))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CREATED
+        );
+        for _ in 0..2 {
+            let req = as_admin(test::TestRequest::post())
+                .uri("/api/hotp/verify")
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "otp": "111111",
+                    "counter": 1,
+                    "credential_id": "token-1",
+                    "user_id": "alice",
+                }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+        let req = as_admin(test::TestRequest::post())
+            .uri("/api/admin/unlock")
+            .set_json(serde_json::json!({ "credential_id": "token-1" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = as_admin(test::TestRequest::delete())
+            .uri("/api/admin/enrollments/alice")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let records = crate::audit::AuditSink::records(sink.as_ref())
+            .await
+            .unwrap();
+        crate::audit::verify_chain(&records, crate::audit::TEST_KEY).unwrap();
+        let actions: Vec<Action> = records.iter().map(|r| r.event.action).collect();
+        assert_eq!(
+            actions,
+            [
+                Action::EnrollmentCreated,
+                Action::Verification,
+                Action::Lockout,
+                Action::Verification,
+                Action::Unlock,
+                Action::EnrollmentDeleted,
+            ]
+        );
+        assert_eq!(records[2].event.credential_id.as_deref(), Some("token-1"));
+        assert_eq!(records[3].event.outcome.as_deref(), Some("invalid"));
+        assert_eq!(records[3].event.user_id.as_deref(), Some("alice"));
+    }
+
+    #[actix_web::test]
+    async fn test_stuffing_source_is_blocked_across_users() {
+        let config = Config {
+            stuffing_min_attempts: 3,
+            stuffing_failure_ratio: 1.0,
+            ..test_config()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MockOtpStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let verify = |peer: &str, user: &str| {
+            test::TestRequest::post()
+                .uri("/api/hotp/verify")
+                .peer_addr(peer.parse().unwrap())
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "otp": "111111",
+                    "counter": 1,
+                    "credential_id": user,
+                    "user_id": user,
+                }))
+                .to_request()
+        };
+
+        // One wrong guess per user never trips the per-user lockout
+        for (peer, user) in [
+            ("198.51.100.9:4000", "alice"),
+            ("198.51.100.9:4000", "bob"),
+            ("198.51.100.10:4000", "carol"),
+        ] {
+            let resp = test::call_service(&app, verify(peer, user)).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // The /24 both addresses share is now blocked, including for new neighbours
+        let resp = test::call_service(&app, verify("198.51.100.11:4000", "dave")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().get("Retry-After").is_some());
+
+        // Other networks are unaffected
+        let resp = test::call_service(&app, verify("203.0.113.5:4000", "erin")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_errors_are_problem_json() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(admin_config())))
+                .app_data(web::Data::new(
+                    Arc::new(MockOtpStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+        let problem = |resp: actix_web::dev::ServiceResponse| async move {
+            assert_eq!(
+                resp.headers().get("Content-Type").unwrap(),
+                crate::error::PROBLEM_JSON
+            );
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            body["code"].as_str().unwrap().to_string()
+        };
+
+        // Bodies the JSON extractor rejects
+        let req = as_admin(test::TestRequest::post())
+            .uri("/api/v2/hotp/verify")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{\"secret\": ")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(problem(resp).await, "validation_error");
+
+        let req = as_admin(test::TestRequest::get())
+            .uri("/api/v2/nope")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(problem(resp).await, "not_found");
+
+        let req = as_admin(test::TestRequest::delete())
+            .uri("/api/v2/admin/enrollments/nobody")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(problem(resp).await, "not_found");
+
+        // A second rotation during the grace period would cut the previous key off early
+        let req = as_admin(test::TestRequest::post())
+            .uri("/api/v2/admin/api-keys")
+            .set_json(serde_json::json!({ "scopes": ["verify"] }))
+            .to_request();
+        let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let rotate = || {
+            as_admin(test::TestRequest::post())
+                .uri(&format!(
+                    "/api/v2/admin/api-keys/{}/rotate",
+                    created["id"].as_str().unwrap()
+                ))
+                .to_request()
+        };
+        let resp = test::call_service(&app, rotate()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, rotate()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(problem(resp).await, "conflict");
     }
 
     // --- Integration Tests ---
@@ -425,6 +2047,9 @@ mod tests {
             secret: secret_hex.clone(),
             otp: generated_otp.clone(),
             counter,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
         };
         let req_verify_valid = test::TestRequest::post()
             .uri("/api/hotp/verify")
@@ -448,6 +2073,9 @@ mod tests {
             secret: secret_hex.clone(),
             otp: "000000".to_string(), // Incorrect OTP
             counter,
+            credential_id: None,
+            user_id: None,
+            receipt: false,
         };
         let req_verify_invalid_otp = test::TestRequest::post()
             .uri("/api/hotp/verify")
@@ -462,6 +2090,9 @@ mod tests {
             secret: secret_hex,
             otp: generated_otp,
             counter: counter + 1, // Incorrect counter
+            credential_id: None,
+            user_id: None,
+            receipt: false,
         };
         let req_verify_invalid_counter = test::TestRequest::post()
             .uri("/api/hotp/verify")
@@ -1,6 +1,17 @@
 // Server module declaration
+pub mod access_policy;
+pub mod auth;
+pub mod client_ip;
+pub mod forward_auth;
+pub mod grpc;
 pub mod handlers;
+pub mod openapi;
+pub mod radius;
+pub mod rate_limit;
 pub mod routes;
+pub mod tenant;
+pub mod tls;
+pub mod versioning;
 
 // Re-export necessary items
 // pub use handlers::*; // Not directly used in main.rs
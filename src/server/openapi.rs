@@ -0,0 +1,190 @@
+//! OpenAPI description of the HTTP API, generated from the handlers and their request
+//! and response types.
+
+use crate::server::handlers;
+use actix_web::HttpResponse;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "OTP Server",
+        license(name = "MIT"),
+        description = "Generates and verifies TOTP and HOTP codes. Errors are returned as \
+            RFC 7807 problem details with a stable `code`.\n\nThis document describes v2. \
+            The deprecated v1 API serves the same operations under `/api` without the `/v2`, \
+            with errors in a `{\"error\": {\"code\", \"message\"}}` envelope."
+    ),
+    paths(
+        handlers::health_check,
+        handlers::deep_health_check,
+        handlers::readiness_check,
+        handlers::generate_secret,
+        handlers::generate_otp,
+        handlers::verify_otp,
+        handlers::generate_hotp,
+        handlers::verify_hotp,
+        handlers::unlock,
+        handlers::create_enrollment,
+        handlers::delete_enrollment,
+        handlers::create_api_key,
+        handlers::revoke_api_key,
+        handlers::rotate_api_key,
+        handlers::purge_tenant,
+        handlers::receipt_jwks,
+    ),
+    modifiers(&Security),
+    tags(
+        (name = "Health", description = "Liveness, readiness and component health"),
+        (name = "Secrets", description = "Secret generation"),
+        (name = "TOTP", description = "Time-based codes"),
+        (name = "HOTP", description = "Counter-based codes"),
+        (name = "Admin", description = "Lockouts, enrollments, API keys and tenants"),
+        (name = "Receipts", description = "Keys for checking verification receipts"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the API key and bearer token schemes the paths refer to
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Serve the OpenAPI document
+pub async fn spec() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::routes::{API_ROUTES, ROOT_ROUTES};
+    use crate::server::versioning::V2_PREFIX;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::OtpStore;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, web, App};
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    /// Routes that are deliberately left out of the spec
+    fn undocumented(path: &str) -> bool {
+        // The spec itself, and forward auth, which serves HTML to proxies
+        path == format!("{}/openapi.json", V2_PREFIX) || path.starts_with("/auth/")
+    }
+
+    /// `(method, path)` of every registered route. The spec documents v2, so the routes
+    /// shared by both versions are taken as v2 routes.
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        let root = ROOT_ROUTES
+            .iter()
+            .map(|(method, path, _)| (method.to_string(), path.to_string()));
+        let api = API_ROUTES
+            .iter()
+            .map(|(method, path, _)| (method.to_string(), format!("{}{}", V2_PREFIX, path)));
+        root.chain(api)
+            .filter(|(_, path)| !undocumented(path))
+            .collect()
+    }
+
+    fn documented_routes() -> BTreeSet<(String, String)> {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut routes = BTreeSet::new();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                routes.insert((method.to_uppercase(), path.clone()));
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn test_spec_matches_routes() {
+        let registered = registered_routes();
+        let documented = documented_routes();
+        assert!(registered.len() > 10);
+        assert_eq!(
+            registered.difference(&documented).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "routes missing from the OpenAPI spec"
+        );
+        assert_eq!(
+            documented.difference(&registered).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "documented routes that aren't registered"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_documented_routes_are_served() {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(Config::default())))
+                .app_data(web::Data::new(
+                    Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        for (method, path) in documented_routes() {
+            let uri = path
+                .replace("{id}", "x")
+                .replace("{user_id}", "x")
+                .replace("{tenant}", "x");
+            let req = actix_test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(&uri)
+                .to_request();
+            let resp = actix_test::call_service(&app, req).await;
+            assert_ne!(
+                resp.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{} {}",
+                method,
+                path
+            );
+            if resp.status() == StatusCode::NOT_FOUND {
+                // Handlers may answer 404 for unknown resources, but not the router
+                let body: serde_json::Value = actix_test::read_body_json(resp).await;
+                assert_ne!(
+                    body["detail"], "No such API endpoint",
+                    "{} {}",
+                    method, path
+                );
+            }
+        }
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/v2/openapi.json")
+            .to_request();
+        let spec: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(spec["openapi"], "3.1.0");
+        let req = actix_test::TestRequest::get()
+            .uri("/api/v2/docs")
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,703 @@
+//! RADIUS front end (RFC 2865) for VPN concentrators and network devices.
+//!
+//! Access-Requests carry the OTP, or a PIN followed by the OTP, in User-Password and
+//! are checked against the user's enrollment with the same lockout and replay
+//! protection as the HTTP verify endpoints.
+
+use crate::config::Config;
+use crate::enrollment;
+use crate::error::AppError;
+use crate::events::Events;
+use crate::metrics::Outcome;
+use crate::secrets::SecretBox;
+use crate::server::client_ip::ClientIp;
+use crate::server::handlers::{check_totp, TotpCheck};
+use crate::server::tenant::{validate_tenant_id, Tenant};
+use crate::storage::OtpStore;
+use crate::telemetry;
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use md5::{Digest, Md5};
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+type HmacMd5 = Hmac<Md5>;
+
+pub const ACCESS_REQUEST: u8 = 1;
+pub const ACCESS_ACCEPT: u8 = 2;
+pub const ACCESS_REJECT: u8 = 3;
+
+pub const USER_NAME: u8 = 1;
+pub const USER_PASSWORD: u8 = 2;
+pub const REPLY_MESSAGE: u8 = 18;
+pub const MESSAGE_AUTHENTICATOR: u8 = 80;
+
+const HEADER_LEN: usize = 20;
+const MAX_PACKET_LEN: usize = 4096;
+
+/// How long answers are kept to resend for retransmitted requests
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// A RADIUS packet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub code: u8,
+    pub identifier: u8,
+    pub authenticator: [u8; 16],
+    pub attributes: Vec<(u8, Vec<u8>)>,
+}
+
+impl Packet {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_LEN {
+            return Err("Packet too short".to_string());
+        }
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if !(HEADER_LEN..=MAX_PACKET_LEN).contains(&length) || length > data.len() {
+            return Err(format!("Invalid packet length {}", length));
+        }
+
+        // Octets beyond the length field are padding and ignored
+        let mut attributes = Vec::new();
+        let mut rest = &data[HEADER_LEN..length];
+        while !rest.is_empty() {
+            if rest.len() < 2 || (rest[1] as usize) < 2 || rest[1] as usize > rest.len() {
+                return Err("Malformed attribute".to_string());
+            }
+            let (attribute, tail) = rest.split_at(rest[1] as usize);
+            attributes.push((attribute[0], attribute[2..].to_vec()));
+            rest = tail;
+        }
+
+        let mut authenticator = [0u8; 16];
+        authenticator.copy_from_slice(&data[4..20]);
+        Ok(Self {
+            code: data[0],
+            identifier: data[1],
+            authenticator,
+            attributes,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.code, self.identifier, 0, 0];
+        data.extend_from_slice(&self.authenticator);
+        for (kind, value) in &self.attributes {
+            data.push(*kind);
+            data.push((value.len() + 2) as u8);
+            data.extend_from_slice(value);
+        }
+        let length = (data.len() as u16).to_be_bytes();
+        data[2..4].copy_from_slice(&length);
+        data
+    }
+
+    pub fn attribute(&self, kind: u8) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// HMAC-MD5 Message-Authenticator (RFC 3579) over the packet with the attribute zeroed
+    pub fn message_authenticator(&self, secret: &[u8]) -> [u8; 16] {
+        let mut zeroed = self.clone();
+        for (kind, value) in zeroed.attributes.iter_mut() {
+            if *kind == MESSAGE_AUTHENTICATOR {
+                *value = vec![0; 16];
+            }
+        }
+        let mut mac = HmacMd5::new_from_slice(secret).expect("HMAC accepts any key length");
+        mac.update(&zeroed.encode());
+        mac.finalize().into_bytes().into()
+    }
+}
+
+/// Undo the User-Password hiding: each 16-octet block is XORed with
+/// MD5(secret + previous ciphertext block), starting from the request authenticator
+pub fn decrypt_password(secret: &[u8], authenticator: &[u8; 16], hidden: &[u8]) -> Option<Vec<u8>> {
+    if hidden.is_empty() || !hidden.len().is_multiple_of(16) || hidden.len() > 128 {
+        return None;
+    }
+    let mut password = Vec::with_capacity(hidden.len());
+    let mut previous: &[u8] = authenticator;
+    for block in hidden.chunks(16) {
+        let key = Md5::new()
+            .chain_update(secret)
+            .chain_update(previous)
+            .finalize();
+        password.extend(block.iter().zip(key.iter()).map(|(c, k)| c ^ k));
+        previous = block;
+    }
+    // Passwords are padded with NULs to a multiple of 16 octets
+    while password.last() == Some(&0) {
+        password.pop();
+    }
+    Some(password)
+}
+
+/// Build an Access-Accept or Access-Reject for a request
+pub fn response(request: &Packet, code: u8, secret: &[u8], reply_message: Option<&str>) -> Vec<u8> {
+    let mut packet = Packet {
+        code,
+        identifier: request.identifier,
+        authenticator: request.authenticator,
+        attributes: Vec::new(),
+    };
+    if let Some(message) = reply_message {
+        packet
+            .attributes
+            .push((REPLY_MESSAGE, message.as_bytes().to_vec()));
+    }
+    // Always signed, so clients can insist on Message-Authenticator (BlastRADIUS)
+    packet.attributes.push((MESSAGE_AUTHENTICATOR, vec![0; 16]));
+    let signature = packet.message_authenticator(secret);
+    packet.attributes.last_mut().expect("just pushed").1 = signature.to_vec();
+
+    // Response Authenticator: MD5(Code + ID + Length + Request Authenticator + Attributes + Secret)
+    let mut data = packet.encode();
+    let digest = Md5::new()
+        .chain_update(&data)
+        .chain_update(secret)
+        .finalize();
+    data[4..20].copy_from_slice(&digest);
+    data
+}
+
+fn deserialize_network<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpNet, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<std::net::IpAddr>().map(IpNet::from))
+        .map_err(|_| serde::de::Error::custom(format!("invalid address or network: {}", value)))
+}
+
+/// A network access server allowed to send requests
+#[derive(Debug, Clone, Deserialize)]
+pub struct RadiusClient {
+    /// Address or network requests may come from
+    #[serde(deserialize_with = "deserialize_network")]
+    pub address: IpNet,
+    pub secret: String,
+    /// Tenant whose enrollments users are checked against; the default tenant when absent
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Drop requests without a valid Message-Authenticator
+    #[serde(default = "default_true")]
+    pub require_message_authenticator: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RadiusClients {
+    #[serde(default)]
+    pub clients: Vec<RadiusClient>,
+}
+
+impl RadiusClients {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let clients: Self = serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid RADIUS clients {}: {}", path, e))?;
+        for client in &clients.clients {
+            if let Some(tenant) = &client.tenant {
+                validate_tenant_id(tenant).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(clients)
+    }
+
+    /// The most specific client entry covering an address
+    fn find(&self, addr: &SocketAddr) -> Option<&RadiusClient> {
+        self.clients
+            .iter()
+            .filter(|c| c.address.contains(&addr.ip()))
+            .max_by_key(|c| c.address.prefix_len())
+    }
+}
+
+/// Source, identifier and request authenticator of a request
+type RequestKey = (SocketAddr, u8, [u8; 16]);
+
+enum Cached {
+    InProgress,
+    Answered(Vec<u8>),
+}
+
+/// Requests seen recently, so retransmissions get the original answer instead of
+/// failing replay protection
+#[derive(Default)]
+struct DuplicateCache {
+    entries: Mutex<HashMap<RequestKey, (Instant, Cached)>>,
+}
+
+impl DuplicateCache {
+    /// `None` for a new request, otherwise the cached answer (or `Some(None)` while the
+    /// first copy is still being processed)
+    fn begin(&self, key: RequestKey) -> Option<Option<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        entries.retain(|_, (seen, _)| now.duration_since(*seen) < DUPLICATE_WINDOW);
+        match entries.get(&key) {
+            Some((_, Cached::InProgress)) => Some(None),
+            Some((_, Cached::Answered(answer))) => Some(Some(answer.clone())),
+            None => {
+                entries.insert(key, (now, Cached::InProgress));
+                None
+            }
+        }
+    }
+
+    fn finish(&self, key: RequestKey, answer: Option<&[u8]>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match answer {
+            Some(answer) => {
+                entries.insert(key, (Instant::now(), Cached::Answered(answer.to_vec())));
+            }
+            // Nothing was sent; let a retransmission try again
+            None => {
+                entries.remove(&key);
+            }
+        }
+    }
+}
+
+pub struct RadiusServer {
+    socket: UdpSocket,
+    clients: RadiusClients,
+    config: Arc<Config>,
+    storage: Arc<dyn OtpStore>,
+    secrets: SecretBox,
+    events: Option<Arc<Events>>,
+    duplicates: DuplicateCache,
+}
+
+impl RadiusServer {
+    /// Bind the listener when `RADIUS_BIND` is set
+    pub async fn from_config(
+        config: Arc<Config>,
+        storage: Arc<dyn OtpStore>,
+        events: Option<Arc<Events>>,
+    ) -> Result<Option<Self>, String> {
+        let bind = match &config.radius_bind {
+            Some(bind) => bind.clone(),
+            None => return Ok(None),
+        };
+        let clients = match &config.radius_clients_file {
+            Some(path) => RadiusClients::load(path)?,
+            None => return Err("RADIUS_CLIENTS_FILE is required with RADIUS_BIND".to_string()),
+        };
+        let secrets = SecretBox::from_config(&config)?;
+        let socket = UdpSocket::bind(&bind)
+            .await
+            .map_err(|e| format!("Failed to bind RADIUS listener on {}: {}", bind, e))?;
+
+        Ok(Some(Self {
+            socket,
+            clients,
+            config,
+            storage,
+            secrets,
+            events,
+            duplicates: DuplicateCache::default(),
+        }))
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Serve requests until the task is dropped
+    pub async fn run(self) {
+        let server = Arc::new(self);
+        if let Ok(addr) = server.local_addr() {
+            log::info!("RADIUS listener on {}", addr);
+        }
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        loop {
+            let (len, peer) = match server.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    log::warn!("RADIUS receive failed: {}", e);
+                    continue;
+                }
+            };
+            let data = buf[..len].to_vec();
+            let server = server.clone();
+            tokio::spawn(async move { server.handle(&data, peer).await });
+        }
+    }
+
+    async fn handle(&self, data: &[u8], peer: SocketAddr) {
+        // Requests from unknown clients are silently discarded (RFC 2865 section 3)
+        let client = match self.clients.find(&peer) {
+            Some(client) => client,
+            None => {
+                log::warn!("RADIUS request from unknown client {}", peer);
+                return;
+            }
+        };
+        let request = match Packet::parse(data) {
+            Ok(request) if request.code == ACCESS_REQUEST => request,
+            Ok(request) => {
+                log::debug!("Ignoring RADIUS code {} from {}", request.code, peer);
+                return;
+            }
+            Err(e) => {
+                log::warn!("Malformed RADIUS packet from {}: {}", peer, e);
+                return;
+            }
+        };
+        let secret = client.secret.as_bytes();
+
+        match request.attribute(MESSAGE_AUTHENTICATOR) {
+            Some(signature) => {
+                let expected = request.message_authenticator(secret);
+                if !crate::api_keys::constant_time_eq(signature, &expected) {
+                    log::warn!("Invalid Message-Authenticator from {}", peer);
+                    return;
+                }
+            }
+            None if client.require_message_authenticator => {
+                log::warn!("RADIUS request without Message-Authenticator from {}", peer);
+                return;
+            }
+            None => {}
+        }
+
+        let key = (peer, request.identifier, request.authenticator);
+        match self.duplicates.begin(key) {
+            None => {}
+            Some(Some(answer)) => {
+                let _ = self.socket.send_to(&answer, peer).await;
+                return;
+            }
+            Some(None) => return,
+        }
+
+        let attributes = vec![
+            KeyValue::new("network.peer.address", peer.ip().to_string()),
+            KeyValue::new("radius.identifier", request.identifier as i64),
+        ];
+        let answer = telemetry::in_span(
+            "radius Access-Request",
+            SpanKind::Server,
+            attributes,
+            self.authenticate(client, &request),
+        )
+        .await
+        .map(|(code, message)| response(&request, code, secret, message));
+        self.duplicates.finish(key, answer.as_deref());
+        if let Some(answer) = answer {
+            if let Err(e) = self.socket.send_to(&answer, peer).await {
+                log::warn!("RADIUS send to {} failed: {}", peer, e);
+            }
+        }
+    }
+
+    /// Decide on a request; `None` sends no answer so the client can retry or fail over
+    async fn authenticate(
+        &self,
+        client: &RadiusClient,
+        request: &Packet,
+    ) -> Option<(u8, Option<&'static str>)> {
+        let secret = client.secret.as_bytes();
+        let reject = Some((ACCESS_REJECT, None));
+
+        let user = request
+            .attribute(USER_NAME)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(str::to_string);
+        let password = request
+            .attribute(USER_PASSWORD)
+            .and_then(|hidden| decrypt_password(secret, &request.authenticator, hidden))
+            .and_then(|p| String::from_utf8(p).ok());
+        let (user, password) = match (user, password) {
+            (Some(user), Some(password)) => (user, password),
+            _ => return reject,
+        };
+
+        let tenant = Tenant(
+            client
+                .tenant
+                .clone()
+                .unwrap_or_else(|| self.config.default_tenant.clone()),
+        );
+        let enrollment =
+            match enrollment::get(self.storage.as_ref(), &self.secrets, tenant.as_str(), &user)
+                .await
+            {
+                Ok(Some(enrollment)) => enrollment,
+                Ok(None) => {
+                    log::warn!("RADIUS login for unknown user {}", user);
+                    return reject;
+                }
+                Err(e) => {
+                    log::error!("RADIUS enrollment lookup failed: {}", e);
+                    return None;
+                }
+            };
+
+        // Users with a PIN send it followed by the code
+        let split = password.len().saturating_sub(self.config.otp_length);
+        if !password.is_char_boundary(split) {
+            return reject;
+        }
+        let (pin, otp) = password.split_at(split);
+        let pin_matches = match enrollment.pin_hash {
+            Some(_) => enrollment.check_pin(pin),
+            None => pin.is_empty(),
+        };
+        if pin_matches && enrollment.pin_needs_upgrade() {
+            if let Err(e) = enrollment::upgrade_pin(
+                self.storage.as_ref(),
+                &self.secrets,
+                tenant.as_str(),
+                &enrollment,
+                pin,
+            )
+            .await
+            {
+                log::warn!("Failed to rehash the PIN of {}: {}", user, e);
+            }
+        }
+
+        // The NAS address says nothing about the user, so it isn't tracked as a source
+        let checked = check_totp(
+            &self.config,
+            &self.storage,
+            self.events.as_deref(),
+            &tenant,
+            ClientIp(None),
+            TotpCheck {
+                secret: &enrollment.secret,
+                otp,
+                credential_id: None,
+                user_id: Some(&user),
+                // A wrong PIN counts towards the lockouts like a wrong code
+                pin_matches,
+            },
+        )
+        .await;
+        match checked {
+            Ok(Outcome::Valid) => {
+                log::info!(tenant = tenant.as_str(), user_id = user, outcome = "accept"; "RADIUS Access-Request");
+                Some((ACCESS_ACCEPT, None))
+            }
+            Ok(_) => {
+                log::warn!(tenant = tenant.as_str(), user_id = user, outcome = "reject"; "RADIUS Access-Request");
+                reject
+            }
+            Err(AppError::Locked { .. } | AppError::TooManyRequests { .. }) => Some((
+                ACCESS_REJECT,
+                Some("Too many failed attempts, try again later"),
+            )),
+            Err(e) => {
+                log::error!("RADIUS verification failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockout::{LockoutPolicy, Subject};
+    use crate::otp::totp::Totp;
+    use crate::storage::memory::MemoryStore;
+
+    const SECRET: &[u8] = b"testing123";
+
+    /// Hide a password the way a client does
+    fn encrypt_password(secret: &[u8], authenticator: &[u8; 16], password: &[u8]) -> Vec<u8> {
+        let mut padded = password.to_vec();
+        padded.resize(password.len().div_ceil(16).max(1) * 16, 0);
+        let mut hidden = Vec::with_capacity(padded.len());
+        let mut previous = authenticator.to_vec();
+        for block in padded.chunks(16) {
+            let key = Md5::new()
+                .chain_update(secret)
+                .chain_update(&previous)
+                .finalize();
+            let cipher: Vec<u8> = block.iter().zip(key.iter()).map(|(p, k)| p ^ k).collect();
+            hidden.extend_from_slice(&cipher);
+            previous = cipher;
+        }
+        hidden
+    }
+
+    fn access_request(identifier: u8, user: &str, password: &str) -> Packet {
+        let authenticator: [u8; 16] = rand::random();
+        let mut request = Packet {
+            code: ACCESS_REQUEST,
+            identifier,
+            authenticator,
+            attributes: vec![
+                (USER_NAME, user.as_bytes().to_vec()),
+                (
+                    USER_PASSWORD,
+                    encrypt_password(SECRET, &authenticator, password.as_bytes()),
+                ),
+                (MESSAGE_AUTHENTICATOR, vec![0; 16]),
+            ],
+        };
+        let signature = request.message_authenticator(SECRET);
+        request.attributes.last_mut().unwrap().1 = signature.to_vec();
+        request
+    }
+
+    #[test]
+    fn test_password_round_trip() {
+        let authenticator = [7u8; 16];
+        for password in ["", "287082", "a-longer-password-over-16"] {
+            let hidden = encrypt_password(SECRET, &authenticator, password.as_bytes());
+            assert_eq!(
+                decrypt_password(SECRET, &authenticator, &hidden).unwrap(),
+                password.as_bytes()
+            );
+        }
+        assert!(decrypt_password(SECRET, &authenticator, &[0u8; 15]).is_none());
+    }
+
+    #[test]
+    fn test_packet_parsing() {
+        let request = access_request(9, "alice", "123456");
+        assert_eq!(Packet::parse(&request.encode()).unwrap(), request);
+
+        let mut truncated = request.encode();
+        truncated[21] = 200;
+        assert!(Packet::parse(&truncated).is_err());
+        assert!(Packet::parse(&[1, 2, 0, 20]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_access_requests_with_local_client() {
+        let clients = serde_json::json!({
+            "clients": [{ "address": "127.0.0.1", "secret": "testing123", "tenant": "acme" }]
+        });
+        let clients_path =
+            std::env::temp_dir().join(format!("otp-radius-clients-{}.json", std::process::id()));
+        std::fs::write(&clients_path, clients.to_string()).unwrap();
+        let config = Arc::new(Config {
+            otp_length: 6,
+            otp_expiry_seconds: 30,
+            radius_bind: Some("127.0.0.1:0".to_string()),
+            radius_clients_file: Some(clients_path.display().to_string()),
+            lockout_max_user_failures: 3,
+            secrets_key: Some(crate::secrets::tests::KEY.to_string()),
+            ..Config::default()
+        });
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+        let secrets = SecretBox::from_config(&config).unwrap();
+        let alice = enrollment::enroll(storage.as_ref(), &secrets, "acme", "alice", None, 0)
+            .await
+            .unwrap();
+        let bob = enrollment::enroll(storage.as_ref(), &secrets, "acme", "bob", Some("4711"), 0)
+            .await
+            .unwrap();
+        let carol =
+            enrollment::enroll(storage.as_ref(), &secrets, "acme", "carol", Some("1234"), 0)
+                .await
+                .unwrap();
+
+        let lockout = LockoutPolicy::from_config(&config);
+        let server = RadiusServer::from_config(config, storage.clone(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let task = tokio::spawn(server.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let exchange = |request: Packet| {
+            let client = &client;
+            async move {
+                client.send_to(&request.encode(), addr).await.unwrap();
+                let mut buf = [0u8; MAX_PACKET_LEN];
+                let (len, _) =
+                    tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+                        .await
+                        .unwrap()
+                        .unwrap();
+                let reply = Packet::parse(&buf[..len]).unwrap();
+
+                // The reply is authenticated with the shared secret
+                let mut check = buf[..len].to_vec();
+                check[4..20].copy_from_slice(&request.authenticator);
+                let digest = Md5::new()
+                    .chain_update(&check)
+                    .chain_update(SECRET)
+                    .finalize();
+                assert_eq!(reply.authenticator.as_slice(), digest.as_slice());
+                assert_eq!(reply.identifier, request.identifier);
+                reply.code
+            }
+        };
+        let code = |secret: &str| {
+            Totp::new(hex::decode(secret).unwrap(), 6, 30)
+                .generate()
+                .unwrap()
+        };
+
+        assert_eq!(
+            exchange(access_request(1, "alice", "000000")).await,
+            ACCESS_REJECT
+        );
+        let request = access_request(2, "alice", &code(&alice.secret));
+        assert_eq!(exchange(request.clone()).await, ACCESS_ACCEPT);
+        // A retransmission gets the same answer; the same code in a new request is a replay
+        assert_eq!(exchange(request).await, ACCESS_ACCEPT);
+        assert_eq!(
+            exchange(access_request(3, "alice", &code(&alice.secret))).await,
+            ACCESS_REJECT
+        );
+
+        // PIN + OTP; a wrong PIN fails before the code is accepted, so it isn't used up
+        let bob_code = code(&bob.secret);
+        assert_eq!(
+            exchange(access_request(4, "bob", &format!("0000{}", bob_code))).await,
+            ACCESS_REJECT
+        );
+        assert_eq!(
+            exchange(access_request(5, "bob", &format!("4711{}", bob_code))).await,
+            ACCESS_ACCEPT
+        );
+
+        // Wrong PINs count as failures, so guessing the PIN with valid codes locks the user
+        for (identifier, pin) in [(6, "0000"), (7, "1111"), (8, "2222")] {
+            let password = format!("{}{}", pin, code(&carol.secret));
+            assert_eq!(
+                exchange(access_request(identifier, "carol", &password)).await,
+                ACCESS_REJECT
+            );
+        }
+        let password = format!("1234{}", code(&carol.secret));
+        assert_eq!(
+            exchange(access_request(9, "carol", &password)).await,
+            ACCESS_REJECT
+        );
+        let carol_locked = lockout
+            .locked_for(
+                storage.as_ref(),
+                "acme",
+                &[Subject::User("carol".to_string())],
+            )
+            .await
+            .unwrap();
+        assert!(carol_locked.is_some());
+
+        task.abort();
+        std::fs::remove_file(clients_path).ok();
+    }
+}
@@ -0,0 +1,348 @@
+use crate::config::{Config, RateLimit};
+use crate::error::AppError;
+use crate::server::client_ip::client_ip;
+use crate::server::versioning;
+use crate::storage::{OtpStore, SYSTEM_TENANT};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use otp_types::API_KEY_HEADER;
+
+/// Groups of routes that share a rate limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteGroup {
+    Secret,
+    Otp,
+    Hotp,
+}
+
+impl RouteGroup {
+    /// Group a route belongs to, by its unversioned pattern, if it is rate limited
+    pub fn for_path(path: &str) -> Option<Self> {
+        if path == "/api/secret" {
+            Some(RouteGroup::Secret)
+        } else if path.starts_with("/api/otp/") {
+            Some(RouteGroup::Otp)
+        } else if path.starts_with("/api/hotp/") {
+            Some(RouteGroup::Hotp)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RouteGroup::Secret => "secret",
+            RouteGroup::Otp => "otp",
+            RouteGroup::Hotp => "hotp",
+        }
+    }
+
+    pub fn limit(self, config: &Config) -> Option<RateLimit> {
+        match self {
+            RouteGroup::Secret => config.rate_limit_secret,
+            RouteGroup::Otp => config.rate_limit_otp,
+            RouteGroup::Hotp => config.rate_limit_hotp,
+        }
+    }
+}
+
+/// Outcome of counting a request against a limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the current window ends
+    pub reset: u64,
+}
+
+impl Decision {
+    /// The more restrictive of two decisions
+    fn min(self, other: Decision) -> Decision {
+        if !self.allowed || (other.allowed && self.remaining <= other.remaining) {
+            self
+        } else {
+            other
+        }
+    }
+}
+
+/// Count a request against a sliding-window limit.
+///
+/// Uses the sliding window counter approximation: the previous fixed window's count,
+/// weighted by how much of it still overlaps the sliding window, plus the current
+/// window's count. Counters live in the shared store, so the limit holds across replicas.
+pub async fn check(
+    store: &dyn OtpStore,
+    key: &str,
+    limit: RateLimit,
+    now_ms: u64,
+) -> Result<Decision, String> {
+    let window_ms = limit.window_seconds.max(1) * 1000;
+    let window = now_ms / window_ms;
+    let elapsed_ms = now_ms % window_ms;
+
+    // Keep each window around long enough to be read as the previous one
+    let current = store
+        .increment(
+            SYSTEM_TENANT,
+            &format!("{}:{}", key, window),
+            limit.window_seconds.max(1) * 2,
+        )
+        .await?;
+    let previous = match window.checked_sub(1) {
+        Some(previous) => {
+            store
+                .counter(SYSTEM_TENANT, &format!("{}:{}", key, previous))
+                .await?
+        }
+        None => 0,
+    };
+
+    let overlap = (window_ms - elapsed_ms) as f64 / window_ms as f64;
+    let estimate = (previous as f64 * overlap).floor() as u64 + current;
+
+    Ok(Decision {
+        allowed: estimate <= limit.limit,
+        limit: limit.limit,
+        remaining: limit.limit.saturating_sub(estimate),
+        reset: (window_ms - elapsed_ms).div_ceil(1000),
+    })
+}
+
+/// Count a request in a route group against the limits for its client IP and API key,
+/// returning the most restrictive decision. `None` when neither could be counted.
+pub async fn decide(
+    store: &dyn OtpStore,
+    group: RouteGroup,
+    limit: RateLimit,
+    ip: Option<IpAddr>,
+    api_key: Option<&str>,
+) -> Option<Decision> {
+    let mut keys = Vec::new();
+    if let Some(ip) = ip {
+        keys.push(format!("ratelimit:{}:ip:{}", group.name(), ip));
+    }
+    if let Some(api_key) = api_key {
+        // Never store the key itself
+        let digest = Sha256::digest(api_key.as_bytes());
+        keys.push(format!(
+            "ratelimit:{}:key:{}",
+            group.name(),
+            hex::encode(&digest[..16])
+        ));
+    }
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let mut decision: Option<Decision> = None;
+    for key in &keys {
+        match check(store, key, limit, now_ms).await {
+            Ok(d) => decision = Some(decision.map_or(d, |current| current.min(d))),
+            // Rate limiting is best effort; an outage must not take the API down with it
+            Err(e) => log::warn!("Rate limit check failed, allowing request: {}", e),
+        }
+    }
+    decision
+}
+
+/// Rate-limiting middleware for the `/api` scope, keyed by client IP and API key
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    // By the route serving the request, so encoded paths share their route's budget
+    let group = versioning::route_pattern(&req).and_then(|route| RouteGroup::for_path(&route));
+    let config = req.app_data::<web::Data<Arc<Config>>>().cloned();
+    let storage = req.app_data::<web::Data<Arc<dyn OtpStore>>>().cloned();
+
+    let (group, config, storage) = match (group, config, storage) {
+        (Some(group), Some(config), Some(storage)) => (group, config, storage),
+        _ => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+    let limit = match group.limit(&config) {
+        Some(limit) => limit,
+        None => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    let ip = client_ip(req.request(), &config.trusted_proxies);
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let decision = match decide(storage.as_ref().as_ref(), group, limit, ip, api_key).await {
+        Some(decision) => decision,
+        None => return next.call(req).await.map(|res| res.map_into_boxed_body()),
+    };
+
+    let mut res = if decision.allowed {
+        next.call(req).await?.map_into_boxed_body()
+    } else {
+        log::warn!("Rate limit exceeded for {} routes", group.name());
+        let error = AppError::TooManyRequests {
+            message: "Rate limit exceeded".to_string(),
+            retry_after: decision.reset,
+        };
+        req.into_response(error.error_response())
+    };
+
+    let headers = res.headers_mut();
+    for (name, value) in [
+        ("ratelimit-limit", decision.limit),
+        ("ratelimit-remaining", decision.remaining),
+        ("ratelimit-reset", decision.reset),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client_ip::parse_networks;
+    use crate::storage::memory::MemoryStore;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+
+    #[test]
+    fn test_route_groups() {
+        assert_eq!(
+            RouteGroup::for_path("/api/secret"),
+            Some(RouteGroup::Secret)
+        );
+        assert_eq!(
+            RouteGroup::for_path("/api/otp/verify"),
+            Some(RouteGroup::Otp)
+        );
+        assert_eq!(
+            RouteGroup::for_path("/api/hotp/generate"),
+            Some(RouteGroup::Hotp)
+        );
+        assert_eq!(RouteGroup::for_path("/api/health"), None);
+    }
+
+    #[tokio::test]
+    async fn test_limit_within_window() {
+        let store = MemoryStore::new();
+        let limit = RateLimit {
+            limit: 3,
+            window_seconds: 60,
+        };
+        // Start of window 10, so nothing carries over from window 9
+        let now = 600_000;
+
+        for remaining in [2, 1, 0] {
+            let decision = check(&store, "k", limit, now).await.unwrap();
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+            assert_eq!(decision.reset, 60);
+        }
+        assert!(!check(&store, "k", limit, now).await.unwrap().allowed);
+
+        // Other keys are independent
+        assert!(check(&store, "other", limit, now).await.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_previous_window_is_weighted() {
+        let store = MemoryStore::new();
+        let limit = RateLimit {
+            limit: 4,
+            window_seconds: 60,
+        };
+
+        // Four requests late in window 10
+        for _ in 0..4 {
+            check(&store, "k", limit, 659_000).await.unwrap();
+        }
+
+        // A quarter into window 11, three quarters of the previous window still count: 3 + 1
+        let decision = check(&store, "k", limit, 675_000).await.unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.reset, 45);
+        assert!(!check(&store, "k", limit, 675_000).await.unwrap().allowed);
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_limits_per_client_ip() {
+        let config = Config {
+            trusted_proxies: parse_networks("10.0.0.0/8"),
+            rate_limit_hotp: Some(RateLimit {
+                limit: 2,
+                window_seconds: 60,
+            }),
+            ..Config::default()
+        };
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+
+        let generate_at = |uri: &str, forwarded_for: &str| {
+            actix_test::TestRequest::post()
+                .uri(uri)
+                .peer_addr("10.0.0.2:4000".parse().unwrap())
+                .insert_header((
+                    crate::server::client_ip::FORWARDED_FOR_HEADER,
+                    forwarded_for,
+                ))
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "counter": 1
+                }))
+                .to_request()
+        };
+        let generate = |forwarded_for: &str| generate_at("/api/hotp/generate", forwarded_for);
+
+        for _ in 0..2 {
+            let resp = actix_test::call_service(&app, generate("198.51.100.9")).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers().get("RateLimit-Limit").unwrap(), "2");
+        }
+
+        let resp = actix_test::call_service(&app, generate("198.51.100.9")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get("RateLimit-Remaining").unwrap(), "0");
+        assert!(resp.headers().get("Retry-After").is_some());
+
+        // Encoded paths reach the same route, and share its budget
+        for uri in ["/api/hotp/%67enerate", "/api/v2/hotp/%67enerate"] {
+            let resp = actix_test::call_service(&app, generate_at(uri, "198.51.100.9")).await;
+            assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS, "{}", uri);
+        }
+
+        // Another client behind the same proxy has its own budget
+        let resp = actix_test::call_service(&app, generate("203.0.113.5")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Routes without a configured limit are unaffected
+        let req = actix_test::TestRequest::get()
+            .uri("/api/health")
+            .peer_addr("10.0.0.2:4000".parse().unwrap())
+            .insert_header((
+                crate::server::client_ip::FORWARDED_FOR_HEADER,
+                "198.51.100.9",
+            ))
+            .to_request();
+        let resp = actix_test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("RateLimit-Limit").is_none());
+    }
+}
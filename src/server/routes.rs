@@ -1,17 +1,124 @@
-use crate::server::handlers;
-use actix_web::web;
+use crate::error::{AppError, AppResult};
+use crate::server::openapi::{self, ApiDoc};
+use crate::server::versioning::{self, V1_PREFIX, V2_PREFIX};
+use crate::server::{access_policy, auth, forward_auth, handlers, rate_limit};
+use actix_web::http::Method;
+use actix_web::{middleware, web, HttpRequest, HttpResponse, Route};
+use std::fmt::Display;
+use utoipa::OpenApi;
+use utoipa_scalar::{Scalar, Servable};
+
+/// Report a request body, path or query the extractors reject as a validation problem,
+/// rather than actix's plain-text default
+fn extractor_error(error: impl Display, _req: &HttpRequest) -> actix_web::Error {
+    AppError::Validation(error.to_string()).into()
+}
+
+async fn unknown_route() -> AppResult<HttpResponse> {
+    Err(AppError::NotFound("No such API endpoint".to_string()))
+}
+
+/// `(method, path, handler)` of routes; the handler is attached to a route for the method
+pub(crate) type RouteTable = &'static [(Method, &'static str, fn(Route) -> Route)];
+
+/// Routes outside the versioned API
+pub(crate) const ROOT_ROUTES: RouteTable = &[
+    // Public keys for checking verification receipts, at the conventional location
+    (Method::GET, "/.well-known/jwks.json", |r| {
+        r.to(handlers::receipt_jwks)
+    }),
+    // Forward auth for reverse proxies, outside /api so proxies and browsers reach it
+    // without API credentials
+    (Method::GET, "/auth/verify", |r| r.to(forward_auth::verify)),
+    (Method::GET, "/auth/login", |r| {
+        r.to(forward_auth::login_form)
+    }),
+    (Method::POST, "/auth/login", |r| r.to(forward_auth::login)),
+    (Method::GET, "/auth/logout", |r| r.to(forward_auth::logout)),
+];
+
+/// Routes served under both API versions
+pub(crate) const API_ROUTES: RouteTable = &[
+    // API description, public like the health checks
+    (Method::GET, "/openapi.json", |r| r.to(openapi::spec)),
+    (Method::GET, "/health", |r| r.to(handlers::health_check)),
+    (Method::GET, "/health/deep", |r| {
+        r.to(handlers::deep_health_check)
+    }),
+    (Method::GET, "/ready", |r| r.to(handlers::readiness_check)),
+    (Method::POST, "/secret", |r| r.to(handlers::generate_secret)),
+    // TOTP routes (prefixed with /otp for clarity, could be /totp)
+    (Method::POST, "/otp/generate", |r| {
+        r.to(handlers::generate_otp)
+    }),
+    (Method::POST, "/otp/verify", |r| r.to(handlers::verify_otp)),
+    // HOTP routes
+    (Method::POST, "/hotp/generate", |r| {
+        r.to(handlers::generate_hotp)
+    }),
+    (Method::POST, "/hotp/verify", |r| {
+        r.to(handlers::verify_hotp)
+    }),
+    // Admin routes
+    (Method::POST, "/admin/unlock", |r| r.to(handlers::unlock)),
+    (Method::POST, "/admin/enrollments", |r| {
+        r.to(handlers::create_enrollment)
+    }),
+    (Method::DELETE, "/admin/enrollments/{user_id}", |r| {
+        r.to(handlers::delete_enrollment)
+    }),
+    (Method::POST, "/admin/api-keys", |r| {
+        r.to(handlers::create_api_key)
+    }),
+    (Method::DELETE, "/admin/api-keys/{id}", |r| {
+        r.to(handlers::revoke_api_key)
+    }),
+    (Method::POST, "/admin/api-keys/{id}/rotate", |r| {
+        r.to(handlers::rotate_api_key)
+    }),
+    (Method::DELETE, "/admin/tenants/{tenant}", |r| {
+        r.to(handlers::purge_tenant)
+    }),
+];
+
+fn register(cfg: &mut web::ServiceConfig, routes: RouteTable) {
+    for (method, path, to) in routes {
+        cfg.route(path, to(web::method(method.clone())));
+    }
+}
 
 /// Configure API routes
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    register(cfg, ROOT_ROUTES);
+    // v2 is registered first, as the v1 scope's prefix would also match v2 paths
+    cfg.service(
+        web::scope(V2_PREFIX)
+            // Innermost, so tenant rules see the tenant of the authenticated client
+            .wrap(middleware::from_fn(access_policy::enforce_tenant))
+            .wrap(middleware::from_fn(auth::authenticate))
+            .wrap(middleware::from_fn(rate_limit::rate_limit))
+            // Registered last so it runs first: refused clients don't use up rate limits
+            .wrap(middleware::from_fn(access_policy::enforce))
+            .configure(api_routes),
+    );
     cfg.service(
-        web::scope("/api")
-            .route("/health", web::get().to(handlers::health_check))
-            .route("/secret", web::post().to(handlers::generate_secret))
-            // TOTP routes (prefixed with /otp for clarity, could be /totp)
-            .route("/otp/generate", web::post().to(handlers::generate_otp))
-            .route("/otp/verify", web::post().to(handlers::verify_otp))
-            // HOTP routes
-            .route("/hotp/generate", web::post().to(handlers::generate_hotp))
-            .route("/hotp/verify", web::post().to(handlers::verify_hotp)),
+        web::scope(V1_PREFIX)
+            .wrap(middleware::from_fn(access_policy::enforce_tenant))
+            .wrap(middleware::from_fn(auth::authenticate))
+            .wrap(middleware::from_fn(rate_limit::rate_limit))
+            .wrap(middleware::from_fn(access_policy::enforce))
+            // Outermost, so refusals by the other middleware get the v1 error envelope too
+            .wrap(middleware::from_fn(versioning::v1))
+            .configure(api_routes),
     );
 }
+
+fn api_routes(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(extractor_error))
+        .app_data(web::PathConfig::default().error_handler(extractor_error))
+        .app_data(web::QueryConfig::default().error_handler(extractor_error))
+        .default_service(web::to(unknown_route))
+        // Interactive API docs
+        .service(Scalar::with_url("/docs", ApiDoc::openapi()));
+    register(cfg, API_ROUTES);
+}
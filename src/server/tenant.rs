@@ -0,0 +1,108 @@
+use crate::api_keys::ApiClient;
+use crate::config::Config;
+use crate::error::AppError;
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use std::future::{ready, Ready};
+use std::sync::Arc;
+use utoipa::openapi::path::{Parameter, ParameterBuilder, ParameterIn};
+use utoipa::openapi::Required;
+use utoipa::{IntoParams, PartialSchema};
+
+pub use otp_types::TENANT_HEADER;
+
+/// Tenant a request operates on, taken from the `X-Tenant-Id` header, the tenant the
+/// caller's API key is bound to, or the configured default
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Documents the tenant header on the paths that take a tenant
+impl IntoParams for Tenant {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        vec![ParameterBuilder::new()
+            .name(TENANT_HEADER)
+            .parameter_in(ParameterIn::Header)
+            .required(Required::False)
+            .description(Some(
+                "Tenant to act on; defaults to the API key's tenant or DEFAULT_TENANT",
+            ))
+            .schema(Some(String::schema()))
+            .build()]
+    }
+}
+
+/// Check that a tenant ID is safe to embed in storage keys.
+///
+/// IDs are 1-64 characters of ASCII letters, digits, `-` and `_`, starting with a
+/// letter or digit; a leading `_` is reserved for internal namespaces.
+pub fn validate_tenant_id(id: &str) -> Result<(), AppError> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!("Invalid tenant ID: {}", id)))
+    }
+}
+
+impl FromRequest for Tenant {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let header = match req.headers().get(TENANT_HEADER) {
+            Some(value) => match value.to_str() {
+                Ok(value) => Some(value.trim().to_string()),
+                Err(_) => {
+                    return ready(Err(AppError::Validation(format!(
+                        "Invalid {} header",
+                        TENANT_HEADER
+                    ))))
+                }
+            },
+            None => None,
+        };
+
+        let default_tenant = req
+            .app_data::<web::Data<Arc<Config>>>()
+            .map(|config| config.default_tenant.clone())
+            .unwrap_or_else(|| Config::default().default_tenant);
+
+        ready(resolve(
+            header.as_deref(),
+            req.extensions().get::<ApiClient>(),
+            &default_tenant,
+        ))
+    }
+}
+
+/// Tenant a caller acts on: the requested tenant, the tenant its key is bound to, or the
+/// default. Keys bound to a tenant may only act on that tenant.
+pub fn resolve(
+    requested: Option<&str>,
+    client: Option<&ApiClient>,
+    default_tenant: &str,
+) -> Result<Tenant, AppError> {
+    let bound = client.and_then(|client| client.tenant.as_deref());
+    if let (Some(bound), Some(requested)) = (bound, requested) {
+        if bound != requested {
+            return Err(AppError::Forbidden(format!(
+                "API key is not valid for tenant {}",
+                requested
+            )));
+        }
+    }
+
+    let tenant = requested.or(bound).unwrap_or(default_tenant);
+    validate_tenant_id(tenant).map(|_| Tenant(tenant.to_string()))
+}
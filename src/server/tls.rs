@@ -0,0 +1,478 @@
+use crate::api_keys::{ApiClient, Scope};
+use crate::config::Config;
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use serde::Deserialize;
+use std::any::Any;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Load a certificate chain and its private key, which must belong to the certificate
+pub fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, String> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| {
+        format!(
+            "Failed to read private key from {}: {}",
+            key_path.display(),
+            e
+        )
+    })?;
+    let signing_key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| format!("Unsupported private key in {}: {}", key_path.display(), e))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    // A key from another pair would only surface as failed handshakes
+    certified.keys_match().map_err(|e| {
+        format!(
+            "Private key in {} does not belong to the certificate in {}: {}",
+            key_path.display(),
+            cert_path.display(),
+            e
+        )
+    })?;
+    Ok(certified)
+}
+
+/// Serves the current certificate, which can be swapped while the server runs
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(key: CertifiedKey) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(key)),
+        }
+    }
+
+    pub fn replace(&self, key: CertifiedKey) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(key);
+    }
+
+    fn current(&self) -> Arc<CertifiedKey> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Poll the certificate and key files and load new versions; a pair that fails to
+    /// load (e.g. mid-renewal) keeps the previous certificate
+    fn spawn_reloader(
+        self: Arc<Self>,
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        provider: Arc<CryptoProvider>,
+        interval: Duration,
+    ) {
+        let modified = move |cert: &Path, key: &Path| -> Option<(SystemTime, SystemTime)> {
+            let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            Some((mtime(cert)?, mtime(key)?))
+        };
+        let mut last_modified = modified(&cert_path, &key_path);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let current = modified(&cert_path, &key_path);
+                if current == last_modified {
+                    continue;
+                }
+
+                match load_certified_key(&cert_path, &key_path, &provider) {
+                    Ok(key) => {
+                        self.replace(key);
+                        last_modified = current;
+                        log::info!("Reloaded TLS certificate from {}", cert_path.display());
+                    }
+                    Err(e) => log::error!("Keeping previous TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+/// Build the TLS configuration, `None` when TLS isn't configured
+pub fn server_config(config: &Config) -> Result<Option<ServerConfig>, String> {
+    let (cert_path, key_path) = match (&config.tls_cert_file, &config.tls_key_file) {
+        (Some(cert), Some(key)) => (PathBuf::from(cert), PathBuf::from(key)),
+        (None, None) => return Ok(None),
+        _ => return Err("TLS_CERT_FILE and TLS_KEY_FILE must be set together".to_string()),
+    };
+
+    let provider = provider();
+    let resolver = Arc::new(ReloadingCertResolver::new(load_certified_key(
+        &cert_path, &key_path, &provider,
+    )?));
+    if config.tls_reload_seconds > 0 {
+        resolver.clone().spawn_reloader(
+            cert_path,
+            key_path,
+            provider.clone(),
+            Duration::from_secs(config.tls_reload_seconds),
+        );
+    }
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+    let builder = match &config.tls_client_ca_file {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(Path::new(ca_path))? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("Invalid CA certificate in {}: {}", ca_path, e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            // Without a required certificate, clients may still authenticate by other means
+            let verifier = if config.tls_client_auth_required {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            builder.with_client_cert_verifier(
+                verifier
+                    .build()
+                    .map_err(|e| format!("Invalid client CA configuration: {}", e))?,
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Some(builder.with_cert_resolver(resolver)))
+}
+
+/// Verified client certificate of a TLS connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    /// Subject distinguished name, e.g. `CN=billing, O=Example`
+    pub subject: String,
+    pub common_name: Option<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject = cert.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        Some(Self {
+            subject: subject.to_string(),
+            common_name,
+        })
+    }
+}
+
+/// Connection hook recording the client certificate, if any, for the requests on it
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    let Some(tls) = conn.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let (_, session) = tls.get_ref();
+    if let Some(cert) = session
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(|cert| ClientCertificate::from_der(cert))
+    {
+        data.insert(cert);
+    }
+}
+
+/// Maps a client certificate to an API identity by full subject or common name
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientIdentity {
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub common_name: Option<String>,
+    /// Tenant the identity is bound to; every tenant when absent
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+}
+
+impl ClientIdentity {
+    fn matches(&self, cert: &ClientCertificate) -> bool {
+        match (&self.subject, &self.common_name) {
+            (Some(subject), _) => *subject == cert.subject,
+            (None, Some(cn)) => cert.common_name.as_ref() == Some(cn),
+            (None, None) => false,
+        }
+    }
+}
+
+/// Identities client certificates map to
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ClientIdentities {
+    #[serde(default)]
+    pub identities: Vec<ClientIdentity>,
+}
+
+impl ClientIdentities {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let path = match &config.tls_client_identities_file {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid client identities {}: {}", path, e))
+    }
+
+    pub fn identify(&self, cert: &ClientCertificate) -> Option<ApiClient> {
+        let identity = self.identities.iter().find(|i| i.matches(cert))?;
+        Some(ApiClient {
+            key_id: format!("cert:{}", cert.subject),
+            tenant: identity.tenant.clone(),
+            scopes: identity.scopes.clone(),
+            require_signature: false,
+            signing_key: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::OtpStore;
+    use actix_web::{web, App, HttpServer};
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    pub(crate) struct Issued {
+        pub cert_pem: String,
+        pub key_pem: String,
+    }
+
+    pub(crate) struct TestPki {
+        pub ca_pem: String,
+        ca_params: CertificateParams,
+        ca_key: KeyPair,
+    }
+
+    impl TestPki {
+        pub fn new() -> Self {
+            let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            ca_params
+                .distinguished_name
+                .push(DnType::CommonName, "Test CA");
+            let ca_key = KeyPair::generate().unwrap();
+            let ca_pem = ca_params.clone().self_signed(&ca_key).unwrap().pem();
+            Self {
+                ca_pem,
+                ca_params,
+                ca_key,
+            }
+        }
+
+        pub fn issue(&self, names: Vec<String>, common_name: &str) -> Issued {
+            let mut params = CertificateParams::new(names).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, common_name);
+            let key = KeyPair::generate().unwrap();
+            let ca = self.ca_params.clone().self_signed(&self.ca_key).unwrap();
+            let cert = params.signed_by(&key, &ca, &self.ca_key).unwrap();
+            Issued {
+                cert_pem: cert.pem(),
+                key_pem: key.serialize_pem(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_identities_match_subject_or_common_name() {
+        let identities: ClientIdentities = serde_json::from_value(serde_json::json!({
+            "identities": [
+                { "common_name": "billing", "tenant": "acme", "scopes": ["verify"] },
+                { "subject": "CN=ops, O=Example", "scopes": ["admin"] }
+            ]
+        }))
+        .unwrap();
+
+        let billing = ClientCertificate {
+            subject: "CN=billing".to_string(),
+            common_name: Some("billing".to_string()),
+        };
+        let client = identities.identify(&billing).unwrap();
+        assert_eq!(client.tenant.as_deref(), Some("acme"));
+        assert_eq!(client.scopes, vec![Scope::Verify]);
+
+        let ops = ClientCertificate {
+            subject: "CN=ops, O=Example".to_string(),
+            common_name: Some("ops".to_string()),
+        };
+        assert_eq!(identities.identify(&ops).unwrap().tenant, None);
+
+        let unknown = ClientCertificate {
+            subject: "CN=unknown".to_string(),
+            common_name: Some("unknown".to_string()),
+        };
+        assert!(identities.identify(&unknown).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_client_certificate_identities_over_tls() {
+        let pki = TestPki::new();
+        let dir = std::env::temp_dir().join(format!("otp-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        };
+
+        let server_cert = pki.issue(vec!["localhost".to_string()], "localhost");
+        let identities = serde_json::json!({
+            "identities": [{ "common_name": "billing", "tenant": "acme", "scopes": ["verify"] }]
+        });
+        let config = Config {
+            api_auth_enabled: true,
+            tls_cert_file: Some(write("server.pem", &server_cert.cert_pem)),
+            tls_key_file: Some(write("server.key", &server_cert.key_pem)),
+            tls_client_ca_file: Some(write("ca.pem", &pki.ca_pem)),
+            tls_client_identities_file: Some(write("identities.json", &identities.to_string())),
+            tls_reload_seconds: 0,
+            ..Config::default()
+        };
+        let tls = server_config(&config).unwrap().unwrap();
+        let identities = web::Data::new(ClientIdentities::from_config(&config).unwrap());
+        let config = Arc::new(config);
+        let storage = Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>;
+
+        let app_storage = storage.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(app_storage.clone()))
+                .app_data(identities.clone())
+                .configure(crate::server::routes::configure_routes)
+        })
+        .workers(1)
+        .on_connect(on_connect)
+        .bind_rustls_0_23(("127.0.0.1", 0), tls)
+        .unwrap();
+        let port = server.addrs()[0].port();
+        let running = server.run();
+        let handle = running.handle();
+        actix_web::rt::spawn(running);
+
+        let ca = reqwest::Certificate::from_pem(pki.ca_pem.as_bytes()).unwrap();
+        let client = |cert: Option<&Issued>| {
+            let mut builder = reqwest::Client::builder()
+                .use_rustls_tls()
+                .add_root_certificate(ca.clone());
+            if let Some(cert) = cert {
+                let pem = format!("{}{}", cert.cert_pem, cert.key_pem);
+                builder = builder.identity(reqwest::Identity::from_pem(pem.as_bytes()).unwrap());
+            }
+            builder.build().unwrap()
+        };
+        let verify = |client: reqwest::Client| async move {
+            client
+                .post(format!("https://localhost:{}/api/hotp/verify", port))
+                .json(&serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "otp": "287082",
+                    "counter": 1
+                }))
+                .send()
+                .await
+                .unwrap()
+                .status()
+        };
+
+        // A mapped certificate authenticates as its identity, bound to its tenant
+        let billing = pki.issue(Vec::new(), "billing");
+        assert_eq!(verify(client(Some(&billing))).await, 200);
+        assert!(storage.is_used("acme", "hotp-287082-1").await.unwrap());
+
+        // Client certificates are optional at the TLS layer, but unmapped or missing ones
+        // don't authenticate
+        let unknown = pki.issue(Vec::new(), "unknown");
+        assert_eq!(verify(client(Some(&unknown))).await, 401);
+        assert_eq!(verify(client(None)).await, 401);
+
+        handle.stop(false).await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_resolver_serves_replaced_certificate() {
+        let pki = TestPki::new();
+        let dir = std::env::temp_dir().join(format!("otp-tls-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let provider = provider();
+
+        let load = |issued: &Issued| {
+            std::fs::write(&cert_path, &issued.cert_pem).unwrap();
+            std::fs::write(&key_path, &issued.key_pem).unwrap();
+            load_certified_key(&cert_path, &key_path, &provider).unwrap()
+        };
+        let first = load(&pki.issue(vec!["localhost".to_string()], "first"));
+        let first_der = first.cert[0].clone();
+        let resolver = ReloadingCertResolver::new(first);
+        assert_eq!(resolver.current().cert[0], first_der);
+
+        let second = load(&pki.issue(vec!["localhost".to_string()], "second"));
+        let second_der = second.cert[0].clone();
+        resolver.replace(second);
+        assert_eq!(resolver.current().cert[0], second_der);
+        assert_ne!(first_der, second_der);
+
+        // A certificate with another certificate's key is refused
+        let (third, fourth) = (
+            pki.issue(vec!["localhost".to_string()], "third"),
+            pki.issue(vec!["localhost".to_string()], "fourth"),
+        );
+        std::fs::write(&cert_path, &third.cert_pem).unwrap();
+        std::fs::write(&key_path, &fourth.key_pem).unwrap();
+        let error = load_certified_key(&cert_path, &key_path, &provider).unwrap_err();
+        assert!(error.contains("does not belong"), "{}", error);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
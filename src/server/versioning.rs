@@ -0,0 +1,205 @@
+//! API versions.
+//!
+//! `/api/v2` is the current API. The unversioned `/api` routes are v1: they run the same
+//! handlers, but keep the error envelope and lockout status they had before problem
+//! details, and every response announces the deprecation and the v2 successor.
+
+use crate::config::Config;
+use crate::error::{Problem, PROBLEM_JSON};
+use actix_web::body::{to_bytes, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// Prefix of the v1 routes
+pub const V1_PREFIX: &str = "/api";
+/// Prefix of the v2 routes
+pub const V2_PREFIX: &str = "/api/v2";
+
+/// When v1 was deprecated, as sent in the `Deprecation` header (RFC 9745)
+const V1_DEPRECATED_AT: u64 = 1792281600; // 2026-10-18T00:00:00Z
+
+/// The v1 path a request path corresponds to, so route-based policies such as scopes,
+/// rate limits and access rules apply to both versions alike
+pub fn unversioned(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix(V2_PREFIX) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            Cow::Owned(format!("{}{}", V1_PREFIX, rest))
+        }
+        _ => Cow::Borrowed(path),
+    }
+}
+
+/// Path of a request as the router matches it: percent-encoded characters other than
+/// `/`, `%` and `+` are decoded, so `/api/%61dmin/unlock` is `/api/admin/unlock`.
+/// Route-based policies must classify this path, not the raw one.
+pub fn routed_path(req: &ServiceRequest) -> &str {
+    req.match_info().get_ref().path()
+}
+
+/// Unversioned pattern of the route serving a request, e.g. `/api/admin/api-keys/{id}`,
+/// or `None` when no route matches
+pub fn route_pattern(req: &ServiceRequest) -> Option<String> {
+    req.resource_map()
+        .match_pattern(routed_path(req))
+        .map(|pattern| unversioned(&pattern).into_owned())
+}
+
+/// The v2 path a v1 request path corresponds to
+fn successor(path: &str) -> String {
+    let rest = path.strip_prefix(V1_PREFIX).unwrap_or(path);
+    format!("{}{}", V2_PREFIX, rest)
+}
+
+/// v1 status and error body for a problem, as returned before problem details.
+/// Lockouts were reported as rate limiting and storage outages as internal errors.
+fn legacy_error(status: StatusCode, problem: &Problem) -> (StatusCode, serde_json::Value) {
+    let (status, code) = match status {
+        StatusCode::LOCKED => (StatusCode::TOO_MANY_REQUESTS, "too_many_requests"),
+        StatusCode::SERVICE_UNAVAILABLE => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+        _ => (status, problem.code.as_str()),
+    };
+    let body = serde_json::json!({
+        "error": {
+            "code": code,
+            "message": problem.detail,
+        }
+    });
+    (status, body)
+}
+
+/// Serve a v1 request: mark it deprecated and translate problem details back to the v1
+/// error envelope
+pub async fn v1(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let sunset = req
+        .app_data::<web::Data<Arc<Config>>>()
+        .and_then(|config| config.api_v1_sunset.clone());
+    let link = format!("<{}>; rel=\"successor-version\"", successor(req.path()));
+
+    let res = next.call(req).await?.map_into_boxed_body();
+    let is_problem = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v == PROBLEM_JSON);
+    let mut res = if is_problem {
+        let (req, response) = res.into_parts();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body())
+            .await
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+        let problem: Problem = serde_json::from_slice(&body)
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+
+        let (status, body) = legacy_error(status, &problem);
+        let mut legacy = HttpResponse::build(status);
+        for (name, value) in headers.iter() {
+            if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+                legacy.insert_header((name.clone(), value.clone()));
+            }
+        }
+        ServiceResponse::new(req, legacy.json(body))
+    } else {
+        res
+    };
+
+    let headers = res.headers_mut();
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_str(&format!("@{}", V1_DEPRECATED_AT))?,
+    );
+    headers.insert(header::LINK, HeaderValue::from_str(&link)?);
+    if let Some(sunset) = sunset {
+        headers.insert(
+            HeaderName::from_static("sunset"),
+            HeaderValue::from_str(&sunset)?,
+        );
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::OtpStore;
+    use actix_web::{test as actix_test, App};
+
+    #[test]
+    fn test_unversioned_paths() {
+        assert_eq!(unversioned("/api/v2/otp/verify"), "/api/otp/verify");
+        assert_eq!(unversioned("/api/v2"), "/api");
+        assert_eq!(unversioned("/api/otp/verify"), "/api/otp/verify");
+        assert_eq!(unversioned("/api/v2x/otp"), "/api/v2x/otp");
+        assert_eq!(successor("/api/hotp/verify"), "/api/v2/hotp/verify");
+    }
+
+    #[actix_web::test]
+    async fn test_v1_is_deprecated_with_legacy_errors() {
+        let config = Config {
+            lockout_max_credential_failures: 1,
+            api_v1_sunset: Some("Wed, 30 Jun 2027 00:00:00 GMT".to_string()),
+            ..Config::default()
+        };
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(
+                    Arc::new(MemoryStore::new()) as Arc<dyn OtpStore>
+                ))
+                .configure(crate::server::routes::configure_routes),
+        )
+        .await;
+        let verify = |prefix: &str, otp: &str| {
+            actix_test::TestRequest::post()
+                .uri(&format!("{}/hotp/verify", prefix))
+                .set_json(serde_json::json!({
+                    "secret": "3132333435363738393031323334353637383930",
+                    "otp": otp,
+                    "counter": 1,
+                }))
+                .to_request()
+        };
+
+        // Both versions serve the same handlers; only v1 is marked deprecated
+        let resp = actix_test::call_service(&app, verify(V1_PREFIX, "111111")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Deprecation").unwrap(), "@1792281600");
+        assert_eq!(
+            resp.headers().get("Link").unwrap(),
+            "</api/v2/hotp/verify>; rel=\"successor-version\""
+        );
+        assert_eq!(
+            resp.headers().get("Sunset").unwrap(),
+            "Wed, 30 Jun 2027 00:00:00 GMT"
+        );
+
+        // The credential is now locked out: v2 answers with problem details...
+        let resp = actix_test::call_service(&app, verify(V2_PREFIX, "287082")).await;
+        assert_eq!(resp.status(), StatusCode::LOCKED);
+        assert!(resp.headers().get("Deprecation").is_none());
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), PROBLEM_JSON);
+
+        // ...and v1 with its original status and error envelope
+        let resp = actix_test::call_service(&app, verify(V1_PREFIX, "287082")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().get("Retry-After").is_some());
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/json"
+        );
+        let body: serde_json::Value = actix_test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "too_many_requests");
+        assert_eq!(
+            body["error"]["message"],
+            "Too many failed attempts, try again later"
+        );
+    }
+}
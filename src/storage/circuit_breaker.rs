@@ -0,0 +1,332 @@
+use super::OtpStore;
+use crate::metrics::metrics;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Internal breaker state
+#[derive(Debug)]
+enum BreakerState {
+    /// Calls pass through; counts consecutive failures
+    Closed { consecutive_failures: u32 },
+    /// Calls are rejected without touching the inner store until `until`
+    Open { until: Instant },
+    /// A single probe call is in flight; everything else is rejected
+    HalfOpen,
+}
+
+/// OtpStore wrapper that stops calling the inner store after repeated failures.
+///
+/// After `failure_threshold` consecutive failures the breaker opens and every call
+/// fails immediately. Once `reset_timeout` has elapsed a single probe call is let
+/// through (half-open): success closes the breaker, failure opens it again, as does a
+/// probe that is dropped before it completes.
+pub struct CircuitBreakerStore {
+    inner: Arc<dyn OtpStore>,
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreakerStore {
+    pub fn new(inner: Arc<dyn OtpStore>, failure_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            inner,
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            state: Mutex::new(BreakerState::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Decide whether a call may proceed, moving Open to HalfOpen when the timeout expires.
+    /// Returns whether the call is the probe.
+    fn acquire(&self) -> Result<bool, String> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => Ok(false),
+            BreakerState::Open { until } if Instant::now() >= until => {
+                log::info!("Storage circuit breaker half-open, sending probe");
+                *state = BreakerState::HalfOpen;
+                Ok(true)
+            }
+            BreakerState::Open { .. } => Err("Storage circuit breaker is open".to_string()),
+            BreakerState::HalfOpen => {
+                Err("Storage circuit breaker is half-open, probe in flight".to_string())
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(*state, BreakerState::HalfOpen) {
+            log::info!("Storage circuit breaker closed, probe succeeded");
+            metrics().set_circuit_open(false);
+        }
+        *state = BreakerState::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let open = match *state {
+            BreakerState::Closed {
+                consecutive_failures,
+            } => {
+                let failures = consecutive_failures + 1;
+                *state = BreakerState::Closed {
+                    consecutive_failures: failures,
+                };
+                failures >= self.failure_threshold
+            }
+            // A failed probe (or a failure racing with one) re-opens the breaker
+            BreakerState::HalfOpen | BreakerState::Open { .. } => true,
+        };
+
+        if open {
+            log::error!(
+                "Storage circuit breaker open for {}s",
+                self.reset_timeout.as_secs()
+            );
+            *state = BreakerState::Open {
+                until: Instant::now() + self.reset_timeout,
+            };
+            metrics().set_circuit_open(true);
+        }
+    }
+
+    async fn call<T, F, Fut>(&self, op: F) -> Result<T, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, String>>,
+    {
+        let mut probe = ProbeGuard {
+            breaker: self,
+            pending: self.acquire()?,
+        };
+        let result = op().await;
+        probe.pending = false;
+        match result {
+            Ok(_) => self.record_success(),
+            Err(_) => self.record_failure(),
+        }
+        result
+    }
+}
+
+/// Fails a probe whose call is dropped before it completes, e.g. when the request waiting
+/// on it is cancelled, so the breaker isn't left half-open with no probe in flight
+struct ProbeGuard<'a> {
+    breaker: &'a CircuitBreakerStore,
+    pending: bool,
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        if self.pending {
+            log::warn!("Storage circuit breaker probe was cancelled");
+            self.breaker.record_failure();
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OtpStore for CircuitBreakerStore {
+    async fn mark_used(&self, tenant: &str, otp: &str, expiry_seconds: u64) -> Result<(), String> {
+        self.call(|| self.inner.mark_used(tenant, otp, expiry_seconds))
+            .await
+    }
+
+    async fn is_used(&self, tenant: &str, otp: &str) -> Result<bool, String> {
+        self.call(|| self.inner.is_used(tenant, otp)).await
+    }
+
+    async fn mark_used_once(
+        &self,
+        tenant: &str,
+        otp: &str,
+        expiry_seconds: u64,
+    ) -> Result<bool, String> {
+        self.call(|| self.inner.mark_used_once(tenant, otp, expiry_seconds))
+            .await
+    }
+
+    async fn increment(&self, tenant: &str, key: &str, window_seconds: u64) -> Result<u64, String> {
+        self.call(|| self.inner.increment(tenant, key, window_seconds))
+            .await
+    }
+
+    async fn counter(&self, tenant: &str, key: &str) -> Result<u64, String> {
+        self.call(|| self.inner.counter(tenant, key)).await
+    }
+
+    async fn set_flag(&self, tenant: &str, key: &str, seconds: u64) -> Result<(), String> {
+        self.call(|| self.inner.set_flag(tenant, key, seconds))
+            .await
+    }
+
+    async fn set_value(&self, tenant: &str, key: &str, value: &str) -> Result<(), String> {
+        self.call(|| self.inner.set_value(tenant, key, value)).await
+    }
+
+    async fn get_value(&self, tenant: &str, key: &str) -> Result<Option<String>, String> {
+        self.call(|| self.inner.get_value(tenant, key)).await
+    }
+
+    async fn flag_ttl(&self, tenant: &str, key: &str) -> Result<Option<u64>, String> {
+        self.call(|| self.inner.flag_ttl(tenant, key)).await
+    }
+
+    async fn remove(&self, tenant: &str, keys: &[String]) -> Result<(), String> {
+        self.call(|| self.inner.remove(tenant, keys)).await
+    }
+
+    async fn schedule(
+        &self,
+        tenant: &str,
+        queue: &str,
+        item: &str,
+        due_at: u64,
+    ) -> Result<(), String> {
+        self.call(|| self.inner.schedule(tenant, queue, item, due_at))
+            .await
+    }
+
+    async fn claim_due(
+        &self,
+        tenant: &str,
+        queue: &str,
+        now: u64,
+        lease_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<String>, String> {
+        self.call(|| {
+            self.inner
+                .claim_due(tenant, queue, now, lease_seconds, limit)
+        })
+        .await
+    }
+
+    async fn unschedule(&self, tenant: &str, queue: &str, item: &str) -> Result<(), String> {
+        self.call(|| self.inner.unschedule(tenant, queue, item))
+            .await
+    }
+
+    async fn purge_tenant(&self, tenant: &str) -> Result<u64, String> {
+        self.call(|| self.inner.purge_tenant(tenant)).await
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        self.call(|| self.inner.ping()).await
+    }
+
+    async fn server_time(&self) -> Result<u64, String> {
+        self.call(|| self.inner.server_time()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore;
+
+    fn breaker(reset_timeout: Duration) -> (Arc<FaultInjectingStore>, CircuitBreakerStore) {
+        let faulty = Arc::new(FaultInjectingStore::new(Arc::new(MemoryStore::new())));
+        let breaker = CircuitBreakerStore::new(faulty.clone(), 3, reset_timeout);
+        (faulty, breaker)
+    }
+
+    #[tokio::test]
+    async fn test_opens_after_threshold_and_short_circuits() {
+        let (faulty, breaker) = breaker(Duration::from_secs(60));
+        faulty.set_failing(true);
+
+        for _ in 0..3 {
+            assert!(breaker.is_used("default", "123456").await.is_err());
+        }
+        assert_eq!(faulty.calls(), 3);
+
+        // Breaker is open: calls fail without reaching the inner store
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert_eq!(faulty.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_success_resets_failure_count() {
+        let (faulty, breaker) = breaker(Duration::from_secs(60));
+
+        faulty.set_failing(true);
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        faulty.set_failing(false);
+        assert!(breaker.is_used("default", "123456").await.is_ok());
+        faulty.set_failing(true);
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert!(breaker.is_used("default", "123456").await.is_err());
+
+        // Still closed: only two consecutive failures since the last success
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert_eq!(faulty.calls(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe_closes_on_success() {
+        let (faulty, breaker) = breaker(Duration::from_millis(20));
+        faulty.set_failing(true);
+        for _ in 0..3 {
+            assert!(breaker.is_used("default", "123456").await.is_err());
+        }
+
+        faulty.set_failing(false);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // Probe goes through and closes the breaker
+        assert!(breaker.mark_used("default", "123456", 30).await.is_ok());
+        assert!(breaker.is_used("default", "123456").await.unwrap());
+        assert_eq!(faulty.calls(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe_failure_reopens() {
+        let (faulty, breaker) = breaker(Duration::from_millis(20));
+        faulty.set_failing(true);
+        for _ in 0..3 {
+            assert!(breaker.is_used("default", "123456").await.is_err());
+        }
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // Probe reaches the store, fails, and re-opens the breaker
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert_eq!(faulty.calls(), 4);
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert_eq!(faulty.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_probe_reopens() {
+        let (faulty, breaker) = breaker(Duration::from_millis(20));
+        faulty.set_failing(true);
+        for _ in 0..3 {
+            assert!(breaker.is_used("default", "123456").await.is_err());
+        }
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // The probe never completes and is dropped, like a cancelled request's
+        let probe = breaker.call(std::future::pending::<Result<(), String>>);
+        assert!(tokio::time::timeout(Duration::from_millis(10), probe)
+            .await
+            .is_err());
+
+        // The breaker is open again rather than stuck half-open, and probes once more
+        assert!(breaker.is_used("default", "123456").await.is_err());
+        assert_eq!(faulty.calls(), 3);
+        faulty.set_failing(false);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(breaker.is_used("default", "123456").await.is_ok());
+        assert!(breaker.is_used("default", "123456").await.is_ok());
+    }
+}
@@ -0,0 +1,156 @@
+use super::OtpStore;
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// OtpStore wrapper that fails or slows down on demand, used to simulate storage outages
+/// and round trips in tests
+pub struct FaultInjectingStore {
+    inner: Arc<dyn OtpStore>,
+    failing: AtomicBool,
+    calls: AtomicUsize,
+    delay_ms: AtomicU64,
+}
+
+impl FaultInjectingStore {
+    pub fn new(inner: Arc<dyn OtpStore>) -> Self {
+        Self {
+            inner,
+            failing: AtomicBool::new(false),
+            calls: AtomicUsize::new(0),
+            delay_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Start or stop failing every call
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Number of calls that reached this store, failed or not
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Wait this long before every call, so concurrent requests interleave around it
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay_ms
+            .store(delay.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let delay = self.delay_ms.load(Ordering::SeqCst);
+        if delay > 0 {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+        if self.failing.load(Ordering::SeqCst) {
+            return Err("Injected storage fault".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OtpStore for FaultInjectingStore {
+    async fn mark_used(&self, tenant: &str, otp: &str, expiry_seconds: u64) -> Result<(), String> {
+        self.check().await?;
+        self.inner.mark_used(tenant, otp, expiry_seconds).await
+    }
+
+    async fn is_used(&self, tenant: &str, otp: &str) -> Result<bool, String> {
+        self.check().await?;
+        self.inner.is_used(tenant, otp).await
+    }
+
+    async fn mark_used_once(
+        &self,
+        tenant: &str,
+        otp: &str,
+        expiry_seconds: u64,
+    ) -> Result<bool, String> {
+        self.check().await?;
+        self.inner.mark_used_once(tenant, otp, expiry_seconds).await
+    }
+
+    async fn increment(&self, tenant: &str, key: &str, window_seconds: u64) -> Result<u64, String> {
+        self.check().await?;
+        self.inner.increment(tenant, key, window_seconds).await
+    }
+
+    async fn counter(&self, tenant: &str, key: &str) -> Result<u64, String> {
+        self.check().await?;
+        self.inner.counter(tenant, key).await
+    }
+
+    async fn set_flag(&self, tenant: &str, key: &str, seconds: u64) -> Result<(), String> {
+        self.check().await?;
+        self.inner.set_flag(tenant, key, seconds).await
+    }
+
+    async fn set_value(&self, tenant: &str, key: &str, value: &str) -> Result<(), String> {
+        self.check().await?;
+        self.inner.set_value(tenant, key, value).await
+    }
+
+    async fn get_value(&self, tenant: &str, key: &str) -> Result<Option<String>, String> {
+        self.check().await?;
+        self.inner.get_value(tenant, key).await
+    }
+
+    async fn flag_ttl(&self, tenant: &str, key: &str) -> Result<Option<u64>, String> {
+        self.check().await?;
+        self.inner.flag_ttl(tenant, key).await
+    }
+
+    async fn remove(&self, tenant: &str, keys: &[String]) -> Result<(), String> {
+        self.check().await?;
+        self.inner.remove(tenant, keys).await
+    }
+
+    async fn schedule(
+        &self,
+        tenant: &str,
+        queue: &str,
+        item: &str,
+        due_at: u64,
+    ) -> Result<(), String> {
+        self.check().await?;
+        self.inner.schedule(tenant, queue, item, due_at).await
+    }
+
+    async fn claim_due(
+        &self,
+        tenant: &str,
+        queue: &str,
+        now: u64,
+        lease_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<String>, String> {
+        self.check().await?;
+        self.inner
+            .claim_due(tenant, queue, now, lease_seconds, limit)
+            .await
+    }
+
+    async fn unschedule(&self, tenant: &str, queue: &str, item: &str) -> Result<(), String> {
+        self.check().await?;
+        self.inner.unschedule(tenant, queue, item).await
+    }
+
+    async fn purge_tenant(&self, tenant: &str) -> Result<u64, String> {
+        self.check().await?;
+        self.inner.purge_tenant(tenant).await
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        self.check().await?;
+        self.inner.ping().await
+    }
+
+    async fn server_time(&self) -> Result<u64, String> {
+        self.check().await?;
+        self.inner.server_time().await
+    }
+}
@@ -0,0 +1,179 @@
+use super::OtpStore;
+use crate::metrics::metrics;
+use crate::telemetry;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// OtpStore wrapper around the Redis store that records the latency and result of every
+/// call and traces it as a client span of the request that made it
+pub struct InstrumentedStore {
+    inner: Arc<dyn OtpStore>,
+}
+
+impl InstrumentedStore {
+    pub fn new(inner: Arc<dyn OtpStore>) -> Self {
+        Self { inner }
+    }
+
+    async fn call<T, Fut>(&self, operation: &'static str, op: Fut) -> Result<T, String>
+    where
+        Fut: Future<Output = Result<T, String>>,
+    {
+        let attributes = vec![
+            KeyValue::new("db.system.name", "redis"),
+            KeyValue::new("db.operation.name", operation),
+        ];
+        telemetry::in_span(
+            format!("storage {}", operation),
+            SpanKind::Client,
+            attributes,
+            async {
+                let started = Instant::now();
+                let result = op.await;
+                metrics().storage_call(operation, result.is_ok(), started);
+                if let Err(e) = &result {
+                    telemetry::record_error(e);
+                }
+                result
+            },
+        )
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl OtpStore for InstrumentedStore {
+    async fn mark_used(&self, tenant: &str, otp: &str, expiry_seconds: u64) -> Result<(), String> {
+        self.call(
+            "mark_used",
+            self.inner.mark_used(tenant, otp, expiry_seconds),
+        )
+        .await
+    }
+
+    async fn is_used(&self, tenant: &str, otp: &str) -> Result<bool, String> {
+        self.call("is_used", self.inner.is_used(tenant, otp)).await
+    }
+
+    async fn mark_used_once(
+        &self,
+        tenant: &str,
+        otp: &str,
+        expiry_seconds: u64,
+    ) -> Result<bool, String> {
+        self.call(
+            "mark_used_once",
+            self.inner.mark_used_once(tenant, otp, expiry_seconds),
+        )
+        .await
+    }
+
+    async fn increment(&self, tenant: &str, key: &str, window_seconds: u64) -> Result<u64, String> {
+        self.call(
+            "increment",
+            self.inner.increment(tenant, key, window_seconds),
+        )
+        .await
+    }
+
+    async fn counter(&self, tenant: &str, key: &str) -> Result<u64, String> {
+        self.call("counter", self.inner.counter(tenant, key)).await
+    }
+
+    async fn set_flag(&self, tenant: &str, key: &str, seconds: u64) -> Result<(), String> {
+        self.call("set_flag", self.inner.set_flag(tenant, key, seconds))
+            .await
+    }
+
+    async fn flag_ttl(&self, tenant: &str, key: &str) -> Result<Option<u64>, String> {
+        self.call("flag_ttl", self.inner.flag_ttl(tenant, key))
+            .await
+    }
+
+    async fn set_value(&self, tenant: &str, key: &str, value: &str) -> Result<(), String> {
+        self.call("set_value", self.inner.set_value(tenant, key, value))
+            .await
+    }
+
+    async fn get_value(&self, tenant: &str, key: &str) -> Result<Option<String>, String> {
+        self.call("get_value", self.inner.get_value(tenant, key))
+            .await
+    }
+
+    async fn remove(&self, tenant: &str, keys: &[String]) -> Result<(), String> {
+        self.call("remove", self.inner.remove(tenant, keys)).await
+    }
+
+    async fn schedule(
+        &self,
+        tenant: &str,
+        queue: &str,
+        item: &str,
+        due_at: u64,
+    ) -> Result<(), String> {
+        self.call("schedule", self.inner.schedule(tenant, queue, item, due_at))
+            .await
+    }
+
+    async fn claim_due(
+        &self,
+        tenant: &str,
+        queue: &str,
+        now: u64,
+        lease_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<String>, String> {
+        self.call(
+            "claim_due",
+            self.inner
+                .claim_due(tenant, queue, now, lease_seconds, limit),
+        )
+        .await
+    }
+
+    async fn unschedule(&self, tenant: &str, queue: &str, item: &str) -> Result<(), String> {
+        self.call("unschedule", self.inner.unschedule(tenant, queue, item))
+            .await
+    }
+
+    async fn purge_tenant(&self, tenant: &str) -> Result<u64, String> {
+        self.call("purge_tenant", self.inner.purge_tenant(tenant))
+            .await
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        self.call("ping", self.inner.ping()).await
+    }
+
+    async fn server_time(&self) -> Result<u64, String> {
+        self.call("server_time", self.inner.server_time()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_records_calls_by_result() {
+        let faulty = Arc::new(FaultInjectingStore::new(Arc::new(MemoryStore::new())));
+        let store = InstrumentedStore::new(faulty.clone());
+
+        assert!(store.flag_ttl("default", "metered").await.is_ok());
+        faulty.set_failing(true);
+        assert!(store.flag_ttl("default", "metered").await.is_err());
+
+        let rendered = metrics().render(None);
+        for result in ["ok", "error"] {
+            assert!(rendered.contains(&format!(
+                r#"otp_storage_operation_duration_seconds_count{{operation="flag_ttl",result="{}"}}"#,
+                result
+            )));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Derives opaque storage keys for used OTPs so storage never holds codes or counters.
+///
+/// Keys are `HMAC-SHA256(pepper, tenant || 0x00 || otp)`. During a pepper rotation the
+/// previous pepper is still checked on lookup; it can be dropped once one OTP expiry
+/// period has passed, as every key derived from it has expired by then.
+#[derive(Clone)]
+pub struct ReplayKeyHasher {
+    current: Vec<u8>,
+    previous: Option<Vec<u8>>,
+}
+
+impl ReplayKeyHasher {
+    pub fn new(current: &[u8], previous: Option<&[u8]>) -> Self {
+        Self {
+            current: current.to_vec(),
+            previous: previous.map(|p| p.to_vec()),
+        }
+    }
+
+    /// Key under which a used OTP is recorded
+    pub fn derive(&self, tenant: &str, otp: &str) -> String {
+        Self::hmac(&self.current, tenant, otp)
+    }
+
+    /// Every key a used OTP may have been recorded under, current pepper first
+    pub fn candidates(&self, tenant: &str, otp: &str) -> Vec<String> {
+        let mut keys = vec![self.derive(tenant, otp)];
+        if let Some(previous) = &self.previous {
+            keys.push(Self::hmac(previous, tenant, otp));
+        }
+        keys
+    }
+
+    fn hmac(pepper: &[u8], tenant: &str, otp: &str) -> String {
+        // HMAC accepts keys of any length, including empty
+        let mut mac = HmacSha256::new_from_slice(pepper).expect("HMAC accepts any key length");
+        mac.update(tenant.as_bytes());
+        mac.update(&[0]);
+        mac.update(otp.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_hides_code() {
+        let hasher = ReplayKeyHasher::new(b"pepper", None);
+        let key = hasher.derive("default", "287082");
+        assert_eq!(key.len(), 64);
+        assert!(!key.contains("287082"));
+        assert_eq!(key, hasher.derive("default", "287082"));
+    }
+
+    #[test]
+    fn test_derive_depends_on_pepper_and_tenant() {
+        let hasher = ReplayKeyHasher::new(b"pepper", None);
+        let other = ReplayKeyHasher::new(b"other-pepper", None);
+        assert_ne!(
+            hasher.derive("default", "287082"),
+            other.derive("default", "287082")
+        );
+        assert_ne!(
+            hasher.derive("acme", "287082"),
+            hasher.derive("globex", "287082")
+        );
+    }
+
+    #[test]
+    fn test_candidates_include_previous_pepper() {
+        let old = ReplayKeyHasher::new(b"old", None);
+        let rotated = ReplayKeyHasher::new(b"new", Some(b"old"));
+
+        let candidates = rotated.candidates("default", "287082");
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0], rotated.derive("default", "287082"));
+        // Keys written before the rotation are still found
+        assert_eq!(candidates[1], old.derive("default", "287082"));
+    }
+}
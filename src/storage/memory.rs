@@ -0,0 +1,215 @@
+use super::OtpStore;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A counter or flag with its expiry
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    value: u64,
+    expires_at: Instant,
+}
+
+/// In-memory OtpStore for testing handlers and storage wrappers in isolation
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// Used OTPs keyed by (tenant, otp)
+    used_otps: DashMap<(String, String), ()>,
+    /// Counters and flags keyed by (tenant, key)
+    entries: DashMap<(String, String), Entry>,
+    /// Values keyed by (tenant, key)
+    values: DashMap<(String, String), String>,
+    /// Queued items and when they are due, keyed by (tenant, queue)
+    queues: DashMap<(String, String), HashMap<String, u64>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Live entry for a key, dropping it if it has expired
+    fn live_entry(&self, tenant: &str, key: &str) -> Option<Entry> {
+        let id = (tenant.to_string(), key.to_string());
+        let entry = *self.entries.get(&id)?;
+        if entry.expires_at <= Instant::now() {
+            self.entries.remove(&id);
+            return None;
+        }
+        Some(entry)
+    }
+}
+
+#[async_trait]
+impl OtpStore for MemoryStore {
+    async fn mark_used(
+        &self,
+        tenant: &str,
+        otp_key: &str,
+        _expiry_seconds: u64,
+    ) -> Result<(), String> {
+        self.used_otps
+            .insert((tenant.to_string(), otp_key.to_string()), ());
+        Ok(())
+    }
+
+    async fn is_used(&self, tenant: &str, otp_key: &str) -> Result<bool, String> {
+        Ok(self
+            .used_otps
+            .contains_key(&(tenant.to_string(), otp_key.to_string())))
+    }
+
+    async fn mark_used_once(
+        &self,
+        tenant: &str,
+        otp_key: &str,
+        _expiry_seconds: u64,
+    ) -> Result<bool, String> {
+        match self
+            .used_otps
+            .entry((tenant.to_string(), otp_key.to_string()))
+        {
+            dashmap::mapref::entry::Entry::Occupied(_) => Ok(false),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(());
+                Ok(true)
+            }
+        }
+    }
+
+    async fn increment(&self, tenant: &str, key: &str, window_seconds: u64) -> Result<u64, String> {
+        let entry = match self.live_entry(tenant, key) {
+            Some(entry) => Entry {
+                value: entry.value + 1,
+                ..entry
+            },
+            None => Entry {
+                value: 1,
+                expires_at: Instant::now() + Duration::from_secs(window_seconds),
+            },
+        };
+        self.entries
+            .insert((tenant.to_string(), key.to_string()), entry);
+        Ok(entry.value)
+    }
+
+    async fn counter(&self, tenant: &str, key: &str) -> Result<u64, String> {
+        Ok(self.live_entry(tenant, key).map_or(0, |e| e.value))
+    }
+
+    async fn set_flag(&self, tenant: &str, key: &str, seconds: u64) -> Result<(), String> {
+        self.entries.insert(
+            (tenant.to_string(), key.to_string()),
+            Entry {
+                value: 1,
+                expires_at: Instant::now() + Duration::from_secs(seconds),
+            },
+        );
+        Ok(())
+    }
+
+    async fn set_value(&self, tenant: &str, key: &str, value: &str) -> Result<(), String> {
+        self.values
+            .insert((tenant.to_string(), key.to_string()), value.to_string());
+        Ok(())
+    }
+
+    async fn get_value(&self, tenant: &str, key: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .values
+            .get(&(tenant.to_string(), key.to_string()))
+            .map(|v| v.clone()))
+    }
+
+    async fn flag_ttl(&self, tenant: &str, key: &str) -> Result<Option<u64>, String> {
+        Ok(self.live_entry(tenant, key).map(|e| {
+            // Round up like Redis TTL so a live flag never reports 0
+            let remaining = e.expires_at.saturating_duration_since(Instant::now());
+            remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)
+        }))
+    }
+
+    async fn remove(&self, tenant: &str, keys: &[String]) -> Result<(), String> {
+        for key in keys {
+            self.entries.remove(&(tenant.to_string(), key.clone()));
+            self.values.remove(&(tenant.to_string(), key.clone()));
+            self.queues.remove(&(tenant.to_string(), key.clone()));
+        }
+        Ok(())
+    }
+
+    async fn schedule(
+        &self,
+        tenant: &str,
+        queue: &str,
+        item: &str,
+        due_at: u64,
+    ) -> Result<(), String> {
+        self.queues
+            .entry((tenant.to_string(), queue.to_string()))
+            .or_default()
+            .insert(item.to_string(), due_at);
+        Ok(())
+    }
+
+    async fn claim_due(
+        &self,
+        tenant: &str,
+        queue: &str,
+        now: u64,
+        lease_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<String>, String> {
+        let mut items = match self
+            .queues
+            .get_mut(&(tenant.to_string(), queue.to_string()))
+        {
+            Some(items) => items,
+            None => return Ok(Vec::new()),
+        };
+        let mut due: Vec<(u64, String)> = items
+            .iter()
+            .filter(|(_, due_at)| **due_at <= now)
+            .map(|(item, due_at)| (*due_at, item.clone()))
+            .collect();
+        due.sort();
+        due.truncate(limit);
+        for (_, item) in &due {
+            items.insert(item.clone(), now + lease_seconds);
+        }
+        Ok(due.into_iter().map(|(_, item)| item).collect())
+    }
+
+    async fn unschedule(&self, tenant: &str, queue: &str, item: &str) -> Result<(), String> {
+        if let Some(mut items) = self
+            .queues
+            .get_mut(&(tenant.to_string(), queue.to_string()))
+        {
+            items.remove(item);
+        }
+        Ok(())
+    }
+
+    async fn purge_tenant(&self, tenant: &str) -> Result<u64, String> {
+        let len =
+            || self.used_otps.len() + self.entries.len() + self.values.len() + self.queues.len();
+        let before = len();
+        self.used_otps.retain(|(t, _), _| t != tenant);
+        self.entries.retain(|(t, _), _| t != tenant);
+        self.values.retain(|(t, _), _| t != tenant);
+        self.queues.retain(|(t, _), _| t != tenant);
+        Ok((before - len()) as u64)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn server_time(&self) -> Result<u64, String> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .map_err(|e| e.to_string())
+    }
+}
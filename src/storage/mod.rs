@@ -0,0 +1,259 @@
+use crate::config::Config;
+use crate::logging;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod circuit_breaker;
+#[cfg(test)]
+pub mod fault;
+mod instrumented;
+mod keys;
+#[cfg(any(test, feature = "test-util"))]
+pub mod memory;
+mod redis_store;
+mod status;
+
+pub use circuit_breaker::CircuitBreakerStore;
+pub use instrumented::InstrumentedStore;
+pub use keys::ReplayKeyHasher;
+pub use redis_store::{ConnectRetry, RedisStore};
+pub use status::{StorageState, StorageStatus};
+
+/// Namespace for data that belongs to no tenant, such as per-IP rate limits.
+/// Tenant IDs can't start with `_`, so it never collides with a tenant.
+pub const SYSTEM_TENANT: &str = "_system";
+
+/// Storage trait for OTP storage backends.
+///
+/// Every data operation takes a tenant; backends must keep tenants' data isolated.
+#[async_trait::async_trait]
+pub trait OtpStore: Send + Sync {
+    /// Mark an OTP as used
+    async fn mark_used(&self, tenant: &str, otp: &str, expiry_seconds: u64) -> Result<(), String>;
+
+    /// Check if an OTP has been used
+    async fn is_used(&self, tenant: &str, otp: &str) -> Result<bool, String>;
+
+    /// Mark an OTP as used unless it already is, in one atomic step; `false` when it was
+    /// already used, so of concurrent callers only one gets `true`
+    async fn mark_used_once(
+        &self,
+        tenant: &str,
+        otp: &str,
+        expiry_seconds: u64,
+    ) -> Result<bool, String>;
+
+    /// Increment a counter, returning the new value. The counter expires
+    /// `window_seconds` after it was created; later increments don't extend it.
+    async fn increment(&self, tenant: &str, key: &str, window_seconds: u64) -> Result<u64, String>;
+
+    /// Current value of a counter, 0 if it doesn't exist or has expired
+    async fn counter(&self, tenant: &str, key: &str) -> Result<u64, String>;
+
+    /// Set a flag that expires after `seconds`, replacing any existing expiry
+    async fn set_flag(&self, tenant: &str, key: &str, seconds: u64) -> Result<(), String>;
+
+    /// Remaining lifetime of a flag in seconds, `None` if it isn't set
+    async fn flag_ttl(&self, tenant: &str, key: &str) -> Result<Option<u64>, String>;
+
+    /// Store a value that doesn't expire, replacing any existing one
+    async fn set_value(&self, tenant: &str, key: &str, value: &str) -> Result<(), String>;
+
+    /// Value stored under a key, `None` if there is none
+    async fn get_value(&self, tenant: &str, key: &str) -> Result<Option<String>, String>;
+
+    /// Remove counters, flags or values
+    async fn remove(&self, tenant: &str, keys: &[String]) -> Result<(), String>;
+
+    /// Add an item to a queue, due at `due_at` seconds since the Unix epoch.
+    /// Scheduling an item that is already queued moves it to the new time.
+    async fn schedule(
+        &self,
+        tenant: &str,
+        queue: &str,
+        item: &str,
+        due_at: u64,
+    ) -> Result<(), String>;
+
+    /// Claim up to `limit` items due by `now`, earliest first. Claimed items stay queued
+    /// but only come due again after `lease_seconds`, so an item whose claimant dies
+    /// before unscheduling it is handed out again.
+    async fn claim_due(
+        &self,
+        tenant: &str,
+        queue: &str,
+        now: u64,
+        lease_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<String>, String>;
+
+    /// Remove an item from a queue
+    async fn unschedule(&self, tenant: &str, queue: &str, item: &str) -> Result<(), String>;
+
+    /// Delete everything stored for a tenant, returning the number of entries removed
+    async fn purge_tenant(&self, tenant: &str) -> Result<u64, String>;
+
+    /// Round-trip a no-op command to check the backend is reachable
+    async fn ping(&self) -> Result<(), String>;
+
+    /// Current time according to the backend, in seconds since the Unix epoch
+    async fn server_time(&self) -> Result<u64, String>;
+}
+
+/// Factory for creating OTP storage backends
+pub struct OtpStorage;
+
+impl OtpStorage {
+    /// Create a new OTP storage backend based on configuration.
+    ///
+    /// Returns immediately; the connection is established in a background task that
+    /// reports progress through the returned `StorageStatus`.
+    #[allow(clippy::new_ret_no_self)] // This is a factory function, not a constructor for OtpStorage
+    pub fn new(config: &Config) -> Result<(Arc<dyn OtpStore>, StorageStatus), String> {
+        log::info!(
+            "Using Redis storage for OTPs at {} with key prefix '{}'",
+            logging::redact_url(&config.redis_url),
+            config.redis_key_prefix
+        );
+        // With a known or guessable pepper the keys of used codes can be recomputed from
+        // the few million possible codes
+        check_replay_key_pepper(&config.replay_key_pepper)?;
+        let replay_keys = ReplayKeyHasher::new(
+            config.replay_key_pepper.as_bytes(),
+            config
+                .replay_key_pepper_previous
+                .as_ref()
+                .map(|p| p.as_bytes()),
+        );
+        let store = Arc::new(RedisStore::open(
+            &config.redis_url,
+            &config.redis_key_prefix,
+            replay_keys,
+        )?);
+        let status = StorageStatus::default();
+
+        let retry = ConnectRetry {
+            max_retries: config.redis_connect_retries,
+            initial_backoff: Duration::from_millis(config.redis_connect_backoff_ms),
+            max_backoff: Duration::from_millis(config.redis_connect_backoff_max_ms),
+        };
+        let connecting = store.clone();
+        let connect_status = status.clone();
+        let ping_interval = Duration::from_secs(config.storage_ping_interval_seconds.max(1));
+        tokio::spawn(async move {
+            match connecting.connect(&retry).await {
+                Ok(()) => connect_status.set_ready(),
+                Err(e) => {
+                    log::error!("Failed to initialize OTP storage: {}", e);
+                    connect_status.set_failed(e);
+                }
+            }
+            watch(connecting, connect_status, ping_interval).await;
+        });
+
+        // Guard every storage call so an outage fails fast instead of piling up timeouts.
+        // Latency and spans are recorded inside the breaker, so they reflect Redis round trips.
+        let store = CircuitBreakerStore::new(
+            Arc::new(InstrumentedStore::new(store)),
+            config.circuit_breaker_failure_threshold,
+            Duration::from_secs(config.circuit_breaker_reset_seconds),
+        );
+        Ok((Arc::new(store), status))
+    }
+}
+
+/// Shortest accepted `REPLAY_KEY_PEPPER`, in characters
+const MIN_PEPPER_LEN: usize = 32;
+
+/// Peppers that were published with the example configuration
+const PUBLISHED_PEPPERS: &[&str] = &["change-me"];
+
+fn check_replay_key_pepper(pepper: &str) -> Result<(), String> {
+    if pepper.is_empty() {
+        return Err("REPLAY_KEY_PEPPER must be set".to_string());
+    }
+    if PUBLISHED_PEPPERS.contains(&pepper) {
+        return Err(
+            "REPLAY_KEY_PEPPER is a published example value; generate your own with \
+             `openssl rand -hex 32`"
+                .to_string(),
+        );
+    }
+    if pepper.len() < MIN_PEPPER_LEN {
+        return Err(format!(
+            "REPLAY_KEY_PEPPER must be at least {} characters",
+            MIN_PEPPER_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Keep the storage state current by pinging the store: a failed ping marks it failed, so
+/// readiness drops during an outage, and the next successful one marks it ready again.
+/// This also keeps retrying after the initial connection gave up.
+async fn watch(store: Arc<dyn OtpStore>, status: StorageStatus, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let ping = tokio::time::timeout(interval, store.ping())
+            .await
+            .unwrap_or_else(|_| Err("Storage ping timed out".to_string()));
+        let ready = status.state() == StorageState::Ready;
+        match ping {
+            Ok(()) if !ready => {
+                log::info!("OTP storage is answering again");
+                status.set_ready();
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if ready {
+                    log::error!("OTP storage stopped answering: {}", e);
+                }
+                status.set_failed(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore;
+
+    #[test]
+    fn test_replay_key_pepper_is_required() {
+        let error = OtpStorage::new(&Config::default()).err().unwrap();
+        assert_eq!(error, "REPLAY_KEY_PEPPER must be set");
+
+        let error = check_replay_key_pepper("change-me").unwrap_err();
+        assert!(error.contains("published"), "{}", error);
+        let error = check_replay_key_pepper("short-but-secret").unwrap_err();
+        assert!(error.contains("at least 32"), "{}", error);
+        assert!(check_replay_key_pepper(&"p".repeat(32)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_pings() {
+        let faulty = Arc::new(FaultInjectingStore::new(Arc::new(MemoryStore::new())));
+        let status = StorageStatus::default();
+        status.set_ready();
+        let task = tokio::spawn(watch(
+            faulty.clone(),
+            status.clone(),
+            Duration::from_millis(10),
+        ));
+
+        // An outage demotes a ready store, and recovery promotes it again
+        faulty.set_failing(true);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(status.state(), StorageState::Failed);
+        assert_eq!(status.last_error().unwrap(), "Injected storage fault");
+        faulty.set_failing(false);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(status.state(), StorageState::Ready);
+
+        task.abort();
+    }
+}
@@ -0,0 +1,422 @@
+use super::{OtpStore, ReplayKeyHasher};
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, Client as RedisClient};
+use std::time::Duration;
+use tokio::time;
+
+/// Redis storage for used OTPs.
+///
+/// Every key is namespaced as `{key_prefix}:{tenant}:...` so several environments and
+/// tenants can share one Redis without seeing each other's data. Used OTPs are stored
+/// under keyed hashes, never as plaintext codes.
+pub struct RedisStore {
+    client: RedisClient,
+    key_prefix: String,
+    replay_keys: ReplayKeyHasher,
+}
+
+/// Retry settings for the initial Redis connection
+#[derive(Debug, Clone)]
+pub struct ConnectRetry {
+    /// Retries after the first attempt; 0 retries forever
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RedisStore {
+    /// Create a new Redis OTP storage without connecting; call `connect` to establish the connection
+    pub fn open(
+        redis_url: &str,
+        key_prefix: &str,
+        replay_keys: ReplayKeyHasher,
+    ) -> Result<Self, String> {
+        // Create Redis client
+        let client = RedisClient::open(redis_url)
+            .map_err(|e| format!("Failed to create Redis client: {}", e))?;
+        Ok(Self {
+            client,
+            key_prefix: key_prefix.to_string(),
+            replay_keys,
+        })
+    }
+
+    /// Connect to Redis with retry logic, returning once a PING succeeds
+    pub async fn connect(&self, retry: &ConnectRetry) -> Result<(), String> {
+        // Retry connection with exponential backoff
+        let mut retry_count = 0;
+        let mut backoff = retry.initial_backoff;
+        let attempts = if retry.max_retries == 0 {
+            "unlimited".to_string()
+        } else {
+            (retry.max_retries + 1).to_string()
+        };
+
+        loop {
+            log::info!(
+                "Attempting to connect to Redis (attempt {}/{})",
+                retry_count + 1,
+                attempts
+            );
+
+            let error = match OtpStore::ping(self).await {
+                Ok(()) => {
+                    log::info!("Successfully connected to Redis");
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            log::warn!("{}", error);
+
+            if retry.max_retries != 0 && retry_count >= retry.max_retries {
+                return Err(format!(
+                    "Giving up on Redis after {} attempts: {}",
+                    retry_count + 1,
+                    error
+                ));
+            }
+
+            // Increment retry count
+            retry_count += 1;
+
+            // Sleep with exponential backoff
+            log::info!("Waiting {}ms before retrying...", backoff.as_millis());
+            time::sleep(backoff).await;
+
+            // Double the backoff time for next retry (exponential backoff)
+            backoff = std::cmp::min(backoff * 2, retry.max_backoff);
+        }
+    }
+
+    /// Get a multiplexed connection for a single operation
+    async fn connection(&self) -> Result<MultiplexedConnection, String> {
+        self.client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))
+    }
+
+    /// Key prefix shared by everything stored for a tenant
+    fn tenant_prefix(&self, tenant: &str) -> String {
+        format!("{}:{}:", self.key_prefix, tenant)
+    }
+
+    /// Key for a counter or flag
+    fn data_key(&self, tenant: &str, key: &str) -> String {
+        format!("{}{}", self.tenant_prefix(tenant), key)
+    }
+
+    fn used_key(&self, tenant: &str, hashed: &str) -> String {
+        format!("{}used:{}", self.tenant_prefix(tenant), hashed)
+    }
+}
+
+/// Record a used OTP unless it is recorded under any of its keys.
+///
+/// KEYS: current key, then keys under previous peppers. ARGV: expiry in seconds.
+const MARK_USED_ONCE_SCRIPT: &str = r"
+for i = 2, #KEYS do
+    if redis.call('EXISTS', KEYS[i]) == 1 then
+        return 0
+    end
+end
+if redis.call('SET', KEYS[1], '1', 'EX', ARGV[1], 'NX') then
+    return 1
+end
+return 0
+";
+
+/// Claim due items of a sorted-set queue by pushing their score past the lease.
+///
+/// KEYS: queue. ARGV: now, lease expiry, limit.
+const CLAIM_DUE_SCRIPT: &str = r"
+local items = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[3])
+for _, item in ipairs(items) do
+    redis.call('ZADD', KEYS[1], ARGV[2], item)
+end
+return items
+";
+
+/// Escape glob metacharacters so a literal prefix can be used in a SCAN MATCH pattern
+fn escape_glob(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[async_trait::async_trait]
+impl OtpStore for RedisStore {
+    async fn mark_used(&self, tenant: &str, otp: &str, expiry_seconds: u64) -> Result<(), String> {
+        let mut conn = self.connection().await?;
+
+        // Set key with expiration using raw command
+        let _: () = redis::cmd("SETEX")
+            .arg(self.used_key(tenant, &self.replay_keys.derive(tenant, otp)))
+            .arg(expiry_seconds.to_string())
+            .arg("1")
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Failed to set OTP in Redis: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn is_used(&self, tenant: &str, otp: &str) -> Result<bool, String> {
+        let mut conn = self.connection().await?;
+
+        // Check keys derived from both the current and any previous pepper
+        let keys: Vec<String> = self
+            .replay_keys
+            .candidates(tenant, otp)
+            .iter()
+            .map(|hashed| self.used_key(tenant, hashed))
+            .collect();
+        let existing: u64 = conn
+            .exists(keys)
+            .await
+            .map_err(|e| format!("Failed to check OTP in Redis: {}", e))?;
+
+        Ok(existing > 0)
+    }
+
+    async fn mark_used_once(
+        &self,
+        tenant: &str,
+        otp: &str,
+        expiry_seconds: u64,
+    ) -> Result<bool, String> {
+        let mut conn = self.connection().await?;
+
+        let script = redis::Script::new(MARK_USED_ONCE_SCRIPT);
+        let mut script = script.prepare_invoke();
+        for hashed in self.replay_keys.candidates(tenant, otp) {
+            script.key(self.used_key(tenant, &hashed));
+        }
+        let marked: i64 = script
+            .arg(expiry_seconds.max(1))
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| format!("Failed to set OTP in Redis: {}", e))?;
+
+        Ok(marked == 1)
+    }
+
+    async fn increment(&self, tenant: &str, key: &str, window_seconds: u64) -> Result<u64, String> {
+        let mut conn = self.connection().await?;
+        let key = self.data_key(tenant, key);
+
+        // Create the counter with its expiry only if missing, then increment, atomically
+        let (value,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("EX")
+            .arg(window_seconds.max(1))
+            .arg("NX")
+            .ignore()
+            .cmd("INCR")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Failed to increment counter in Redis: {}", e))?;
+
+        Ok(value)
+    }
+
+    async fn counter(&self, tenant: &str, key: &str) -> Result<u64, String> {
+        let mut conn = self.connection().await?;
+
+        let value: Option<u64> = conn
+            .get(self.data_key(tenant, key))
+            .await
+            .map_err(|e| format!("Failed to read counter from Redis: {}", e))?;
+
+        Ok(value.unwrap_or(0))
+    }
+
+    async fn set_flag(&self, tenant: &str, key: &str, seconds: u64) -> Result<(), String> {
+        let mut conn = self.connection().await?;
+
+        let _: () = conn
+            .set_ex(self.data_key(tenant, key), 1, seconds.max(1))
+            .await
+            .map_err(|e| format!("Failed to set flag in Redis: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn set_value(&self, tenant: &str, key: &str, value: &str) -> Result<(), String> {
+        let mut conn = self.connection().await?;
+
+        let _: () = conn
+            .set(self.data_key(tenant, key), value)
+            .await
+            .map_err(|e| format!("Failed to store value in Redis: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn get_value(&self, tenant: &str, key: &str) -> Result<Option<String>, String> {
+        let mut conn = self.connection().await?;
+
+        conn.get(self.data_key(tenant, key))
+            .await
+            .map_err(|e| format!("Failed to read value from Redis: {}", e))
+    }
+
+    async fn flag_ttl(&self, tenant: &str, key: &str) -> Result<Option<u64>, String> {
+        let mut conn = self.connection().await?;
+
+        // TTL is -2 for a missing key and -1 for a key without expiry
+        let ttl: i64 = conn
+            .ttl(self.data_key(tenant, key))
+            .await
+            .map_err(|e| format!("Failed to read flag from Redis: {}", e))?;
+
+        Ok((ttl > 0).then_some(ttl as u64))
+    }
+
+    async fn remove(&self, tenant: &str, keys: &[String]) -> Result<(), String> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection().await?;
+
+        let keys: Vec<String> = keys.iter().map(|k| self.data_key(tenant, k)).collect();
+        let _: () = conn
+            .unlink(keys)
+            .await
+            .map_err(|e| format!("Failed to delete keys in Redis: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn schedule(
+        &self,
+        tenant: &str,
+        queue: &str,
+        item: &str,
+        due_at: u64,
+    ) -> Result<(), String> {
+        let mut conn = self.connection().await?;
+
+        let _: () = conn
+            .zadd(self.data_key(tenant, queue), item, due_at)
+            .await
+            .map_err(|e| format!("Failed to schedule item in Redis: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn claim_due(
+        &self,
+        tenant: &str,
+        queue: &str,
+        now: u64,
+        lease_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<String>, String> {
+        let mut conn = self.connection().await?;
+
+        // Select and lease in one script so two replicas never claim the same item
+        redis::Script::new(CLAIM_DUE_SCRIPT)
+            .key(self.data_key(tenant, queue))
+            .arg(now)
+            .arg(now + lease_seconds)
+            .arg(limit)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| format!("Failed to claim queued items in Redis: {}", e))
+    }
+
+    async fn unschedule(&self, tenant: &str, queue: &str, item: &str) -> Result<(), String> {
+        let mut conn = self.connection().await?;
+
+        let _: () = conn
+            .zrem(self.data_key(tenant, queue), item)
+            .await
+            .map_err(|e| format!("Failed to unschedule item in Redis: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn purge_tenant(&self, tenant: &str) -> Result<u64, String> {
+        let mut conn = self.connection().await?;
+        let pattern = format!("{}*", escape_glob(&self.tenant_prefix(tenant)));
+
+        // SCAN in batches rather than KEYS so a large tenant doesn't block Redis
+        let mut cursor: u64 = 0;
+        let mut deleted: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(500)
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| format!("Failed to scan tenant keys in Redis: {}", e))?;
+
+            if !keys.is_empty() {
+                let removed: u64 = redis::cmd("UNLINK")
+                    .arg(&keys)
+                    .query_async(&mut conn)
+                    .await
+                    .map_err(|e| format!("Failed to delete tenant keys in Redis: {}", e))?;
+                deleted += removed;
+            }
+
+            if next == 0 {
+                return Ok(deleted);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        let mut conn = self.connection().await?;
+
+        // Explicitly type the result variable
+        let ping_result: Result<(), redis::RedisError> =
+            redis::cmd("PING").query_async(&mut conn).await;
+        ping_result.map_err(|e| format!("Failed to ping Redis: {}", e))
+    }
+
+    async fn server_time(&self) -> Result<u64, String> {
+        let mut conn = self.connection().await?;
+
+        // TIME replies with [unix seconds, microseconds]
+        let (seconds, _micros): (u64, u64) = redis::cmd("TIME")
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Failed to read Redis time: {}", e))?;
+
+        Ok(seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_namespaced_by_prefix_and_tenant() {
+        let hasher = ReplayKeyHasher::new(b"pepper", None);
+        let store = RedisStore::open("redis://127.0.0.1:6379", "staging", hasher).unwrap();
+        assert_eq!(store.used_key("acme", "abc123"), "staging:acme:used:abc123");
+        assert_eq!(store.tenant_prefix("acme"), "staging:acme:");
+    }
+
+    #[test]
+    fn test_escape_glob() {
+        assert_eq!(escape_glob("otp:acme:"), "otp:acme:");
+        assert_eq!(escape_glob("a*b?[c]"), "a\\*b\\?\\[c\\]");
+    }
+}
@@ -0,0 +1,60 @@
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+/// Connection state of the storage backend as seen by readiness checks
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageState {
+    /// Initial connection is still being attempted
+    Connecting,
+    /// Storage answered the last ping
+    Ready,
+    /// The initial connection gave up after the configured retries, or a ping failed.
+    /// Pinging continues, so storage becomes ready again once it answers.
+    Failed,
+}
+
+#[derive(Debug)]
+struct StatusInner {
+    state: StorageState,
+    last_error: Option<String>,
+}
+
+/// Shared, cloneable handle to the storage connection state
+#[derive(Debug, Clone)]
+pub struct StorageStatus {
+    inner: Arc<RwLock<StatusInner>>,
+}
+
+impl Default for StorageStatus {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(StatusInner {
+                state: StorageState::Connecting,
+                last_error: None,
+            })),
+        }
+    }
+}
+
+impl StorageStatus {
+    pub fn state(&self) -> StorageState {
+        self.inner.read().unwrap().state
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.inner.read().unwrap().last_error.clone()
+    }
+
+    pub fn set_ready(&self) {
+        let mut inner = self.inner.write().unwrap();
+        inner.state = StorageState::Ready;
+        inner.last_error = None;
+    }
+
+    pub fn set_failed(&self, error: String) {
+        let mut inner = self.inner.write().unwrap();
+        inner.state = StorageState::Failed;
+        inner.last_error = Some(error);
+    }
+}
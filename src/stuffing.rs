@@ -0,0 +1,318 @@
+use crate::config::Config;
+use crate::storage::{OtpStore, SYSTEM_TENANT};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::net::IpAddr;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Log target of alerts meant for the security operations centre
+pub const ALERT_TARGET: &str = "security_alert";
+
+/// Where verification attempts come from, tracked across every tenant and credential
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A single client address
+    Ip(IpAddr),
+    /// The network around a client address, catching sprays spread over neighbouring hosts
+    Network(IpNet),
+}
+
+impl Source {
+    fn name(&self) -> String {
+        match self {
+            Source::Ip(ip) => format!("ip:{}", ip),
+            Source::Network(net) => format!("net:{}", net),
+        }
+    }
+
+    fn attempts_key(&self) -> String {
+        format!("stuffing:attempts:{}", self.name())
+    }
+
+    fn failures_key(&self) -> String {
+        format!("stuffing:failures:{}", self.name())
+    }
+
+    fn blocked_key(&self) -> String {
+        format!("stuffing:blocked:{}", self.name())
+    }
+
+    fn flagged_key(&self) -> String {
+        format!("stuffing:flagged:{}", self.name())
+    }
+}
+
+/// Sources an attempt from `ip` counts against: the address and its enclosing network
+pub fn sources(config: &Config, ip: IpAddr) -> Vec<Source> {
+    let prefix = match ip {
+        IpAddr::V4(_) => config.stuffing_ipv4_prefix,
+        IpAddr::V6(_) => config.stuffing_ipv6_prefix,
+    };
+
+    let mut sources = vec![Source::Ip(ip)];
+    // A full-length prefix is the address itself
+    if let Ok(net) = IpNet::new(ip, prefix) {
+        if net.prefix_len() < net.max_prefix_len() {
+            sources.push(Source::Network(net.trunc()));
+        }
+    }
+    sources
+}
+
+/// What the detector did about a source
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertAction {
+    /// Verification from the source is refused until the block expires
+    Blocked,
+    /// The source was reported but is still allowed
+    Flagged,
+}
+
+/// Structured credential-stuffing alert, logged as a single JSON line
+#[derive(Debug, Clone, Serialize)]
+pub struct StuffingAlert {
+    pub event: &'static str,
+    pub timestamp: String,
+    pub source: String,
+    /// Tenant of the attempt that crossed the threshold; the source may span tenants
+    pub tenant: String,
+    pub attempts: u64,
+    pub failures: u64,
+    pub failure_ratio: f64,
+    pub window_seconds: u64,
+    pub action: AlertAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_seconds: Option<u64>,
+}
+
+impl StuffingAlert {
+    /// Write the alert to the SOC log target
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => log::warn!(target: ALERT_TARGET, "{}", json),
+            Err(e) => log::error!("Failed to serialize security alert: {}", e),
+        }
+    }
+}
+
+/// Detects sources whose verification attempts mostly fail.
+///
+/// Per-credential lockout can't see one source spraying a single guess at each of many
+/// users, so attempts and failures are also counted per source across all tenants and
+/// credentials. Once a source has made at least `min_attempts` in the window and the
+/// share of failures reaches `failure_ratio`, it is blocked for `block_seconds`
+/// (or only flagged when that is 0) and an alert is raised.
+#[derive(Debug, Clone)]
+pub struct StuffingDetector {
+    /// Attempts before the failure ratio is judged; 0 disables detection
+    pub min_attempts: u64,
+    pub failure_ratio: f64,
+    pub window_seconds: u64,
+    pub block_seconds: u64,
+}
+
+impl StuffingDetector {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            min_attempts: config.stuffing_min_attempts,
+            failure_ratio: config.stuffing_failure_ratio,
+            window_seconds: config.stuffing_window_seconds,
+            block_seconds: config.stuffing_block_seconds,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.min_attempts > 0
+    }
+
+    /// Longest remaining block across the sources, `None` if none is blocked
+    pub async fn blocked_for(
+        &self,
+        store: &dyn OtpStore,
+        sources: &[Source],
+    ) -> Result<Option<u64>, String> {
+        let mut longest = None;
+        for source in sources {
+            if let Some(ttl) = store.flag_ttl(SYSTEM_TENANT, &source.blocked_key()).await? {
+                longest = longest.max(Some(ttl));
+            }
+        }
+        Ok(longest)
+    }
+
+    /// Count an attempt and return an alert for every source that crossed the threshold
+    pub async fn record(
+        &self,
+        store: &dyn OtpStore,
+        tenant: &str,
+        sources: &[Source],
+        failed: bool,
+    ) -> Result<Vec<StuffingAlert>, String> {
+        let mut alerts = Vec::new();
+        for source in sources {
+            let attempts = store
+                .increment(SYSTEM_TENANT, &source.attempts_key(), self.window_seconds)
+                .await?;
+            let failures = if failed {
+                store
+                    .increment(SYSTEM_TENANT, &source.failures_key(), self.window_seconds)
+                    .await?
+            } else {
+                store.counter(SYSTEM_TENANT, &source.failures_key()).await?
+            };
+
+            let ratio = failures as f64 / attempts as f64;
+            if attempts < self.min_attempts || ratio < self.failure_ratio {
+                continue;
+            }
+
+            let (action, blocked_seconds) = if self.block_seconds > 0 {
+                store
+                    .set_flag(SYSTEM_TENANT, &source.blocked_key(), self.block_seconds)
+                    .await?;
+                // Judge the source afresh once the block ends
+                store
+                    .remove(
+                        SYSTEM_TENANT,
+                        &[source.attempts_key(), source.failures_key()],
+                    )
+                    .await?;
+                (AlertAction::Blocked, Some(self.block_seconds))
+            } else {
+                // Report a source once per window rather than on every attempt
+                if store
+                    .flag_ttl(SYSTEM_TENANT, &source.flagged_key())
+                    .await?
+                    .is_some()
+                {
+                    continue;
+                }
+                store
+                    .set_flag(SYSTEM_TENANT, &source.flagged_key(), self.window_seconds)
+                    .await?;
+                (AlertAction::Flagged, None)
+            };
+
+            alerts.push(StuffingAlert {
+                event: "credential_stuffing",
+                timestamp: OffsetDateTime::now_utc()
+                    .format(&Rfc3339)
+                    .unwrap_or_default(),
+                source: source.name(),
+                tenant: tenant.to_string(),
+                attempts,
+                failures,
+                failure_ratio: ratio,
+                window_seconds: self.window_seconds,
+                action,
+                blocked_seconds,
+            });
+        }
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+
+    fn detector(block_seconds: u64) -> StuffingDetector {
+        StuffingDetector {
+            min_attempts: 4,
+            failure_ratio: 0.75,
+            window_seconds: 60,
+            block_seconds,
+        }
+    }
+
+    #[test]
+    fn test_sources_include_network() {
+        let config = Config::default();
+        let sources = sources(&config, "198.51.100.9".parse().unwrap());
+        assert_eq!(
+            sources,
+            vec![
+                Source::Ip("198.51.100.9".parse().unwrap()),
+                Source::Network("198.51.100.0/24".parse().unwrap()),
+            ]
+        );
+
+        let config = Config {
+            stuffing_ipv6_prefix: 128,
+            ..Config::default()
+        };
+        assert_eq!(
+            super::sources(&config, "2001:db8::1".parse().unwrap()).len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocks_source_above_failure_ratio() {
+        let store = MemoryStore::new();
+        let detector = detector(300);
+        let sources = vec![Source::Ip("198.51.100.9".parse().unwrap())];
+
+        // One success and two failures: below the minimum number of attempts
+        for failed in [false, true, true] {
+            let alerts = detector
+                .record(&store, "t", &sources, failed)
+                .await
+                .unwrap();
+            assert!(alerts.is_empty());
+        }
+        assert_eq!(detector.blocked_for(&store, &sources).await.unwrap(), None);
+
+        // Fourth attempt fails: 3 of 4 reaches the ratio
+        let alerts = detector.record(&store, "t", &sources, true).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].action, AlertAction::Blocked);
+        assert_eq!(alerts[0].source, "ip:198.51.100.9");
+        assert_eq!(alerts[0].attempts, 4);
+        assert_eq!(alerts[0].failures, 3);
+        assert!(detector
+            .blocked_for(&store, &sources)
+            .await
+            .unwrap()
+            .is_some());
+
+        let json: serde_json::Value = serde_json::to_value(&alerts[0]).unwrap();
+        assert_eq!(json["event"], "credential_stuffing");
+        assert_eq!(json["action"], "blocked");
+        assert_eq!(json["blocked_seconds"], 300);
+    }
+
+    #[tokio::test]
+    async fn test_mostly_successful_source_is_not_blocked() {
+        let store = MemoryStore::new();
+        let detector = detector(300);
+        let sources = vec![Source::Ip("198.51.100.9".parse().unwrap())];
+
+        for failed in [true, false, true, false, true, false] {
+            let alerts = detector
+                .record(&store, "t", &sources, failed)
+                .await
+                .unwrap();
+            assert!(alerts.is_empty());
+        }
+        assert_eq!(detector.blocked_for(&store, &sources).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_alert_only_mode_flags_once_per_window() {
+        let store = MemoryStore::new();
+        let detector = detector(0);
+        let sources = vec![Source::Ip("198.51.100.9".parse().unwrap())];
+
+        let mut alerts = Vec::new();
+        for _ in 0..8 {
+            alerts.extend(detector.record(&store, "t", &sources, true).await.unwrap());
+        }
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].action, AlertAction::Flagged);
+        assert_eq!(detector.blocked_for(&store, &sources).await.unwrap(), None);
+    }
+}
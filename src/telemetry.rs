@@ -0,0 +1,310 @@
+//! Request IDs and OpenTelemetry tracing.
+//!
+//! Every request gets an `X-Request-Id`, taken from the caller when it sends a usable one,
+//! and a server span that continues the caller's W3C `traceparent`. Spans are exported
+//! over OTLP/HTTP when an endpoint is configured and dropped otherwise.
+
+use crate::config::Config;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::HttpMessage;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::borrow::Cow;
+use std::future::Future;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Name spans are recorded under
+const TRACER: &str = "otp-server";
+
+/// ID of the request being handled, available to handlers through `web::ReqData`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The caller's ID if it is safe to log and echo back, otherwise a new random one
+    fn from_header(value: Option<&HeaderValue>) -> Self {
+        let usable = |id: &&str| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:/+=".contains(&b))
+        };
+        match value.and_then(|v| v.to_str().ok()).filter(usable) {
+            Some(id) => Self(id.to_string()),
+            None => Self(hex::encode(rand::random::<[u8; 16]>())),
+        }
+    }
+}
+
+/// Install the OTLP exporter as the global tracer provider, if an endpoint is configured.
+///
+/// The returned provider must be shut down on exit to flush spans still in the batch.
+pub fn init(config: &Config) -> Result<Option<SdkTracerProvider>, String> {
+    let endpoint = match &config.otel_exporter_otlp_endpoint {
+        Some(endpoint) => format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+        None => return Ok(None),
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint.clone())
+        .build()
+        .map_err(|e| format!("Failed to create OTLP exporter for {}: {}", endpoint, e))?;
+
+    // Follow the caller's sampling decision; sample root spans by ratio
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        config.otel_sample_ratio,
+    )));
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(sampler)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.otel_service_name.clone())
+                .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+                .build(),
+        )
+        .build();
+    global::set_tracer_provider(provider.clone());
+    log::info!("Exporting traces to {}", endpoint);
+    Ok(Some(provider))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Run a future in a new span, a child of the span it is started from
+pub async fn in_span<T, F>(
+    name: impl Into<Cow<'static, str>>,
+    kind: SpanKind,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> T
+where
+    F: Future<Output = T>,
+{
+    let tracer = global::tracer(TRACER);
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .start(&tracer);
+    future.with_context(Context::current_with_span(span)).await
+}
+
+/// Request ID and trace ID of the request being handled, if any
+pub fn current_ids() -> (Option<String>, Option<String>) {
+    let cx = Context::current();
+    let request_id = cx.get::<RequestId>().map(|id| id.0.clone());
+    let span = cx.span();
+    let span_context = span.span_context();
+    let trace_id = span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string());
+    (request_id, trace_id)
+}
+
+/// Record an error on the current span
+pub fn record_error(message: &str) {
+    Context::current()
+        .span()
+        .set_status(Status::error(message.to_string()));
+}
+
+/// Add an attribute to the current span
+pub fn set_attribute(attribute: KeyValue) {
+    Context::current().span().set_attribute(attribute);
+}
+
+/// Assign the request ID and wrap the request in a server span continuing any `traceparent`
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = RequestId::from_header(req.headers().get(REQUEST_ID_HEADER));
+    req.extensions_mut().insert(request_id.clone());
+
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(req.headers()));
+    let method = req.method().to_string();
+    let tracer = global::tracer(TRACER);
+    let span = tracer
+        .span_builder(method.clone())
+        .with_kind(SpanKind::Server)
+        .with_attributes(vec![
+            KeyValue::new("http.request.method", method.clone()),
+            KeyValue::new("url.path", req.path().to_string()),
+            KeyValue::new("request_id", request_id.0.clone()),
+        ])
+        .start_with_context(&tracer, &parent);
+    // The request ID travels in the context too, so log lines can carry it
+    let cx = parent.with_span(span).with_value(request_id.clone());
+
+    let result = next.call(req).with_context(cx.clone()).await;
+
+    let span = cx.span();
+    match &result {
+        Ok(res) => {
+            // Name spans after the route pattern, not the path, so IDs don't multiply names
+            if let Some(route) = res.request().match_pattern() {
+                span.update_name(format!("{} {}", method, route));
+                span.set_attribute(KeyValue::new("http.route", route));
+            }
+            let status = res.status();
+            span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                status.as_u16() as i64,
+            ));
+            if status.is_server_error() {
+                span.set_status(Status::error(status.to_string()));
+            }
+        }
+        Err(e) => span.set_status(Status::error(e.to_string())),
+    }
+    span.end();
+
+    result.map(|mut res| {
+        if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+            res.headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        res
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::routes::configure_routes;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::{InstrumentedStore, OtpStore};
+    use actix_web::{middleware, test, web, App};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Minimal OTLP/HTTP collector that keeps the bodies of the exports it receives
+    fn collector_stub() -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let exports = Arc::new(Mutex::new(Vec::new()));
+        let received = exports.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                // Serve requests on the connection until the client closes it
+                loop {
+                    let mut content_length = 0;
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                        break;
+                    }
+                    assert!(request_line.starts_with("POST /v1/traces "));
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    received.lock().unwrap().push(body);
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .unwrap();
+                }
+            }
+        });
+        (endpoint, exports)
+    }
+
+    #[actix_web::test]
+    async fn test_request_ids_and_trace_export() {
+        let (endpoint, exports) = collector_stub();
+        let config = Arc::new(Config {
+            otp_length: 6,
+            otp_expiry_seconds: 30,
+            otel_exporter_otlp_endpoint: Some(endpoint),
+            ..Config::default()
+        });
+        let provider = init(&config).unwrap().unwrap();
+
+        let storage: Arc<dyn OtpStore> =
+            Arc::new(InstrumentedStore::new(Arc::new(MemoryStore::new())));
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(trace_request))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(storage))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let req = test::TestRequest::post()
+            .uri("/api/otp/verify")
+            .insert_header((
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", trace_id),
+            ))
+            .insert_header((REQUEST_ID_HEADER, "gateway-42"))
+            .set_json(serde_json::json!({
+                "secret": "3132333435363738393031323334353637383930",
+                "otp": "000000",
+            }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "gateway-42");
+
+        // Unusable IDs are replaced with generated ones
+        let req = test::TestRequest::get()
+            .uri("/api/health")
+            .insert_header((REQUEST_ID_HEADER, "bad id\twith spaces"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let generated = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(generated.len(), 32);
+
+        provider.force_flush().unwrap();
+        let exported = exports.lock().unwrap().concat();
+        let contains = |needle: &[u8]| exported.windows(needle.len()).any(|w| w == needle);
+        // Spans are protobuf-encoded; names and the raw trace ID appear verbatim
+        assert!(contains(&hex::decode(trace_id).unwrap()));
+        assert!(contains(b"POST /api/otp/verify"));
+        assert!(contains(b"storage is_used"));
+        assert!(contains(b"otp.verify"));
+        assert!(contains(b"gateway-42"));
+
+        provider.shutdown().ok();
+    }
+}
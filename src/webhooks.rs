@@ -0,0 +1,579 @@
+//! Outbound webhooks for security events.
+//!
+//! Endpoints are configured per tenant in `WEBHOOKS_FILE`, each with the event types it
+//! wants. Events are queued in the store before anything is sent, so they survive
+//! restarts and are shared by replicas, and delivered at least once: a delivery stays
+//! queued until the endpoint answers 2xx, and is retried with exponential backoff. An
+//! event that can't be queued is handled like one the audit log can't record, following
+//! `AUDIT_FAILURE_POLICY`.
+
+use crate::audit::{Action, AuditEvent};
+use crate::config::{Config, FailurePolicy};
+use crate::error::{AppError, AppResult};
+use crate::server::tenant::validate_tenant_id;
+use crate::storage::{OtpStore, SYSTEM_TENANT};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying `t=<unix time>,v1=<hex HMAC-SHA256 of "<t>.<body>">`
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+pub const ID_HEADER: &str = "x-webhook-id";
+
+/// Queue pending deliveries are kept in
+const QUEUE: &str = "webhooks:deliveries";
+
+/// Deliveries claimed per poll
+const BATCH_SIZE: usize = 50;
+
+/// How often the queue is checked for retries that have come due
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Kinds of events endpoints can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    Lockout,
+    EnrollmentCreated,
+    EnrollmentDeleted,
+    /// A valid code was presented again
+    CodeReused,
+}
+
+impl EventType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::Lockout => "lockout",
+            EventType::EnrollmentCreated => "enrollment_created",
+            EventType::EnrollmentDeleted => "enrollment_deleted",
+            EventType::CodeReused => "code_reused",
+        }
+    }
+
+    /// The webhook event an audited event raises, if any
+    fn of(event: &AuditEvent) -> Option<Self> {
+        match event.action {
+            Action::Lockout => Some(EventType::Lockout),
+            Action::EnrollmentCreated => Some(EventType::EnrollmentCreated),
+            Action::EnrollmentDeleted => Some(EventType::EnrollmentDeleted),
+            Action::Verification if event.outcome.as_deref() == Some("reused") => {
+                Some(EventType::CodeReused)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Where a tenant's events are sent
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookEndpoint {
+    pub tenant: String,
+    pub url: String,
+    /// Key payloads are signed with
+    pub secret: String,
+    /// Event types to send; every type when empty
+    #[serde(default)]
+    pub events: Vec<EventType>,
+}
+
+impl WebhookEndpoint {
+    fn wants(&self, tenant: &str, event_type: EventType) -> bool {
+        self.tenant == tenant && (self.events.is_empty() || self.events.contains(&event_type))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookEndpoints {
+    endpoints: Vec<WebhookEndpoint>,
+}
+
+/// A payload on its way to one endpoint, as queued in the store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Delivery {
+    id: String,
+    tenant: String,
+    url: String,
+    event_type: EventType,
+    /// Failed attempts so far
+    attempts: u32,
+    body: String,
+}
+
+/// Body of a webhook request
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: &'a str,
+    #[serde(rename = "type")]
+    event_type: EventType,
+    tenant: &'a str,
+    time: String,
+    data: &'a AuditEvent,
+}
+
+/// Signature header value for a body sent at `timestamp`
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Queues events for the endpoints that want them and delivers them in the background
+pub struct Webhooks {
+    endpoints: Vec<WebhookEndpoint>,
+    storage: Arc<dyn OtpStore>,
+    client: reqwest::Client,
+    timeout: Duration,
+    max_attempts: u32,
+    backoff_seconds: u64,
+    max_backoff_seconds: u64,
+    /// What to do with the request when an event can't be queued
+    failure_policy: FailurePolicy,
+    /// Wakes the delivery loop when an event is queued
+    queued: Notify,
+}
+
+impl Webhooks {
+    /// Webhooks from `WEBHOOKS_FILE`, `None` when it isn't set
+    pub fn from_config(
+        config: &Config,
+        storage: Arc<dyn OtpStore>,
+    ) -> Result<Option<Self>, String> {
+        let path = match &config.webhooks_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let file: WebhookEndpoints = serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid webhook endpoints {}: {}", path, e))?;
+        for endpoint in &file.endpoints {
+            validate_tenant_id(&endpoint.tenant).map_err(|e| e.to_string())?;
+            if !endpoint.url.starts_with("https://") && !endpoint.url.starts_with("http://") {
+                return Err(format!("Webhook URL must be http(s): {}", endpoint.url));
+            }
+            if endpoint.secret.is_empty() {
+                return Err(format!("Webhook for {} has no secret", endpoint.url));
+            }
+        }
+        log::info!("Sending webhooks to {} endpoints", file.endpoints.len());
+
+        Self::new(file.endpoints, storage, config).map(Some)
+    }
+
+    fn new(
+        endpoints: Vec<WebhookEndpoint>,
+        storage: Arc<dyn OtpStore>,
+        config: &Config,
+    ) -> Result<Self, String> {
+        let timeout = Duration::from_secs(config.webhook_timeout_seconds);
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("Failed to create webhook HTTP client: {}", e))?;
+        Ok(Self {
+            endpoints,
+            storage,
+            client,
+            timeout,
+            max_attempts: config.webhook_max_attempts.max(1),
+            backoff_seconds: config.webhook_backoff_seconds,
+            max_backoff_seconds: config.webhook_backoff_max_seconds,
+            failure_policy: config.audit_failure_policy,
+            queued: Notify::new(),
+        })
+    }
+
+    /// Queue an event for every endpoint of its tenant that wants it, applying the failure
+    /// policy when a delivery can't be queued
+    pub async fn notify(&self, event: &AuditEvent) -> AppResult<()> {
+        let (Some(event_type), Some(tenant)) = (EventType::of(event), event.tenant.as_deref())
+        else {
+            return Ok(());
+        };
+        let id = hex::encode(rand::random::<[u8; 16]>());
+        let body = serde_json::to_string(&Payload {
+            id: &id,
+            event_type,
+            tenant,
+            time: time::OffsetDateTime::now_utc()
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+            data: event,
+        })
+        .expect("webhook payloads serialize");
+
+        let now = unix_now();
+        let mut failed = None;
+        for endpoint in self
+            .endpoints
+            .iter()
+            .filter(|e| e.wants(tenant, event_type))
+        {
+            let delivery = Delivery {
+                id: id.clone(),
+                tenant: tenant.to_string(),
+                url: endpoint.url.clone(),
+                event_type,
+                attempts: 0,
+                body: body.clone(),
+            };
+            // The other endpoints still get the event when one delivery can't be queued
+            if let Err(e) = self.enqueue(&delivery, now).await {
+                log::error!(
+                    tenant,
+                    event_type = event_type.as_str();
+                    "ALERT: failed to queue webhook for {}: {}",
+                    endpoint.url,
+                    e
+                );
+                failed = Some(e);
+            }
+        }
+        self.queued.notify_one();
+
+        match (failed, self.failure_policy) {
+            (Some(e), FailurePolicy::Closed) => Err(AppError::StorageUnavailable(format!(
+                "Webhook queue unavailable: {}",
+                e
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    async fn enqueue(&self, delivery: &Delivery, due_at: u64) -> Result<(), String> {
+        let item = serde_json::to_string(delivery)
+            .map_err(|e| format!("Failed to serialize webhook delivery: {}", e))?;
+        self.storage
+            .schedule(SYSTEM_TENANT, QUEUE, &item, due_at)
+            .await
+    }
+
+    /// Delay before the next attempt after `attempts` failures
+    fn backoff(&self, attempts: u32) -> u64 {
+        let exponent = attempts.saturating_sub(1).min(32);
+        self.backoff_seconds
+            .saturating_mul(2u64.saturating_pow(exponent))
+            .min(self.max_backoff_seconds)
+    }
+
+    /// Deliver queued events until the process exits
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.deliver_due(unix_now()).await {
+                log::warn!("Failed to read the webhook queue: {}", e);
+            }
+            tokio::select! {
+                _ = self.queued.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Attempt every delivery due by `now`, returning how many were attempted
+    pub async fn deliver_due(self: &Arc<Self>, now: u64) -> Result<usize, String> {
+        // Leased for longer than an attempt can take, so a crash mid-delivery means a retry
+        let lease = self.timeout.as_secs() + 30;
+        let items = self
+            .storage
+            .claim_due(SYSTEM_TENANT, QUEUE, now, lease, BATCH_SIZE)
+            .await?;
+
+        let attempted = items.len();
+        let mut deliveries = JoinSet::new();
+        for item in items {
+            let webhooks = self.clone();
+            deliveries.spawn(async move { webhooks.attempt(item, now).await });
+        }
+        while let Some(result) = deliveries.join_next().await {
+            if let Err(e) = result.map_err(|e| e.to_string()).and_then(|r| r) {
+                log::warn!("Failed to update the webhook queue: {}", e);
+            }
+        }
+        Ok(attempted)
+    }
+
+    /// Send one delivery, then remove it from the queue or schedule its retry
+    async fn attempt(&self, item: String, now: u64) -> Result<(), String> {
+        let delivery: Delivery = match serde_json::from_str(&item) {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                log::error!("Dropping unreadable webhook delivery: {}", e);
+                return self.storage.unschedule(SYSTEM_TENANT, QUEUE, &item).await;
+            }
+        };
+        // Endpoints removed from the configuration get no more deliveries
+        let Some(endpoint) = self
+            .endpoints
+            .iter()
+            .find(|e| e.url == delivery.url && e.tenant == delivery.tenant)
+        else {
+            log::warn!(tenant = delivery.tenant.as_str(); "Dropping webhook for unconfigured endpoint {}", delivery.url);
+            return self.storage.unschedule(SYSTEM_TENANT, QUEUE, &item).await;
+        };
+
+        let error = match self.send(endpoint, &delivery, now).await {
+            Ok(()) => {
+                log::debug!(tenant = delivery.tenant.as_str(), event_type = delivery.event_type.as_str(); "Delivered webhook to {}", delivery.url);
+                return self.storage.unschedule(SYSTEM_TENANT, QUEUE, &item).await;
+            }
+            Err(e) => e,
+        };
+
+        let retry = Delivery {
+            attempts: delivery.attempts + 1,
+            ..delivery
+        };
+        if retry.attempts >= self.max_attempts {
+            log::error!(
+                tenant = retry.tenant.as_str(),
+                event_type = retry.event_type.as_str();
+                "ALERT: giving up on webhook {} to {} after {} attempts: {}",
+                retry.id,
+                retry.url,
+                retry.attempts,
+                error
+            );
+        } else {
+            let delay = self.backoff(retry.attempts);
+            log::warn!(
+                tenant = retry.tenant.as_str(),
+                event_type = retry.event_type.as_str();
+                "Webhook to {} failed, retrying in {}s: {}",
+                retry.url,
+                delay,
+                error
+            );
+            // Queue the retry before dropping the attempt, so a crash in between
+            // can only deliver twice, never lose the event
+            self.enqueue(&retry, now + delay).await?;
+        }
+        self.storage.unschedule(SYSTEM_TENANT, QUEUE, &item).await
+    }
+
+    async fn send(
+        &self,
+        endpoint: &WebhookEndpoint,
+        delivery: &Delivery,
+        now: u64,
+    ) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&endpoint.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(ID_HEADER, &delivery.id)
+            .header(EVENT_HEADER, delivery.event_type.as_str())
+            .header(
+                SIGNATURE_HEADER,
+                sign(&endpoint.secret, now, &delivery.body),
+            )
+            .body(delivery.body.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("endpoint answered {}", response.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fault::FaultInjectingStore;
+    use crate::storage::memory::MemoryStore;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::sync::Mutex;
+
+    /// A request the receiver got: its signature, event type and body
+    type Received = (String, String, String);
+
+    /// Local receiver that fails the first `failures` requests, then accepts
+    fn receiver(failures: usize) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = HttpServer::new(move || {
+            let log = log.clone();
+            App::new().route(
+                "/hook",
+                web::post().to(move |req: HttpRequest, body: String| {
+                    let log = log.clone();
+                    async move {
+                        let header = |name| {
+                            req.headers()
+                                .get(name)
+                                .and_then(|v| v.to_str().ok())
+                                .unwrap_or_default()
+                                .to_string()
+                        };
+                        let mut log = log.lock().unwrap();
+                        log.push((header(SIGNATURE_HEADER), header(EVENT_HEADER), body));
+                        if log.len() <= failures {
+                            HttpResponse::ServiceUnavailable().finish()
+                        } else {
+                            HttpResponse::NoContent().finish()
+                        }
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+        (url, received)
+    }
+
+    fn webhooks(url: &str, events: Vec<EventType>) -> Arc<Webhooks> {
+        webhooks_in(
+            url,
+            events,
+            Arc::new(MemoryStore::new()),
+            FailurePolicy::Closed,
+        )
+    }
+
+    fn webhooks_in(
+        url: &str,
+        events: Vec<EventType>,
+        storage: Arc<dyn OtpStore>,
+        failure_policy: FailurePolicy,
+    ) -> Arc<Webhooks> {
+        let config = Config {
+            webhook_backoff_seconds: 10,
+            webhook_backoff_max_seconds: 15,
+            webhook_max_attempts: 5,
+            audit_failure_policy: failure_policy,
+            ..Config::default()
+        };
+        let endpoint = WebhookEndpoint {
+            tenant: "acme".to_string(),
+            url: url.to_string(),
+            secret: "whsec".to_string(),
+            events,
+        };
+        Arc::new(Webhooks::new(vec![endpoint], storage, &config).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_events_that_cant_be_queued_follow_the_failure_policy() {
+        let store = Arc::new(FaultInjectingStore::new(Arc::new(MemoryStore::new())));
+        store.set_failing(true);
+        let lockout = AuditEvent::new(Action::Lockout, Some("acme"));
+
+        let closed = webhooks_in(
+            "http://127.0.0.1:9/hook",
+            Vec::new(),
+            store.clone(),
+            FailurePolicy::Closed,
+        );
+        let error = closed.notify(&lockout).await.unwrap_err();
+        assert_eq!(error.code(), "storage_unavailable");
+        // Events no endpoint wants don't touch the queue
+        closed
+            .notify(&AuditEvent::new(Action::Lockout, Some("other")))
+            .await
+            .unwrap();
+
+        let open = webhooks_in(
+            "http://127.0.0.1:9/hook",
+            Vec::new(),
+            store.clone(),
+            FailurePolicy::Open,
+        );
+        open.notify(&lockout).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_signed_delivery_is_retried_with_backoff() {
+        let (url, received) = receiver(2);
+        let webhooks = webhooks(&url, vec![EventType::Lockout, EventType::CodeReused]);
+
+        webhooks
+            .notify(
+                &AuditEvent::new(Action::Lockout, Some("acme"))
+                    .credential_id(Some("token-1"))
+                    .detail("locked for 60s".to_string()),
+            )
+            .await
+            .unwrap();
+        // Filtered out: not subscribed, another tenant, not a webhook event
+        webhooks
+            .notify(&AuditEvent::new(Action::EnrollmentCreated, Some("acme")))
+            .await
+            .unwrap();
+        webhooks
+            .notify(&AuditEvent::new(Action::Lockout, Some("other")))
+            .await
+            .unwrap();
+        webhooks
+            .notify(&AuditEvent::new(Action::Verification, Some("acme")).outcome("invalid"))
+            .await
+            .unwrap();
+
+        let now = unix_now();
+        assert_eq!(webhooks.deliver_due(now).await.unwrap(), 1);
+        // Backing off: nothing is due until 10s later, then 15s (capped) after that
+        assert_eq!(webhooks.deliver_due(now + 9).await.unwrap(), 0);
+        assert_eq!(webhooks.deliver_due(now + 10).await.unwrap(), 1);
+        assert_eq!(webhooks.deliver_due(now + 24).await.unwrap(), 0);
+        assert_eq!(webhooks.deliver_due(now + 25).await.unwrap(), 1);
+        // Delivered, so it is gone from the queue
+        assert_eq!(webhooks.deliver_due(now + 10_000).await.unwrap(), 0);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        let (signature, event_type, body) = &received[2];
+        assert_eq!(event_type, "lockout");
+        assert_eq!(signature, &sign("whsec", now + 25, body));
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["type"], "lockout");
+        assert_eq!(payload["tenant"], "acme");
+        assert_eq!(payload["data"]["credential_id"], "token-1");
+        // Every attempt carries the same event
+        assert_eq!(received[0].2, *body);
+    }
+
+    #[actix_web::test]
+    async fn test_undeliverable_events_are_dropped_after_max_attempts() {
+        let (url, received) = receiver(usize::MAX);
+        let webhooks = webhooks(&url, Vec::new());
+        webhooks
+            .notify(&AuditEvent::new(Action::Verification, Some("acme")).outcome("reused"))
+            .await
+            .unwrap();
+
+        let mut now = unix_now();
+        for _ in 0..10 {
+            webhooks.deliver_due(now).await.unwrap();
+            now += 60;
+        }
+        assert_eq!(received.lock().unwrap().len(), 5);
+        assert_eq!(received.lock().unwrap()[0].1, "code_reused");
+    }
+}
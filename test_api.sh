@@ -2,7 +2,7 @@
 
 # Test script for OTP Server API
 
-BASE_URL="http://127.0.0.1:8080/api"
+BASE_URL="http://127.0.0.1:8080/api/v2"
 
 # Colors for output
 GREEN='\033[0;32m'